@@ -13,13 +13,20 @@ import com.julian_baumann.intershare_sdk.bluetoothLowEnergy.L2CAPClientManager
 import kotlinx.coroutines.CoroutineScope
 import kotlinx.coroutines.Dispatchers
 import kotlinx.coroutines.launch
+import java.io.File
 
 class NearbyServer(context: Context, myDevice: Device, delegate: NearbyConnectionDelegate) {
     private val bluetoothManager: BluetoothManager by lazy {
         context.getSystemService(Context.BLUETOOTH_SERVICE) as BluetoothManager
     }
 
-    private val internal: InternalNearbyServer = InternalNearbyServer(myDevice, Environment.getExternalStoragePublicDirectory(Environment.DIRECTORY_DOWNLOADS).absolutePath, delegate)
+    // The device id is derived from the identity key, which is kept in the app's private storage
+    private val internal: InternalNearbyServer = InternalNearbyServer(
+        myDevice,
+        Environment.getExternalStoragePublicDirectory(Environment.DIRECTORY_DOWNLOADS).absolutePath,
+        File(context.filesDir, "identity.key").absolutePath,
+        delegate
+    )
     private val internalBleImplementation = BLEPeripheralManager(context, internal, bluetoothManager)
     private val internalL2CapClient = L2CAPClientManager(internal)
     private var currentIPAddress: String? = null
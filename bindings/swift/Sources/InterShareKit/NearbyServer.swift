@@ -64,8 +64,9 @@ public class NearbyServer {
     private var lastKnownIp: String? = nil
     public var state: BluetoothState { get { bleServer.state } }
 
-    public init(myDevice: Device, storage: String, delegate: NearbyServerDelegate) {
-        internalHandler = InternalNearbyServer(myDevice: myDevice, fileStorage: storage, delegate: delegate)
+    /// The id of `myDevice` is derived from the identity key stored at `identityKeyPath`, which is created on first use.
+    public init(myDevice: Device, storage: String, identityKeyPath: String, delegate: NearbyServerDelegate) throws {
+        internalHandler = try InternalNearbyServer(myDevice: myDevice, fileStorage: storage, identityKeyPath: identityKeyPath, delegate: delegate)
         bleServer = BLEPeripheralManager(handler: internalHandler, delegate: delegate)
 
         internalHandler.addBleImplementation(bleImplementation: bleServer)
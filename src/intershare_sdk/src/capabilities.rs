@@ -0,0 +1,137 @@
+pub use protocol::communication::capabilities::{ArchiveFormat, Compression, Intent};
+use protocol::communication::message_header::MessageTypes;
+use protocol::communication::{Capabilities, MessageHeader};
+use protocol::prost::Message;
+
+use crate::encryption::AEAD_CHUNK_SIZE;
+use crate::parallel::MAX_PARALLEL_CONNECTIONS;
+
+/// Version of the wire protocol spoken by this build.
+pub const PROTOCOL_VERSION: i32 = 2;
+
+/// Oldest protocol version this build is still able to talk to.
+pub const MINIMUM_PROTOCOL_VERSION: i32 = 2;
+
+/// Feature set both peers agreed on during the handshake.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NegotiatedCapabilities {
+    pub protocol_version: i32,
+    pub intents: Vec<Intent>,
+    pub compression: Compression,
+    pub archive_format: ArchiveFormat,
+    pub max_chunk_size: u32,
+    pub max_parallel_connections: u32,
+}
+
+impl NegotiatedCapabilities {
+    pub fn supports_intent(&self, intent: Intent) -> bool {
+        return self.intents.contains(&intent);
+    }
+}
+
+pub fn local_header(message_type: MessageTypes) -> MessageHeader {
+    return MessageHeader {
+        protocol_version: PROTOCOL_VERSION,
+        r#type: message_type as i32,
+        minimum_protocol_version: MINIMUM_PROTOCOL_VERSION,
+    };
+}
+
+pub fn local_capabilities() -> Capabilities {
+    return Capabilities {
+        intents: vec![Intent::FileTransfer as i32, Intent::Clipboard as i32],
+        compression_methods: vec![
+            Compression::None as i32,
+            Compression::Deflate as i32,
+            Compression::Zstd as i32,
+        ],
+        archive_formats: vec![ArchiveFormat::Zip as i32, ArchiveFormat::Framed as i32],
+        max_chunk_size: AEAD_CHUNK_SIZE as u32,
+        max_parallel_connections: MAX_PARALLEL_CONNECTIONS,
+    };
+}
+
+/// Returns the protocol version both peers speak, or `None` if there is none.
+///
+/// The result is the same no matter which side evaluates it, so both peers always agree on
+/// whether the handshake continues.
+pub fn negotiate_version(local: &MessageHeader, remote: &MessageHeader) -> Option<i32> {
+    let version = std::cmp::min(local.protocol_version, remote.protocol_version);
+    let minimum_version = std::cmp::max(
+        local.minimum_protocol_version,
+        remote.minimum_protocol_version,
+    );
+
+    if version < minimum_version {
+        return None;
+    }
+
+    return Some(version);
+}
+
+/// Picks the highest common feature set. Enum values are ordered by preference, the
+/// zero-value of every enum is the fallback that every peer supports.
+pub fn negotiate_capabilities(
+    protocol_version: i32,
+    local: &Capabilities,
+    remote: &Capabilities,
+) -> NegotiatedCapabilities {
+    let intents = local
+        .intents()
+        .filter(|intent| remote.intents().any(|other| other == *intent))
+        .collect();
+
+    let compression = local
+        .compression_methods()
+        .filter(|method| remote.compression_methods().any(|other| other == *method))
+        .max()
+        .unwrap_or(Compression::None);
+
+    let archive_format = local
+        .archive_formats()
+        .filter(|format| remote.archive_formats().any(|other| other == *format))
+        .max()
+        .unwrap_or(ArchiveFormat::Zip);
+
+    let max_chunk_size = match remote.max_chunk_size {
+        0 => local.max_chunk_size,
+        remote_size => std::cmp::min(local.max_chunk_size, remote_size),
+    };
+
+    // Peers that don't know about parallel connections use a single one.
+    let max_parallel_connections = std::cmp::max(
+        std::cmp::min(
+            local.max_parallel_connections,
+            remote.max_parallel_connections,
+        ),
+        1,
+    );
+
+    return NegotiatedCapabilities {
+        protocol_version,
+        intents,
+        compression,
+        archive_format,
+        max_chunk_size,
+        max_parallel_connections,
+    };
+}
+
+/// Encoding of a header and capability set as it enters the handshake transcript.
+pub(crate) fn encode_hello(
+    header: &Option<MessageHeader>,
+    capabilities: &Option<Capabilities>,
+) -> Vec<u8> {
+    let mut encoded = header
+        .clone()
+        .unwrap_or_default()
+        .encode_length_delimited_to_vec();
+    encoded.extend(
+        capabilities
+            .clone()
+            .unwrap_or_default()
+            .encode_length_delimited_to_vec(),
+    );
+
+    return encoded;
+}
@@ -0,0 +1,530 @@
+use std::io;
+use std::io::{ErrorKind, Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use prost_stream::Stream;
+use protocol::communication::message_header::MessageTypes;
+use protocol::communication::{ErrorMessage, Frame, ProgressAck};
+use protocol::prost::Message;
+
+use crate::errors::ChannelError;
+
+/// Stream id of frames concerning the whole connection (transfer request, cancellation, ...).
+pub const CONTROL_STREAM_ID: u32 = 0;
+
+/// Stream id of the payload of a transfer.
+pub const TRANSFER_STREAM_ID: u32 = 1;
+
+/// Maximum payload of a single `DATA` frame.
+pub const DATA_FRAME_SIZE: usize = 32 * 1024;
+
+/// Amount of unacknowledged data a sender may have in flight. The receiver acknowledges every
+/// half window, so a stalled or paused receiver stops the sender after at most this many bytes.
+pub const FLOW_CONTROL_WINDOW: u64 = 1024 * 1024;
+
+const PAUSE_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Local cancellation and pause requests for a running transfer.
+///
+/// The flags are only evaluated by the thread driving the [`Channel`], which turns them into the
+/// matching control frames. That way other threads never need access to the stream.
+#[derive(Default)]
+pub struct TransferControl {
+    cancelled: AtomicBool,
+    paused: AtomicBool,
+}
+
+impl TransferControl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::Relaxed);
+    }
+
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+}
+
+/// Turns a [`ChannelError`] into an [`io::Error`], so it can travel through `Read` and `Write`.
+pub fn channel_error(error: ChannelError) -> io::Error {
+    let kind = match error {
+        ChannelError::Cancelled => ErrorKind::ConnectionAborted,
+        _ => ErrorKind::InvalidData,
+    };
+
+    return io::Error::new(kind, error);
+}
+
+/// Extracts the [`ChannelError`] wrapped by [`channel_error`], if there is one.
+pub fn as_channel_error(error: &io::Error) -> Option<&ChannelError> {
+    return error
+        .get_ref()
+        .and_then(|inner| inner.downcast_ref::<ChannelError>());
+}
+
+pub fn is_cancellation(error: &io::Error) -> bool {
+    return matches!(as_channel_error(error), Some(ChannelError::Cancelled));
+}
+
+/// Whether the connection dropped, as opposed to the transfer being ended by one of the peers.
+pub fn is_connection_loss(error: &io::Error) -> bool {
+    if as_channel_error(error).is_some() {
+        return false;
+    }
+
+    return matches!(
+        error.kind(),
+        ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::BrokenPipe
+            | ErrorKind::NotConnected
+            | ErrorKind::TimedOut
+            | ErrorKind::UnexpectedEof
+    );
+}
+
+pub fn decode_payload<M>(frame: &Frame) -> io::Result<M>
+where
+    M: Message + Default,
+{
+    return M::decode(frame.payload.as_slice())
+        .map_err(|error| io::Error::new(ErrorKind::InvalidData, error));
+}
+
+/// Remembers the kind of the last I/O error, which the frame codec only reports as text. A read
+/// hitting the end of the stream counts as [`ErrorKind::UnexpectedEof`], as a frame was expected.
+struct ObservedStream<'a, T> {
+    inner: &'a mut T,
+    error_kind: Option<ErrorKind>,
+}
+
+impl<'a, T> ObservedStream<'a, T> {
+    fn new(inner: &'a mut T) -> Self {
+        Self {
+            inner,
+            error_kind: None,
+        }
+    }
+
+    fn observe<R>(&mut self, result: io::Result<R>) -> io::Result<R> {
+        if let Err(error) = &result {
+            if error.kind() != ErrorKind::Interrupted {
+                self.error_kind = Some(error.kind());
+            }
+        }
+
+        return result;
+    }
+}
+
+impl<T: Read> Read for ObservedStream<'_, T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let result = self.inner.read(buf);
+
+        if matches!(result, Ok(0)) && !buf.is_empty() {
+            self.error_kind = Some(ErrorKind::UnexpectedEof);
+        }
+
+        return self.observe(result);
+    }
+}
+
+impl<T: Write> Write for ObservedStream<'_, T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let result = self.inner.write(buf);
+        return self.observe(result);
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let result = self.inner.flush();
+        return self.observe(result);
+    }
+}
+
+/// Typed, multiplexed frames on top of an encrypted stream.
+///
+/// Works on anything that is `Read + Write`, so TCP and L2CAP connections behave the same.
+pub struct Channel<T>
+where
+    T: Read + Write,
+{
+    stream: T,
+}
+
+impl<T> Channel<T>
+where
+    T: Read + Write,
+{
+    pub fn new(stream: T) -> Self {
+        Self { stream }
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        return &mut self.stream;
+    }
+
+    pub fn send_frame(
+        &mut self,
+        message_type: MessageTypes,
+        stream_id: u32,
+        payload: Vec<u8>,
+    ) -> io::Result<()> {
+        let frame = Frame {
+            r#type: message_type as i32,
+            stream_id,
+            payload,
+        };
+
+        let mut stream = ObservedStream::new(&mut self.stream);
+        let result = Stream::new(&mut stream).send(&frame);
+
+        return match result {
+            Ok(_) => Ok(()),
+            Err(error) => Err(io::Error::new(
+                stream.error_kind.unwrap_or(ErrorKind::Other),
+                error.to_string(),
+            )),
+        };
+    }
+
+    pub fn send_message<M>(
+        &mut self,
+        message_type: MessageTypes,
+        stream_id: u32,
+        message: &M,
+    ) -> io::Result<()>
+    where
+        M: Message,
+    {
+        return self.send_frame(message_type, stream_id, message.encode_to_vec());
+    }
+
+    pub fn send_error(&mut self, stream_id: u32, message: String) -> io::Result<()> {
+        return self.send_message(MessageTypes::Error, stream_id, &ErrorMessage { message });
+    }
+
+    pub fn receive_frame(&mut self) -> io::Result<Frame> {
+        let mut stream = ObservedStream::new(&mut self.stream);
+        let result = Stream::new(&mut stream).recv::<Frame>();
+
+        return match result {
+            Ok(frame) => Ok(frame),
+            Err(error) => Err(io::Error::new(
+                stream.error_kind.unwrap_or(ErrorKind::InvalidData),
+                error.to_string(),
+            )),
+        };
+    }
+
+    /// Receives the next frame and decodes its payload, which has to be of the given type.
+    pub fn receive_message<M>(&mut self, message_type: MessageTypes) -> io::Result<M>
+    where
+        M: Message + Default,
+    {
+        let frame = self.receive_frame()?;
+
+        if frame.r#type() != message_type {
+            return Err(Self::unexpected_frame(&frame));
+        }
+
+        return decode_payload(&frame);
+    }
+
+    /// Turns local cancel and pause requests into control frames. Blocks while paused.
+    ///
+    /// Data still in flight stays in the buffers of the connection until the transfer resumes.
+    fn apply_control(&mut self, control: &TransferControl) -> io::Result<()> {
+        if control.is_cancelled() {
+            let _ = self.send_frame(MessageTypes::Cancel, CONTROL_STREAM_ID, vec![]);
+
+            return Err(channel_error(ChannelError::Cancelled));
+        }
+
+        if !control.is_paused() {
+            return Ok(());
+        }
+
+        self.send_frame(MessageTypes::Pause, CONTROL_STREAM_ID, vec![])?;
+
+        while control.is_paused() && !control.is_cancelled() {
+            thread::sleep(PAUSE_POLL_INTERVAL);
+        }
+
+        if control.is_cancelled() {
+            return self.apply_control(control);
+        }
+
+        return self.send_frame(MessageTypes::Resume, CONTROL_STREAM_ID, vec![]);
+    }
+
+    /// Maps frames that end a transfer prematurely to their error.
+    fn unexpected_frame(frame: &Frame) -> io::Error {
+        return match frame.r#type() {
+            MessageTypes::Cancel => channel_error(ChannelError::Cancelled),
+            MessageTypes::Error => {
+                let message = decode_payload::<ErrorMessage>(frame)
+                    .map(|error| error.message)
+                    .unwrap_or_default();
+
+                channel_error(ChannelError::RemoteError { message })
+            }
+            _ => channel_error(ChannelError::UnexpectedFrame {
+                frame_type: frame.r#type,
+            }),
+        };
+    }
+}
+
+/// Writes the payload of a stream as `DATA` frames, honoring the flow control window and the
+/// control frames sent by the receiver.
+pub struct DataWriter<'a, T>
+where
+    T: Read + Write,
+{
+    channel: &'a mut Channel<T>,
+    stream_id: u32,
+    control: &'a TransferControl,
+    sent_bytes: u64,
+    acknowledged_bytes: u64,
+    remote_paused: bool,
+}
+
+impl<'a, T> DataWriter<'a, T>
+where
+    T: Read + Write,
+{
+    pub fn new(channel: &'a mut Channel<T>, stream_id: u32, control: &'a TransferControl) -> Self {
+        Self {
+            channel,
+            stream_id,
+            control,
+            sent_bytes: 0,
+            acknowledged_bytes: 0,
+            remote_paused: false,
+        }
+    }
+
+    /// Bytes the receiver confirmed so far.
+    pub fn acknowledged_bytes(&self) -> u64 {
+        return self.acknowledged_bytes;
+    }
+
+    /// Ends the stream and waits for the receiver to confirm it processed all data. Returns the
+    /// payload the receiver confirmed with.
+    pub fn finish(self) -> io::Result<Vec<u8>> {
+        return self.finish_with(vec![]);
+    }
+
+    /// Like [`Self::finish`], `trailer` is handed to the receiver along with the end of the
+    /// stream, see [`DataReader::trailer`].
+    pub fn finish_with(mut self, trailer: Vec<u8>) -> io::Result<Vec<u8>> {
+        self.channel
+            .send_frame(MessageTypes::EndOfStream, self.stream_id, trailer)?;
+
+        loop {
+            let frame = self.channel.receive_frame()?;
+
+            if frame.r#type() == MessageTypes::EndOfStream && frame.stream_id == self.stream_id {
+                return Ok(frame.payload);
+            }
+
+            self.handle_control_frame(frame)?;
+        }
+    }
+
+    fn handle_control_frame(&mut self, frame: Frame) -> io::Result<()> {
+        match frame.r#type() {
+            MessageTypes::ProgressAck => {
+                let ack = decode_payload::<ProgressAck>(&frame)?;
+
+                if ack.received_bytes > self.sent_bytes {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "Peer acknowledged more data than was sent",
+                    ));
+                }
+
+                self.acknowledged_bytes =
+                    std::cmp::max(self.acknowledged_bytes, ack.received_bytes);
+            }
+            MessageTypes::Pause => self.remote_paused = true,
+            MessageTypes::Resume => self.remote_paused = false,
+            _ => return Err(Channel::<T>::unexpected_frame(&frame)),
+        }
+
+        return Ok(());
+    }
+}
+
+impl<T> Write for DataWriter<'_, T>
+where
+    T: Read + Write,
+{
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        if buffer.is_empty() {
+            return Ok(0);
+        }
+
+        self.channel.apply_control(self.control)?;
+
+        while self.remote_paused || self.sent_bytes - self.acknowledged_bytes >= FLOW_CONTROL_WINDOW
+        {
+            let frame = self.channel.receive_frame()?;
+            self.handle_control_frame(frame)?;
+        }
+
+        let payload = &buffer[..std::cmp::min(buffer.len(), DATA_FRAME_SIZE)];
+        self.channel
+            .send_frame(MessageTypes::Data, self.stream_id, payload.to_vec())?;
+        self.sent_bytes += payload.len() as u64;
+
+        return Ok(payload.len());
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        return self.channel.get_mut().flush();
+    }
+}
+
+/// Reads the payload of a stream from its `DATA` frames and acknowledges it.
+///
+/// Returns `Ok(0)` once the sender ended the stream. The sender then waits for the
+/// `END_OF_STREAM` confirmation, see [`DataReader::confirm`].
+pub struct DataReader<'a, T>
+where
+    T: Read + Write,
+{
+    channel: &'a mut Channel<T>,
+    stream_id: u32,
+    control: &'a TransferControl,
+    buffer: Vec<u8>,
+    position: usize,
+    received_bytes: u64,
+    acknowledged_bytes: u64,
+    finished: bool,
+    trailer: Vec<u8>,
+}
+
+impl<'a, T> DataReader<'a, T>
+where
+    T: Read + Write,
+{
+    pub fn new(channel: &'a mut Channel<T>, stream_id: u32, control: &'a TransferControl) -> Self {
+        Self {
+            channel,
+            stream_id,
+            control,
+            buffer: Vec::new(),
+            position: 0,
+            received_bytes: 0,
+            acknowledged_bytes: 0,
+            finished: false,
+            trailer: vec![],
+        }
+    }
+
+    pub fn received_bytes(&self) -> u64 {
+        return self.received_bytes;
+    }
+
+    /// Payload the sender ended the stream with, empty until the end was read.
+    pub fn trailer(&self) -> &[u8] {
+        return &self.trailer;
+    }
+
+    /// Tells the sender that all data was received and processed.
+    pub fn confirm(self) -> io::Result<()> {
+        return self.confirm_with(vec![]);
+    }
+
+    /// Like [`Self::confirm`], `payload` is handed to the sender as the result of
+    /// [`DataWriter::finish`].
+    pub fn confirm_with(self, payload: Vec<u8>) -> io::Result<()> {
+        return self
+            .channel
+            .send_frame(MessageTypes::EndOfStream, self.stream_id, payload);
+    }
+
+    /// Tells the sender that processing the received data failed.
+    pub fn fail(self, message: String) -> io::Result<()> {
+        return self.channel.send_error(self.stream_id, message);
+    }
+
+    fn acknowledge(&mut self) -> io::Result<()> {
+        if self.received_bytes - self.acknowledged_bytes < FLOW_CONTROL_WINDOW / 2 {
+            return Ok(());
+        }
+
+        self.acknowledged_bytes = self.received_bytes;
+
+        return self.channel.send_message(
+            MessageTypes::ProgressAck,
+            self.stream_id,
+            &ProgressAck {
+                received_bytes: self.received_bytes,
+            },
+        );
+    }
+}
+
+impl<T> Read for DataReader<'_, T>
+where
+    T: Read + Write,
+{
+    fn read(&mut self, read_buffer: &mut [u8]) -> io::Result<usize> {
+        if read_buffer.is_empty() {
+            return Ok(0);
+        }
+
+        self.channel.apply_control(self.control)?;
+
+        while self.position >= self.buffer.len() {
+            if self.finished {
+                return Ok(0);
+            }
+
+            let frame = self.channel.receive_frame()?;
+
+            match frame.r#type() {
+                MessageTypes::Data if frame.stream_id == self.stream_id => {
+                    self.received_bytes += frame.payload.len() as u64;
+                    self.buffer = frame.payload;
+                    self.position = 0;
+
+                    self.acknowledge()?;
+                }
+                MessageTypes::EndOfStream if frame.stream_id == self.stream_id => {
+                    self.finished = true;
+                    self.trailer = frame.payload;
+                }
+                // The sender paused, the next frame arrives once it resumes.
+                MessageTypes::Pause | MessageTypes::Resume => {}
+                _ => return Err(Channel::<T>::unexpected_frame(&frame)),
+            }
+        }
+
+        let available = &self.buffer[self.position..];
+        let length = std::cmp::min(available.len(), read_buffer.len());
+        read_buffer[..length].copy_from_slice(&available[..length]);
+        self.position += length;
+
+        return Ok(length);
+    }
+}
@@ -0,0 +1,158 @@
+//! Receive checkpoints, so a transfer interrupted by a connection loss continues where it
+//! stopped once the sender reconnects.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use protocol::communication::FileManifestEntry;
+use protocol::storage::{FileCheckpoint, ReceiveCheckpoint};
+use uuid::Uuid;
+
+use crate::errors::StorageError;
+use crate::storage::{load_message, save_message};
+
+/// A checkpoint is persisted at the latest after this many received bytes.
+pub const CHECKPOINT_INTERVAL: u64 = 4 * 1024 * 1024;
+
+/// Kept next to the received files, as the checkpoints describe partial files in there.
+const CHECKPOINT_DIRECTORY: &str = ".intershare/checkpoints";
+
+/// How far a framed file transfer got, persisted inside the file storage.
+pub struct Checkpoint {
+    path: PathBuf,
+    contents: ReceiveCheckpoint,
+    unsaved_bytes: u64,
+}
+
+impl Checkpoint {
+    /// Returns `None` if the transfer id is not a valid UUID, as it becomes part of a path.
+    fn checkpoint_path(file_storage: &str, transfer_id: &str) -> Option<PathBuf> {
+        let transfer_id = Uuid::parse_str(transfer_id).ok()?;
+
+        return Some(
+            Path::new(file_storage)
+                .join(CHECKPOINT_DIRECTORY)
+                .join(transfer_id.hyphenated().to_string()),
+        );
+    }
+
+    /// Starts a checkpoint for a new transfer.
+    pub fn new(
+        file_storage: &str,
+        transfer_id: &str,
+        sender_identity_key: &[u8],
+        manifest: &[FileManifestEntry],
+    ) -> Option<Self> {
+        let path = Self::checkpoint_path(file_storage, transfer_id)?;
+
+        let files = manifest
+            .iter()
+            .map(|entry| FileCheckpoint {
+                path: entry.path.clone(),
+                size: entry.size,
+                received_bytes: 0,
+            })
+            .collect();
+
+        return Some(Self {
+            path,
+            contents: ReceiveCheckpoint {
+                transfer_id: transfer_id.to_string(),
+                sender_identity_key: sender_identity_key.to_vec(),
+                files,
+            },
+            unsaved_bytes: 0,
+        });
+    }
+
+    /// Loads the checkpoint of an interrupted transfer. It's only used if it was received from
+    /// the same sender and still describes the same files.
+    ///
+    /// Files that are shorter on disk than recorded, e.g. because the data never made it out of
+    /// the OS buffers, continue from their actual length.
+    pub fn resume(
+        file_storage: &str,
+        transfer_id: &str,
+        sender_identity_key: &[u8],
+        manifest: &[FileManifestEntry],
+    ) -> Option<Self> {
+        let path = Self::checkpoint_path(file_storage, transfer_id)?;
+
+        let mut contents = match load_message::<ReceiveCheckpoint>(&path) {
+            Ok(Some(contents)) => contents,
+            Ok(None) => return None,
+            Err(error) => {
+                println!("Failed to load checkpoint: {:?}", error);
+                return None;
+            }
+        };
+
+        let same_files = contents.files.len() == manifest.len()
+            && contents
+                .files
+                .iter()
+                .zip(manifest)
+                .all(|(file, entry)| file.path == entry.path && file.size == entry.size);
+
+        if contents.sender_identity_key != sender_identity_key || !same_files {
+            return None;
+        }
+
+        for file in &mut contents.files {
+            let length = fs::metadata(Path::new(file_storage).join(&file.path))
+                .map(|metadata| metadata.len())
+                .unwrap_or(0);
+
+            file.received_bytes = std::cmp::min(file.received_bytes, length);
+        }
+
+        return Some(Self {
+            path,
+            contents,
+            unsaved_bytes: 0,
+        });
+    }
+
+    /// Bytes already received of every file, in manifest order.
+    pub fn resume_offsets(&self) -> Vec<u64> {
+        return self
+            .contents
+            .files
+            .iter()
+            .map(|file| file.received_bytes)
+            .collect();
+    }
+
+    /// Records the progress of a file, persisting it once enough data arrived or the file is
+    /// complete.
+    pub fn update(&mut self, file_index: usize, received_bytes: u64) {
+        let Some(file) = self.contents.files.get_mut(file_index) else {
+            return;
+        };
+
+        self.unsaved_bytes += received_bytes.saturating_sub(file.received_bytes);
+        file.received_bytes = received_bytes;
+
+        if self.unsaved_bytes >= CHECKPOINT_INTERVAL || received_bytes == file.size {
+            if let Err(error) = self.save() {
+                println!("Failed to save checkpoint: {:?}", error);
+            }
+        }
+    }
+
+    pub fn save(&mut self) -> Result<(), StorageError> {
+        save_message(&self.path, &self.contents)?;
+        self.unsaved_bytes = 0;
+
+        return Ok(());
+    }
+
+    /// Deletes the checkpoint once the transfer finished or was cancelled for good.
+    pub fn remove(self) {
+        if let Err(error) = fs::remove_file(&self.path) {
+            if error.kind() != std::io::ErrorKind::NotFound {
+                println!("Failed to remove checkpoint: {:?}", error);
+            }
+        }
+    }
+}
@@ -0,0 +1,150 @@
+use std::io;
+use std::io::Read;
+
+use protocol::communication::{ClipboardEntry, ClipboardTransferIntent};
+
+use crate::errors::ConnectErrors;
+
+/// Representations up to this size are part of the transfer request itself.
+pub const MAX_INLINE_CLIPBOARD_SIZE: u64 = 64 * 1024;
+
+/// Upper limit for all representations of a clipboard combined.
+pub const MAX_CLIPBOARD_SIZE: u64 = 64 * 1024 * 1024;
+
+pub const PLAIN_TEXT_MIME_TYPE: &str = "text/plain";
+
+/// One representation of the clipboard content, e.g. `text/html` or `image/png`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClipboardRepresentation {
+    pub mime_type: String,
+    pub data: Vec<u8>,
+}
+
+impl ClipboardRepresentation {
+    pub fn plain_text(text: String) -> Self {
+        Self {
+            mime_type: PLAIN_TEXT_MIME_TYPE.to_string(),
+            data: text.into_bytes(),
+        }
+    }
+
+    pub fn is_plain_text(&self) -> bool {
+        return is_plain_text(&self.mime_type);
+    }
+}
+
+/// Matches `text/plain` including parameters like `text/plain;charset=utf-8`.
+fn is_plain_text(mime_type: &str) -> bool {
+    return mime_type
+        .split(';')
+        .next()
+        .is_some_and(|essence| essence.trim().eq_ignore_ascii_case(PLAIN_TEXT_MIME_TYPE));
+}
+
+/// Describes the representations in a [`ClipboardTransferIntent`].
+///
+/// Returns the data of the representations that are too large to be inlined. It has to be
+/// streamed, in this order, once the receiver accepted the request.
+pub fn create_clipboard_intent(
+    representations: Vec<ClipboardRepresentation>,
+) -> Result<(ClipboardTransferIntent, Vec<Vec<u8>>), ConnectErrors> {
+    let total_size: u64 = representations
+        .iter()
+        .map(|representation| representation.data.len() as u64)
+        .sum();
+
+    if total_size > MAX_CLIPBOARD_SIZE {
+        return Err(ConnectErrors::ClipboardTooLarge);
+    }
+
+    let mut intent = ClipboardTransferIntent::default();
+    let mut streamed_data = vec![];
+
+    for representation in representations {
+        let size = representation.data.len() as u64;
+        let streamed = size > MAX_INLINE_CLIPBOARD_SIZE;
+
+        if !streamed && representation.is_plain_text() && intent.clipboard_content.is_empty() {
+            intent.clipboard_content = String::from_utf8_lossy(&representation.data).to_string();
+        }
+
+        let mut entry = ClipboardEntry {
+            mime_type: representation.mime_type,
+            size,
+            inline_content: vec![],
+            streamed,
+        };
+
+        if streamed {
+            streamed_data.push(representation.data);
+        } else {
+            entry.inline_content = representation.data;
+        }
+
+        intent.representations.push(entry);
+    }
+
+    return Ok((intent, streamed_data));
+}
+
+/// Whether the intent stays within the size limits and is consistent.
+pub fn is_valid_clipboard_intent(intent: &ClipboardTransferIntent) -> bool {
+    let mut total_size: u64 = 0;
+
+    for entry in &intent.representations {
+        let consistent = if entry.streamed {
+            entry.inline_content.is_empty()
+        } else {
+            entry.size <= MAX_INLINE_CLIPBOARD_SIZE
+                && entry.inline_content.len() as u64 == entry.size
+        };
+
+        if !consistent {
+            return false;
+        }
+
+        total_size = total_size.saturating_add(entry.size);
+    }
+
+    return total_size <= MAX_CLIPBOARD_SIZE;
+}
+
+pub fn has_streamed_representations(intent: &ClipboardTransferIntent) -> bool {
+    return intent.representations.iter().any(|entry| entry.streamed);
+}
+
+/// Collects all representations, reading the streamed ones from `reader`.
+///
+/// Peers that only send `clipboard_content` get a single plain text representation.
+pub fn read_clipboard_representations<R>(
+    intent: &ClipboardTransferIntent,
+    reader: &mut R,
+) -> io::Result<Vec<ClipboardRepresentation>>
+where
+    R: Read,
+{
+    if intent.representations.is_empty() {
+        return Ok(vec![ClipboardRepresentation::plain_text(
+            intent.clipboard_content.clone(),
+        )]);
+    }
+
+    let mut representations = vec![];
+
+    for entry in &intent.representations {
+        let data = if entry.streamed {
+            let mut data = vec![0u8; entry.size as usize];
+            reader.read_exact(&mut data)?;
+            data
+        } else {
+            entry.inline_content.clone()
+        };
+
+        representations.push(ClipboardRepresentation {
+            mime_type: entry.mime_type.clone(),
+            data,
+        });
+    }
+
+    return Ok(representations);
+}
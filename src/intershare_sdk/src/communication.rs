@@ -1,22 +1,135 @@
+use crate::capabilities::{
+    encode_hello, local_capabilities, local_header, negotiate_capabilities, negotiate_version,
+    NegotiatedCapabilities, PROTOCOL_VERSION,
+};
 use crate::encryption::generate_iv;
-use crate::encryption::EncryptedStream;
+use crate::encryption::{AuthenticatedStream, HandshakeRole, SessionKeys};
+use crate::errors::{ConnectErrors, IncomingErrors};
+use crate::identity::{verify_signature, DeviceIdentity};
+use hkdf::Hkdf;
 use prost_stream::Stream;
-use protocol::communication::{EncryptionRequest, EncryptionResponse};
+use protocol::communication::message_header::MessageTypes;
+use protocol::communication::{EncryptionRequest, EncryptionResponse, IdentityProof};
 use rand_core::OsRng;
-use std::error::Error;
+use sha2::{Digest, Sha256};
 use std::io::{Read, Write};
 use x25519_dalek::{EphemeralSecret, PublicKey};
 
+const TRANSCRIPT_LABEL: &[u8] = b"InterShare handshake v2";
+const SENDER_SIGNATURE_LABEL: &[u8] = b"InterShare sender signature";
+const RECEIVER_SIGNATURE_LABEL: &[u8] = b"InterShare receiver signature";
+const VERIFICATION_CODE_INFO: &[u8] = b"InterShare verification code";
+
+/// Result of a successful handshake.
+pub struct Session<T>
+where
+    T: Read + Write,
+{
+    pub stream: AuthenticatedStream<T>,
+    /// Ed25519 identity key of the peer, proven by its signature over the transcript.
+    pub peer_identity_key: [u8; 32],
+    pub transcript_hash: [u8; 32],
+    pub capabilities: NegotiatedCapabilities,
+}
+
+impl<T> Session<T>
+where
+    T: Read + Write,
+{
+    pub fn verification_code(&self) -> String {
+        return derive_verification_code(&self.transcript_hash);
+    }
+}
+
+/// Derives the 6-digit short authentication string both users compare on first contact.
+///
+/// A man in the middle has to run two separate key exchanges, which results in two different
+/// transcripts and therefore (with a probability of 1 - 10^-6) in two different codes.
+pub fn derive_verification_code(transcript_hash: &[u8; 32]) -> String {
+    let hkdf = Hkdf::<Sha256>::new(None, transcript_hash);
+    let mut output = [0u8; 8];
+    hkdf.expand(VERIFICATION_CODE_INFO, &mut output)
+        .expect("Invalid HKDF output length");
+
+    return format!("{:06}", u64::from_be_bytes(output) % 1_000_000);
+}
+
+/// Hashes everything both peers exchanged in the clear during the key exchange.
+///
+/// Every element is length-prefixed, so no two different transcripts produce the same input.
+/// The hellos (version header and capabilities) are part of the transcript as well, so a man in
+/// the middle can't downgrade the negotiated feature set without breaking the signatures.
+pub fn transcript_hash(
+    sender_public_key: &[u8],
+    receiver_public_key: &[u8],
+    receiver_salt: &[u8],
+    sender_hello: &[u8],
+    receiver_hello: &[u8],
+) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(TRANSCRIPT_LABEL);
+
+    for element in [
+        sender_public_key,
+        receiver_public_key,
+        receiver_salt,
+        sender_hello,
+        receiver_hello,
+    ] {
+        hasher.update((element.len() as u32).to_be_bytes());
+        hasher.update(element);
+    }
+
+    return hasher.finalize().into();
+}
+
+/// Message an identity key signs to prove it took part in the handshake with the given transcript.
+fn signed_message(label: &[u8], transcript_hash: &[u8; 32], identity_public_key: &[u8]) -> Vec<u8> {
+    return [label, transcript_hash, identity_public_key].concat();
+}
+
+fn verify_identity(
+    label: &[u8],
+    transcript_hash: &[u8; 32],
+    identity_public_key: Vec<u8>,
+    signature: &[u8],
+) -> Result<[u8; 32], IncomingErrors> {
+    let message = signed_message(label, transcript_hash, &identity_public_key);
+
+    if !verify_signature(&identity_public_key, &message, signature) {
+        return Err(IncomingErrors::InvalidSignature);
+    }
+
+    return match identity_public_key.try_into() {
+        Ok(identity_public_key) => Ok(identity_public_key),
+        Err(_) => Err(IncomingErrors::InvalidSignature),
+    };
+}
+
+fn parse_public_key(public_key: Vec<u8>) -> Result<PublicKey, IncomingErrors> {
+    let public_key: [u8; 32] = match public_key.try_into() {
+        Ok(public_key) => public_key,
+        Err(_) => return Err(IncomingErrors::InvalidForeignPublicKey),
+    };
+
+    return Ok(PublicKey::from(public_key));
+}
+
 pub async fn initiate_sender_communication<T>(
     mut stream: T,
-) -> Result<EncryptedStream<T>, Box<dyn Error>>
+    identity: &DeviceIdentity,
+) -> Result<Session<T>, ConnectErrors>
 where
     T: Read + Write,
 {
     let secret = EphemeralSecret::random_from_rng(OsRng);
     let public_key = PublicKey::from(&secret);
+    let header = local_header(MessageTypes::KeyExchange);
+    let own_capabilities = local_capabilities();
     let encryption_request = EncryptionRequest {
         public_key: public_key.as_bytes().to_vec(),
+        header: Some(header.clone()),
+        capabilities: Some(own_capabilities.clone()),
     };
 
     let mut prost_stream = Stream::new(&mut stream);
@@ -24,59 +137,192 @@ where
 
     let encryption_response: EncryptionResponse = match prost_stream.recv::<EncryptionResponse>() {
         Ok(message) => message,
-        Err(error) => return Err(Box::new(error)),
+        Err(error) => {
+            return Err(ConnectErrors::FailedToEncryptStream {
+                error: error.to_string(),
+            })
+        }
     };
 
-    let public_key: [u8; 32] = encryption_response
-        .public_key
-        .try_into()
-        .expect("Vec length is not 32");
-    let foreign_public_key = PublicKey::from(public_key);
+    // Checked before anything else, an incompatible receiver only answers with its header.
+    let remote_header = encryption_response.header.clone().unwrap_or_default();
+    let Some(protocol_version) = negotiate_version(&header, &remote_header) else {
+        return Err(ConnectErrors::IncompatibleProtocolVersion {
+            local_version: PROTOCOL_VERSION,
+            remote_version: remote_header.protocol_version,
+        });
+    };
+
+    let capabilities = negotiate_capabilities(
+        protocol_version,
+        &own_capabilities,
+        &encryption_response.capabilities.clone().unwrap_or_default(),
+    );
+
+    let foreign_public_key = match parse_public_key(encryption_response.public_key) {
+        Ok(foreign_public_key) => foreign_public_key,
+        Err(error) => {
+            return Err(ConnectErrors::FailedToEncryptStream {
+                error: error.to_string(),
+            })
+        }
+    };
+
+    let transcript = transcript_hash(
+        public_key.as_bytes(),
+        foreign_public_key.as_bytes(),
+        &encryption_response.iv,
+        &encode_hello(&encryption_request.header, &encryption_request.capabilities),
+        &encode_hello(
+            &encryption_response.header,
+            &encryption_response.capabilities,
+        ),
+    );
+
+    let Ok(peer_identity_key) = verify_identity(
+        RECEIVER_SIGNATURE_LABEL,
+        &transcript,
+        encryption_response.identity_public_key,
+        &encryption_response.signature,
+    ) else {
+        return Err(ConnectErrors::FailedToVerifyPeerIdentity);
+    };
 
     let shared_secret = secret.diffie_hellman(&foreign_public_key);
+    let session_keys =
+        SessionKeys::derive(shared_secret.as_bytes(), &transcript, HandshakeRole::Sender);
 
-    let iv: [u8; 24] = encryption_response
-        .iv
-        .try_into()
-        .expect("Vec length is not 24");
+    let mut encrypted_stream = AuthenticatedStream::new(session_keys, stream);
+    encrypted_stream.set_chunk_size(capabilities.max_chunk_size as usize);
 
-    let encrypted_stream = EncryptedStream::new(shared_secret.to_bytes(), iv, stream);
+    // Our own identity is only revealed once the channel is encrypted.
+    let identity_public_key = identity.public_key();
+    let identity_proof = IdentityProof {
+        identity_public_key: identity_public_key.to_vec(),
+        signature: identity.sign(&signed_message(
+            SENDER_SIGNATURE_LABEL,
+            &transcript,
+            &identity_public_key,
+        )),
+    };
+
+    if let Err(error) = Stream::new(&mut encrypted_stream).send(&identity_proof) {
+        return Err(ConnectErrors::FailedToEncryptStream {
+            error: error.to_string(),
+        });
+    }
 
-    return Ok(encrypted_stream);
+    return Ok(Session {
+        stream: encrypted_stream,
+        peer_identity_key,
+        transcript_hash: transcript,
+        capabilities,
+    });
 }
 
 pub fn initiate_receiver_communication<T>(
     mut stream: T,
-) -> Result<EncryptedStream<T>, Box<dyn Error>>
+    identity: &DeviceIdentity,
+) -> Result<Session<T>, IncomingErrors>
 where
     T: Read + Write,
 {
     let secret = EphemeralSecret::random_from_rng(OsRng);
     let public_key = PublicKey::from(&secret);
 
-    let iv = generate_iv();
+    let salt = generate_iv();
 
     let mut prost_stream = Stream::new(&mut stream);
 
     let encryption_request = match prost_stream.recv::<EncryptionRequest>() {
         Ok(message) => message,
-        Err(error) => return Err(Box::new(error)),
+        Err(error) => return Err(IncomingErrors::HandshakeFailed(error.to_string())),
     };
 
-    let _ = prost_stream.send(&EncryptionResponse {
-        public_key: public_key.as_bytes().to_vec(),
-        iv: iv.to_vec(),
-    });
+    let Some(remote_header) = encryption_request.header.clone() else {
+        let _ = prost_stream.send(&EncryptionResponse {
+            header: Some(local_header(MessageTypes::KeyExchangeResponse)),
+            ..Default::default()
+        });
+
+        return Err(IncomingErrors::MissingProtocolVersion);
+    };
 
-    let public_key: [u8; 32] = encryption_request
-        .public_key
-        .try_into()
-        .expect("Vec length is not 32");
-    let foreign_public_key = PublicKey::from(public_key);
+    let header = local_header(MessageTypes::KeyExchangeResponse);
+
+    let Some(protocol_version) = negotiate_version(&header, &remote_header) else {
+        // Only answer with our header, so the sender is able to report the mismatch.
+        let _ = prost_stream.send(&EncryptionResponse {
+            header: Some(header),
+            ..Default::default()
+        });
+
+        return Err(IncomingErrors::InvalidVersion);
+    };
+
+    let own_capabilities = local_capabilities();
+    let capabilities = negotiate_capabilities(
+        protocol_version,
+        &own_capabilities,
+        &encryption_request.capabilities.clone().unwrap_or_default(),
+    );
+
+    let foreign_public_key = parse_public_key(encryption_request.public_key)?;
+
+    let header = Some(header);
+    let own_capabilities = Some(own_capabilities);
+    let transcript = transcript_hash(
+        foreign_public_key.as_bytes(),
+        public_key.as_bytes(),
+        &salt,
+        &encode_hello(&encryption_request.header, &encryption_request.capabilities),
+        &encode_hello(&header, &own_capabilities),
+    );
+
+    let identity_public_key = identity.public_key();
+    let signature = identity.sign(&signed_message(
+        RECEIVER_SIGNATURE_LABEL,
+        &transcript,
+        &identity_public_key,
+    ));
+
+    if let Err(error) = prost_stream.send(&EncryptionResponse {
+        public_key: public_key.as_bytes().to_vec(),
+        iv: salt.to_vec(),
+        identity_public_key: identity_public_key.to_vec(),
+        signature,
+        header,
+        capabilities: own_capabilities,
+    }) {
+        return Err(IncomingErrors::HandshakeFailed(error.to_string()));
+    }
 
     let shared_secret = secret.diffie_hellman(&foreign_public_key);
+    let session_keys = SessionKeys::derive(
+        shared_secret.as_bytes(),
+        &transcript,
+        HandshakeRole::Receiver,
+    );
 
-    let encrypted_stream = EncryptedStream::new(shared_secret.to_bytes(), iv, stream);
+    let mut encrypted_stream = AuthenticatedStream::new(session_keys, stream);
+    encrypted_stream.set_chunk_size(capabilities.max_chunk_size as usize);
 
-    return Ok(encrypted_stream);
+    let identity_proof = match Stream::new(&mut encrypted_stream).recv::<IdentityProof>() {
+        Ok(message) => message,
+        Err(error) => return Err(IncomingErrors::HandshakeFailed(error.to_string())),
+    };
+
+    let peer_identity_key = verify_identity(
+        SENDER_SIGNATURE_LABEL,
+        &transcript,
+        identity_proof.identity_public_key,
+        &identity_proof.signature,
+    )?;
+
+    return Ok(Session {
+        stream: encrypted_stream,
+        peer_identity_key,
+        transcript_hash: transcript,
+        capabilities,
+    });
 }
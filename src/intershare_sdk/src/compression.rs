@@ -0,0 +1,138 @@
+//! Decides how files are compressed. Content that is compressed already, like photos, videos
+//! or archives, is sent as is, compressing it again only costs CPU time and battery.
+
+use std::io::Read;
+use std::path::Path;
+
+use crate::capabilities::Compression;
+use crate::transfer_source::{FileSource, TransferSource};
+
+/// How hard the sender tries to shrink compressible files.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CompressionPolicy {
+    /// Files are sent uncompressed.
+    Off,
+    /// Cheap compression, the best trade-off on fast networks and phones.
+    #[default]
+    Fast,
+    /// Smallest size, for slow connections like BLE.
+    Max,
+}
+
+const COMPRESSED_EXTENSIONS: &[&str] = &[
+    // Images
+    "jpg", "jpeg", "png", "gif", "webp", "heic", "heif", "avif", "jxl",
+    // Audio and video
+    "mp3", "m4a", "aac", "ogg", "opus", "flac", "mp4", "m4v", "mov", "mkv", "webm", "avi",
+    // Archives and packages
+    "zip", "gz", "tgz", "bz2", "xz", "zst", "7z", "rar", "apk", "aab", "ipa", "jar", "dmg",
+    // Documents that are ZIP archives
+    "docx", "xlsx", "pptx", "odt", "ods", "odp", "epub",
+];
+
+const MAGIC_BYTES: &[&[u8]] = &[
+    // JPEG
+    &[0xFF, 0xD8, 0xFF],
+    // PNG
+    &[0x89, b'P', b'N', b'G'],
+    // GIF
+    b"GIF8",
+    // ZIP
+    &[b'P', b'K', 0x03, 0x04],
+    // gzip
+    &[0x1F, 0x8B],
+    // zstd
+    &[0x28, 0xB5, 0x2F, 0xFD],
+    // xz
+    &[0xFD, b'7', b'z', b'X', b'Z', 0x00],
+    // 7z
+    &[b'7', b'z', 0xBC, 0xAF, 0x27, 0x1C],
+    // bzip2
+    b"BZh",
+    // RAR
+    b"Rar!",
+    // Matroska and WebM
+    &[0x1A, 0x45, 0xDF, 0xA3],
+    // Ogg
+    b"OggS",
+    // MP3 with ID3 tag
+    b"ID3",
+    // FLAC
+    b"fLaC",
+];
+
+fn has_compressed_extension(path: &Path) -> bool {
+    return path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            COMPRESSED_EXTENSIONS
+                .iter()
+                .any(|compressed| extension.eq_ignore_ascii_case(compressed))
+        });
+}
+
+fn has_compressed_magic_bytes(source: &dyn TransferSource) -> bool {
+    let mut header = [0u8; 12];
+
+    let Ok(mut reader) = source.open(0) else {
+        return false;
+    };
+
+    let mut length = 0;
+
+    while length < header.len() {
+        match reader.read(&mut header[length..]) {
+            Ok(0) | Err(_) => break,
+            Ok(read_size) => length += read_size,
+        }
+    }
+
+    let header = &header[..length];
+
+    // ISO base media files (MP4, MOV, HEIC, AVIF) and RIFF WebP have their marker at an offset.
+    let is_media_container = header.get(4..8) == Some(b"ftyp")
+        || (header.starts_with(b"RIFF") && header.get(8..12) == Some(b"WEBP"));
+
+    return is_media_container || MAGIC_BYTES.iter().any(|magic| header.starts_with(magic));
+}
+
+/// Whether the data is compressed already, judging by the extension of its name or its first
+/// bytes.
+pub fn is_compressed(source: &dyn TransferSource) -> bool {
+    return has_compressed_extension(Path::new(&source.get_name()))
+        || has_compressed_magic_bytes(source);
+}
+
+pub fn is_compressed_file(path: &Path) -> bool {
+    return is_compressed(&FileSource::new(path.to_path_buf()));
+}
+
+/// Deflate level for an entry of a ZIP archive, `None` if it's stored uncompressed.
+pub fn deflate_level(policy: CompressionPolicy, source: &dyn TransferSource) -> Option<i64> {
+    if policy == CompressionPolicy::Off || is_compressed(source) {
+        return None;
+    }
+
+    return match policy {
+        CompressionPolicy::Max => Some(9),
+        _ => Some(1),
+    };
+}
+
+/// zstd level for a framed file, `None` if it's sent uncompressed.
+pub fn zstd_level(
+    policy: CompressionPolicy,
+    negotiated: Compression,
+    source: &dyn TransferSource,
+) -> Option<i32> {
+    if policy == CompressionPolicy::Off || negotiated != Compression::Zstd || is_compressed(source)
+    {
+        return None;
+    }
+
+    return match policy {
+        CompressionPolicy::Max => Some(19),
+        _ => Some(1),
+    };
+}
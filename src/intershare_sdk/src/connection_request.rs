@@ -1,28 +1,117 @@
-use crate::zip::unzip_file;
-use crate::BLE_BUFFER_SIZE;
-use crate::{encryption::EncryptedReadWrite, nearby::ConnectionIntentType};
-use prost_stream::Stream;
+use crate::capabilities::ArchiveFormat;
+use crate::channel::{
+    is_cancellation, is_connection_loss, Channel, DataReader, TransferControl, CONTROL_STREAM_ID,
+    TRANSFER_STREAM_ID,
+};
+use crate::checkpoint::Checkpoint;
+use crate::clipboard::{
+    has_streamed_representations, read_clipboard_representations, ClipboardRepresentation,
+};
+use crate::encryption::EncryptedReadWrite;
+use crate::extraction::ExtractionLimits;
+use crate::framed::{receive_framed_files, receive_framed_files_into_sink};
+use crate::manifest::{verify_files, ReceivedFiles};
+use crate::nearby::{ConnectionIntentType, ConnectionMedium};
+use crate::parallel::{ParallelReceiver, ParallelTransfers};
+use crate::progress::{ProgressTracker, TransferProgress, DEFAULT_PROGRESS_INTERVAL};
+use crate::rate_limit::{RateLimited, RateLimits, TransferRateLimit};
+use crate::storage_quota::{reserve_storage, StorageQuotas, StorageReservation};
+use crate::transfer_history::{
+    record_transfer, PendingRecord, TransferDirection, TransferHistory, TransferOutcome,
+};
+use crate::transfer_sink::{SinkReceiver, TransferSink};
+use crate::trust_store::{TrustStatus, TrustStore};
+use crate::zip::{unzip_stream, unzip_stream_into_sink};
+use protocol::communication::message_header::MessageTypes;
 use protocol::communication::transfer_request::Intent;
+use protocol::communication::transfer_request_response;
 use protocol::communication::{
-    ClipboardTransferIntent, FileTransferIntent, TransferRequest, TransferRequestResponse,
+    ClipboardTransferIntent, FileManifestEntry, FileTransferIntent, TransferRequest,
+    TransferRequestResponse, TransferTrailer,
 };
 use protocol::discovery::Device;
+use protocol::prost::Message;
 use std::fmt::Debug;
-use std::io::{Read, Write};
+use std::io::{self, Read, Write};
+use std::path::Path;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Mutex, MutexGuard};
-use tempfile::NamedTempFile;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use tokio::sync::RwLock;
 
 pub enum ReceiveProgressState {
     Unknown,
     Handshake,
-    Receiving { progress: f64 },
+    Receiving {
+        progress: TransferProgress,
+    },
     Extracting,
+    /// The connection was lost. The transfer continues as a new, automatically accepted request
+    /// once the sender reconnects.
+    Interrupted,
+    /// Some files didn't match the hash the sender computed, they have been deleted.
+    IntegrityCheckFailed {
+        corrupt_files: Vec<String>,
+    },
     Cancelled,
+    /// The request was declined by the SDK after it was accepted, because the files don't fit.
+    Declined {
+        reason: DeclineReason,
+    },
     Finished,
 }
 
+/// Why a receiver declined a transfer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeclineReason {
+    /// The user declined it.
+    User,
+    /// There isn't enough free space for the files.
+    InsufficientStorage,
+    /// The sender already transferred as much as the receiver allows a single sender to.
+    SenderQuotaExceeded,
+    /// The files would take up more space than the receiver allows transfers to.
+    StorageQuotaExceeded,
+    /// The request is larger than the receiver accepts for its kind.
+    TooLarge,
+}
+
+impl From<transfer_request_response::DeclineReason> for DeclineReason {
+    fn from(reason: transfer_request_response::DeclineReason) -> Self {
+        return match reason {
+            transfer_request_response::DeclineReason::User => DeclineReason::User,
+            transfer_request_response::DeclineReason::InsufficientStorage => {
+                DeclineReason::InsufficientStorage
+            }
+            transfer_request_response::DeclineReason::SenderQuotaExceeded => {
+                DeclineReason::SenderQuotaExceeded
+            }
+            transfer_request_response::DeclineReason::StorageQuotaExceeded => {
+                DeclineReason::StorageQuotaExceeded
+            }
+            transfer_request_response::DeclineReason::TooLarge => DeclineReason::TooLarge,
+        };
+    }
+}
+
+impl From<DeclineReason> for transfer_request_response::DeclineReason {
+    fn from(reason: DeclineReason) -> Self {
+        return match reason {
+            DeclineReason::User => transfer_request_response::DeclineReason::User,
+            DeclineReason::InsufficientStorage => {
+                transfer_request_response::DeclineReason::InsufficientStorage
+            }
+            DeclineReason::SenderQuotaExceeded => {
+                transfer_request_response::DeclineReason::SenderQuotaExceeded
+            }
+            DeclineReason::StorageQuotaExceeded => {
+                transfer_request_response::DeclineReason::StorageQuotaExceeded
+            }
+            DeclineReason::TooLarge => transfer_request_response::DeclineReason::TooLarge,
+        };
+    }
+}
+
 pub trait ReceiveProgressDelegate: Send + Sync + Debug {
     fn progress_changed(&self, progress: ReceiveProgressState);
 }
@@ -35,7 +124,26 @@ pub struct ConnectionRequest {
     transfer_request: TransferRequest,
     connection: Arc<Mutex<Box<dyn EncryptedReadWrite>>>,
     file_storage: String,
-    should_cancel: AtomicBool,
+    sender_identity_key: [u8; 32],
+    verification_code: String,
+    verification_confirmed: AtomicBool,
+    trust_store: Arc<Mutex<TrustStore>>,
+    auto_accept: bool,
+    resumed_checkpoint: Mutex<Option<Checkpoint>>,
+    responded: AtomicBool,
+    control: Arc<TransferControl>,
+    parallel_transfers: ParallelTransfers,
+    max_parallel_connections: u32,
+    rate_limits: RateLimits,
+    transfer_sink: Option<Arc<dyn TransferSink>>,
+    extraction_limits: ExtractionLimits,
+    storage_quotas: Arc<Mutex<StorageQuotas>>,
+    progress_interval: Duration,
+    transfer_history: Arc<Mutex<Option<TransferHistory>>>,
+    medium: Option<ConnectionMedium>,
+    /// How the transfer ended, as far as it's recorded in the history.
+    outcome: Mutex<Option<TransferOutcome>>,
+    received_clipboard: Mutex<Option<Vec<ClipboardRepresentation>>>,
     variables: Arc<RwLock<SharedVariables>>,
 }
 
@@ -44,23 +152,114 @@ impl ConnectionRequest {
         transfer_request: TransferRequest,
         connection: Box<dyn EncryptedReadWrite>,
         file_storage: String,
+        sender_identity_key: [u8; 32],
+        verification_code: String,
+        trust_store: Arc<Mutex<TrustStore>>,
+        auto_accept_trusted: bool,
     ) -> Self {
+        let trust_status = match &transfer_request.device {
+            Some(device) => trust_store
+                .lock()
+                .expect("Failed to lock trust store")
+                .status(device, &sender_identity_key),
+            None => TrustStatus::Unknown,
+        };
+
+        if trust_status == TrustStatus::KeyChanged {
+            println!("Warning: identity key of the sending device changed");
+        }
+
+        let resumed_checkpoint = match &transfer_request.intent {
+            Some(Intent::FileTransfer(file_transfer)) => Checkpoint::resume(
+                &file_storage,
+                &file_transfer.transfer_id,
+                &sender_identity_key,
+                &file_transfer.files,
+            ),
+            _ => None,
+        };
+
+        // The user already accepted the interrupted transfer.
+        let auto_accept = (auto_accept_trusted && trust_status == TrustStatus::Trusted)
+            || resumed_checkpoint.is_some();
+
         Self {
             transfer_request,
             connection: Arc::new(Mutex::new(connection)),
             file_storage,
-            should_cancel: AtomicBool::new(false),
+            sender_identity_key,
+            verification_code,
+            verification_confirmed: AtomicBool::new(false),
+            trust_store,
+            auto_accept,
+            resumed_checkpoint: Mutex::new(resumed_checkpoint),
+            responded: AtomicBool::new(false),
+            control: Arc::new(TransferControl::new()),
+            parallel_transfers: ParallelTransfers::default(),
+            max_parallel_connections: 1,
+            rate_limits: RateLimits::default(),
+            transfer_sink: None,
+            extraction_limits: ExtractionLimits::default(),
+            storage_quotas: Arc::new(Mutex::new(StorageQuotas::default())),
+            progress_interval: DEFAULT_PROGRESS_INTERVAL,
+            transfer_history: Arc::new(Mutex::new(None)),
+            medium: None,
+            outcome: Mutex::new(None),
+            received_clipboard: Mutex::new(None),
             variables: Arc::new(RwLock::new(SharedVariables {
                 receive_progress_delegate: None,
             })),
         }
     }
 
+    /// Lets the sender spread an accepted file transfer over up to `max_connections` connections,
+    /// the additional ones find it in `parallel_transfers`.
+    pub(crate) fn set_parallel_transfers(
+        &mut self,
+        parallel_transfers: ParallelTransfers,
+        max_connections: u32,
+    ) {
+        self.parallel_transfers = parallel_transfers;
+        self.max_parallel_connections = max_connections;
+    }
+
+    pub(crate) fn set_rate_limits(&mut self, rate_limits: RateLimits) {
+        self.rate_limits = rate_limits;
+    }
+
+    pub(crate) fn set_extraction_limits(&mut self, extraction_limits: ExtractionLimits) {
+        self.extraction_limits = extraction_limits;
+    }
+
+    pub(crate) fn set_storage_quotas(&mut self, storage_quotas: Arc<Mutex<StorageQuotas>>) {
+        self.storage_quotas = storage_quotas;
+    }
+
+    pub(crate) fn set_progress_interval(&mut self, progress_interval: Duration) {
+        self.progress_interval = progress_interval;
+    }
+
+    /// Records the file transfer in `transfer_history`, if one was loaded.
+    pub(crate) fn set_transfer_history(
+        &mut self,
+        transfer_history: Arc<Mutex<Option<TransferHistory>>>,
+        medium: ConnectionMedium,
+    ) {
+        self.transfer_history = transfer_history;
+        self.medium = Some(medium);
+    }
+
+    pub(crate) fn set_transfer_sink(&mut self, transfer_sink: Option<Arc<dyn TransferSink>>) {
+        self.transfer_sink = transfer_sink;
+    }
+
     pub fn set_progress_delegate(&self, delegate: Box<dyn ReceiveProgressDelegate>) {
         let mut variables = self.variables.blocking_write();
         variables.receive_progress_delegate = Some(delegate);
     }
 
+    /// The sending device. Its id has been verified to belong to the identity key the sender
+    /// proved ownership of during the handshake.
     pub fn get_sender(&self) -> Device {
         self.transfer_request
             .device
@@ -68,6 +267,164 @@ impl ConnectionRequest {
             .expect("Device information missing")
     }
 
+    pub fn get_sender_identity_key(&self) -> Vec<u8> {
+        self.sender_identity_key.to_vec()
+    }
+
+    /// 6-digit code derived from the handshake. It matches the code shown on the sender
+    /// (`SendProgressState::VerificationCode`) unless someone intercepted the connection.
+    pub fn get_verification_code(&self) -> String {
+        self.verification_code.clone()
+    }
+
+    /// Confirms the code the user compared with the sender's screen.
+    ///
+    /// A mismatch means the connection can't be trusted, so the request is declined right away.
+    pub fn confirm_verification_code(&self, code: String) -> bool {
+        if code.trim() != self.verification_code {
+            self.decline();
+            return false;
+        }
+
+        self.verification_confirmed.store(true, Ordering::Relaxed);
+
+        let result = self
+            .trust_store
+            .lock()
+            .expect("Failed to lock trust store")
+            .trust(&self.get_sender(), &self.sender_identity_key);
+
+        if let Err(error) = result {
+            println!("Failed to update trust store: {:?}", error);
+        }
+
+        return true;
+    }
+
+    pub fn get_trust_status(&self) -> TrustStatus {
+        self.trust_store
+            .lock()
+            .expect("Failed to lock trust store")
+            .status(&self.get_sender(), &self.sender_identity_key)
+    }
+
+    pub fn is_sender_trusted(&self) -> bool {
+        self.get_trust_status() == TrustStatus::Trusted
+    }
+
+    /// Whether the SDK accepts this request by itself once the delegate returned, because the
+    /// sender is trusted and auto-accept is enabled on the `NearbyServer`, or because it resumes
+    /// an interrupted transfer.
+    pub fn is_auto_accepted(&self) -> bool {
+        self.auto_accept
+    }
+
+    /// Whether this request continues a transfer that was interrupted by a connection loss.
+    pub fn is_resumed(&self) -> bool {
+        self.resumed_checkpoint
+            .lock()
+            .expect("Failed to lock checkpoint")
+            .is_some()
+    }
+
+    /// Framed file transfers can be resumed, their progress is recorded in a checkpoint.
+    fn take_checkpoint(&self, file_transfer: &FileTransferIntent) -> Option<Checkpoint> {
+        // Checkpoints rely on the partial files in the file storage.
+        if file_transfer.archive_format != ArchiveFormat::Framed as i32
+            || self.transfer_sink.is_some()
+        {
+            return None;
+        }
+
+        if let Some(checkpoint) = self
+            .resumed_checkpoint
+            .lock()
+            .expect("Failed to lock checkpoint")
+            .take()
+        {
+            return Some(checkpoint);
+        }
+
+        return Checkpoint::new(
+            &self.file_storage,
+            &file_transfer.transfer_id,
+            &self.sender_identity_key,
+            &file_transfer.files,
+        );
+    }
+
+    /// Connections the file transfer may be spread over. Only framed transfers are split.
+    fn parallel_connections(&self, file_transfer: &FileTransferIntent) -> u32 {
+        // Sinks get every file in order.
+        if file_transfer.archive_format != ArchiveFormat::Framed as i32
+            || file_transfer.transfer_id.is_empty()
+            || self.transfer_sink.is_some()
+        {
+            return 1;
+        }
+
+        return std::cmp::max(
+            std::cmp::min(
+                file_transfer.parallel_connections,
+                self.max_parallel_connections,
+            ),
+            1,
+        );
+    }
+
+    /// Registers the transfer before it's accepted, so additional connections are able to join
+    /// right away.
+    fn register_parallel_transfer(
+        &self,
+        file_transfer: &FileTransferIntent,
+        checkpoint: Option<Checkpoint>,
+        rate_limit: Arc<TransferRateLimit>,
+        connections: u32,
+    ) -> Arc<ParallelReceiver> {
+        let parallel_receiver = Arc::new(ParallelReceiver::new(
+            self.file_storage.clone(),
+            self.sender_identity_key,
+            file_transfer.files.clone(),
+            checkpoint,
+            self.control.clone(),
+            rate_limit,
+            connections,
+        ));
+
+        self.parallel_transfers
+            .lock()
+            .expect("Failed to lock parallel transfers")
+            .insert(file_transfer.transfer_id.clone(), parallel_receiver.clone());
+
+        return parallel_receiver;
+    }
+
+    /// Marks the request as answered. Returns `false` if it already was.
+    fn respond(&self) -> bool {
+        !self.responded.swap(true, Ordering::SeqCst)
+    }
+
+    /// Pins a sender the user accepted a transfer from for the first time (trust on first use).
+    fn pin_sender(&self) {
+        if self.get_trust_status() != TrustStatus::Unknown {
+            return;
+        }
+
+        let result = self
+            .trust_store
+            .lock()
+            .expect("Failed to lock trust store")
+            .pin(&self.get_sender(), &self.sender_identity_key);
+
+        if let Err(error) = result {
+            println!("Failed to update trust store: {:?}", error);
+        }
+    }
+
+    pub fn is_verification_confirmed(&self) -> bool {
+        self.verification_confirmed.load(Ordering::Relaxed)
+    }
+
     pub fn get_intent(&self) -> Intent {
         self.transfer_request
             .intent
@@ -83,7 +440,7 @@ impl ConnectionRequest {
             .expect("Intent information missing")
         {
             Intent::FileTransfer(_) => ConnectionIntentType::FileTransfer,
-            Intent::Clipboard(_) => ConnectionIntentType::FileTransfer,
+            Intent::Clipboard(_) => ConnectionIntentType::Clipboard,
         }
     }
 
@@ -99,6 +456,14 @@ impl ConnectionRequest {
         }
     }
 
+    /// Files the sender is about to send, empty for other intents.
+    pub fn get_file_manifest(&self) -> Vec<FileManifestEntry> {
+        return self
+            .get_file_transfer_intent()
+            .map(|intent| intent.files)
+            .unwrap_or_default();
+    }
+
     pub fn get_clipboard_intent(&self) -> Option<ClipboardTransferIntent> {
         match self
             .transfer_request
@@ -112,15 +477,108 @@ impl ConnectionRequest {
     }
 
     pub fn decline(&self) {
-        if let Ok(mut connection_guard) = self.connection.lock() {
-            let mut stream = Stream::new(&mut *connection_guard);
+        if !self.respond() {
+            return;
+        }
+
+        let pending_record = self.pending_record();
+        self.send_decline(DeclineReason::User);
+        self.set_outcome(TransferOutcome::Declined);
+        self.record_history(pending_record);
+    }
+
+    /// Starts recording a file transfer, clipboard transfers aren't part of the history.
+    fn pending_record(&self) -> Option<PendingRecord> {
+        let file_transfer = self.get_file_transfer_intent()?;
+
+        return Some(PendingRecord::start(
+            TransferDirection::Received,
+            self.get_sender(),
+            file_transfer.files,
+            file_transfer.file_size,
+        ));
+    }
+
+    fn set_outcome(&self, outcome: TransferOutcome) {
+        *self.outcome.lock().expect("Failed to lock outcome") = Some(outcome);
+    }
 
-            let _ = stream.send(&TransferRequestResponse { accepted: false });
-            connection_guard.close();
+    fn record_history(&self, pending_record: Option<PendingRecord>) {
+        let Some(pending_record) = pending_record else {
+            return;
+        };
+
+        let outcome = self
+            .outcome
+            .lock()
+            .expect("Failed to lock outcome")
+            .unwrap_or(TransferOutcome::Failed);
+
+        record_transfer(
+            &self.transfer_history,
+            pending_record.finish(self.medium, outcome, None),
+        );
+    }
+
+    fn send_decline(&self, reason: DeclineReason) {
+        if let Ok(mut connection_guard) = self.connection.lock() {
+            let _ = Channel::new(&mut *connection_guard).send_message(
+                MessageTypes::TransferResponse,
+                CONTROL_STREAM_ID,
+                &TransferRequestResponse {
+                    accepted: false,
+                    resume_offsets: vec![],
+                    parallel_connections: 1,
+                    decline_reason: transfer_request_response::DeclineReason::from(reason) as i32,
+                },
+            );
+            connection_guard.shutdown();
         }
     }
 
+    /// Sets aside the space for the files of a file transfer, or tells why they don't fit.
+    ///
+    /// Bytes a resumed transfer already received are on disk, only the rest is reserved.
+    fn reserve_storage(&self) -> Result<Option<StorageReservation>, DeclineReason> {
+        let Some(file_transfer) = self.get_file_transfer_intent() else {
+            return Ok(None);
+        };
+
+        let received_bytes: u64 = self
+            .resumed_checkpoint
+            .lock()
+            .expect("Failed to lock checkpoint")
+            .as_ref()
+            .map(|checkpoint| checkpoint.resume_offsets().iter().sum())
+            .unwrap_or(0);
+
+        // Sinks decide on their own where the files end up.
+        let file_storage = match self.transfer_sink {
+            Some(_) => None,
+            None => Some(Path::new(&self.file_storage)),
+        };
+
+        return reserve_storage(
+            &self.storage_quotas,
+            file_storage,
+            &self.get_sender().id,
+            file_transfer.file_size.saturating_sub(received_bytes),
+        )
+        .map(Some);
+    }
+
     fn update_progress(&self, new_state: ReceiveProgressState) {
+        match &new_state {
+            ReceiveProgressState::Interrupted => self.set_outcome(TransferOutcome::Interrupted),
+            ReceiveProgressState::IntegrityCheckFailed { .. } => {
+                self.set_outcome(TransferOutcome::IntegrityCheckFailed)
+            }
+            ReceiveProgressState::Cancelled => self.set_outcome(TransferOutcome::Cancelled),
+            ReceiveProgressState::Declined { .. } => self.set_outcome(TransferOutcome::Declined),
+            ReceiveProgressState::Finished => self.set_outcome(TransferOutcome::Completed),
+            _ => {}
+        }
+
         if let Some(receive_progress_delegate) =
             &self.variables.blocking_read().receive_progress_delegate
         {
@@ -128,87 +586,424 @@ impl ConnectionRequest {
         }
     }
 
+    fn progress_tracker(&self, file_transfer: &FileTransferIntent) -> ProgressTracker {
+        let files = file_transfer
+            .files
+            .iter()
+            .map(|entry| (entry.path.clone(), entry.size))
+            .collect();
+
+        return ProgressTracker::new(files, file_transfer.file_size, self.progress_interval);
+    }
+
+    fn report_progress(&self, progress_tracker: &ProgressTracker, received_bytes: u64) {
+        if let Some(progress) = progress_tracker.update(received_bytes) {
+            self.update_progress(ReceiveProgressState::Receiving { progress });
+        }
+    }
+
     pub fn cancel(&self) {
-        self.should_cancel.store(true, Ordering::Relaxed);
+        self.control.cancel();
+    }
+
+    /// Asks the sender to pause. Data already in flight is received before the transfer stalls.
+    pub fn pause(&self) {
+        self.control.pause();
+    }
+
+    pub fn resume(&self) {
+        self.control.resume();
     }
 
     pub fn accept(&self) -> Option<Vec<String>> {
+        if !self.respond() {
+            return None;
+        }
+
+        let pending_record = self.pending_record();
+
+        let storage_reservation = match self.reserve_storage() {
+            Ok(storage_reservation) => storage_reservation,
+            Err(reason) => {
+                println!("Declining transfer: {:?}", reason);
+                self.send_decline(reason);
+                self.update_progress(ReceiveProgressState::Declined { reason });
+                self.record_history(pending_record);
+                return None;
+            }
+        };
+
+        self.pin_sender();
         self.update_progress(ReceiveProgressState::Handshake);
 
         if let Ok(mut connection_guard) = self.connection.lock() {
-            let mut stream = Stream::new(&mut *connection_guard);
+            let mut channel = Channel::new(&mut *connection_guard);
+
+            let intent = self.get_intent();
+
+            let mut checkpoint = match &intent {
+                Intent::FileTransfer(file_transfer) => self.take_checkpoint(file_transfer),
+                Intent::Clipboard(_) => None,
+            };
 
-            let _ = stream.send(&TransferRequestResponse { accepted: true });
+            let resume_offsets = checkpoint
+                .as_ref()
+                .map(Checkpoint::resume_offsets)
+                .unwrap_or_default();
 
-            match self.get_intent() {
-                Intent::FileTransfer(file_transfer) => {
-                    self.handle_file(connection_guard, file_transfer)
+            let parallel_connections = match &intent {
+                Intent::FileTransfer(file_transfer) => self.parallel_connections(file_transfer),
+                Intent::Clipboard(_) => 1,
+            };
+
+            // Shared by all connections of the transfer.
+            let rate_limit = Arc::new(self.rate_limits.for_transfer());
+
+            let parallel_receiver = match &intent {
+                Intent::FileTransfer(file_transfer) if parallel_connections > 1 => {
+                    Some(self.register_parallel_transfer(
+                        file_transfer,
+                        checkpoint.take(),
+                        rate_limit.clone(),
+                        parallel_connections,
+                    ))
                 }
-                Intent::Clipboard(clipboard) => self.handle_clipboard(clipboard),
+                _ => None,
+            };
+
+            let _ = channel.send_message(
+                MessageTypes::TransferResponse,
+                CONTROL_STREAM_ID,
+                &TransferRequestResponse {
+                    accepted: true,
+                    resume_offsets,
+                    parallel_connections,
+                    decline_reason: transfer_request_response::DeclineReason::User as i32,
+                },
+            );
+
+            let result = match intent {
+                Intent::FileTransfer(file_transfer) => self.handle_file(
+                    &mut channel,
+                    file_transfer,
+                    checkpoint,
+                    parallel_receiver,
+                    &rate_limit,
+                ),
+                Intent::Clipboard(clipboard) => self.handle_clipboard(&mut channel, clipboard),
+            };
+
+            connection_guard.shutdown();
+
+            if let (Some(storage_reservation), Some(_)) = (storage_reservation, &result) {
+                storage_reservation.finish();
             }
+
+            self.record_history(pending_record);
+
+            result
         } else {
             None
         }
     }
 
-    fn handle_clipboard(
+    /// Receives the representations that didn't fit into the request.
+    ///
+    /// Returns the plain text representations, all of them are available through
+    /// [`Self::get_clipboard_representations`].
+    fn handle_clipboard<T>(
         &self,
-        _clipboard_transfer_intent: ClipboardTransferIntent,
-    ) -> Option<Vec<String>> {
-        panic!("Not implemented yet");
+        channel: &mut Channel<T>,
+        clipboard_transfer_intent: ClipboardTransferIntent,
+    ) -> Option<Vec<String>>
+    where
+        T: Read + Write,
+    {
+        let representations = if has_streamed_representations(&clipboard_transfer_intent) {
+            let mut reader = DataReader::new(channel, TRANSFER_STREAM_ID, &self.control);
+
+            let result = read_clipboard_representations(&clipboard_transfer_intent, &mut reader)
+                .and_then(|representations| {
+                    // The sender ends the stream right after the announced data.
+                    match reader.read(&mut [0u8; 1])? {
+                        0 => Ok(representations),
+                        _ => Err(std::io::Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            "Received more clipboard data than announced",
+                        )),
+                    }
+                });
+
+            match result {
+                Ok(representations) => {
+                    let _ = reader.confirm();
+                    representations
+                }
+                Err(error) => {
+                    if !is_cancellation(&error) {
+                        println!("Error {:?}", error);
+                    }
+
+                    self.update_progress(ReceiveProgressState::Cancelled);
+                    return None;
+                }
+            }
+        } else {
+            read_clipboard_representations(&clipboard_transfer_intent, &mut std::io::empty())
+                .ok()?
+        };
+
+        let text = representations
+            .iter()
+            .filter(|representation| representation.is_plain_text())
+            .map(|representation| String::from_utf8_lossy(&representation.data).to_string())
+            .collect();
+
+        *self
+            .received_clipboard
+            .lock()
+            .expect("Failed to lock clipboard") = Some(representations);
+
+        self.update_progress(ReceiveProgressState::Finished);
+
+        return Some(text);
+    }
+
+    /// All representations of an accepted clipboard transfer, `None` before it was received.
+    pub fn get_clipboard_representations(&self) -> Option<Vec<ClipboardRepresentation>> {
+        return self
+            .received_clipboard
+            .lock()
+            .expect("Failed to lock clipboard")
+            .clone();
     }
 
-    fn handle_file(
+    /// Receives the files. Files of a framed transfer are kept if the connection drops, the
+    /// checkpoint lets the transfer continue once the sender reconnects.
+    ///
+    /// With a [`ParallelReceiver`], this connection is the primary one of a transfer spread over
+    /// several connections, the checkpoint is part of the receiver.
+    fn handle_file<T>(
         &self,
-        mut stream: MutexGuard<Box<dyn EncryptedReadWrite>>,
+        channel: &mut Channel<T>,
         file_transfer: FileTransferIntent,
-    ) -> Option<Vec<String>> {
-        let named_file = NamedTempFile::new().expect("Failed to create temporary ZIP file.");
-        let mut zip_file = named_file
-            .reopen()
-            .expect("Failed to reopen temporary ZIP file");
+        mut checkpoint: Option<Checkpoint>,
+        parallel_receiver: Option<Arc<ParallelReceiver>>,
+        rate_limit: &TransferRateLimit,
+    ) -> Option<Vec<String>>
+    where
+        T: Read + Write,
+    {
+        let mut reader = DataReader::new(channel, TRANSFER_STREAM_ID, &self.control);
 
-        let mut buffer = [0; BLE_BUFFER_SIZE];
-        let mut all_read = 0.0;
+        if let Some(transfer_sink) = &self.transfer_sink {
+            return self.receive_into_sink(
+                reader,
+                &file_transfer,
+                transfer_sink.as_ref(),
+                rate_limit,
+            );
+        }
 
-        while let Ok(read_size) = stream.read(&mut buffer) {
-            if self.should_cancel.load(Ordering::Relaxed) || read_size == 0 {
-                break;
-            }
+        let mut budget = self.extraction_limits.budget(file_transfer.file_size);
+        let progress_tracker = self.progress_tracker(&file_transfer);
 
-            all_read += read_size as f64;
+        let result = match (
+            ArchiveFormat::try_from(file_transfer.archive_format),
+            parallel_receiver,
+        ) {
+            (Ok(ArchiveFormat::Framed), Some(parallel_receiver)) => {
+                // Chunks are checked against the manifest, so it's the manifest that has to
+                // stay within the limits.
+                let result = budget
+                    .add_manifest(&file_transfer.files)
+                    .map_err(io::Error::from)
+                    .and_then(|_| {
+                        parallel_receiver.receive_chunks(&mut reader, |total_bytes| {
+                            self.report_progress(&progress_tracker, total_bytes);
+                        })
+                    })
+                    .and_then(|_| parallel_receiver.finish())
+                    .map(|paths| ReceivedFiles {
+                        paths,
+                        hashes: vec![],
+                    });
 
-            zip_file
-                .write_all(&buffer[..read_size])
-                .expect("Failed to write file to disk");
+                self.parallel_transfers
+                    .lock()
+                    .expect("Failed to lock parallel transfers")
+                    .remove(&file_transfer.transfer_id);
 
-            let progress = all_read / file_transfer.file_size as f64;
-            self.update_progress(ReceiveProgressState::Receiving { progress });
+                checkpoint = parallel_receiver.take_checkpoint();
+                result
+            }
+            (Ok(ArchiveFormat::Framed), None) => {
+                let resume_offsets = checkpoint
+                    .as_ref()
+                    .map(Checkpoint::resume_offsets)
+                    .unwrap_or_default();
+
+                receive_framed_files(
+                    RateLimited::new(&mut reader, rate_limit),
+                    &self.file_storage,
+                    &file_transfer.files,
+                    &resume_offsets,
+                    &mut budget,
+                    |progress| {
+                        if let Some(checkpoint) = &mut checkpoint {
+                            checkpoint.update(progress.file_index, progress.file_bytes);
+                        }
 
-            if all_read >= file_transfer.file_size as f64 {
-                break;
+                        self.report_progress(&progress_tracker, progress.total_bytes);
+                    },
+                )
+                .map_err(io::Error::from)
             }
-        }
+            (Ok(ArchiveFormat::Zip), _) => unzip_stream(
+                RateLimited::new(&mut reader, rate_limit),
+                &self.file_storage,
+                &mut budget,
+                |extracted_bytes| self.report_progress(&progress_tracker, extracted_bytes),
+            )
+            .map(|paths| ReceivedFiles {
+                paths,
+                hashes: vec![],
+            })
+            .map_err(io::Error::from),
+            (Err(_), _) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Unsupported archive format",
+            )),
+        };
 
-        stream.close();
+        let result = result.and_then(|received_files| {
+            let trailer = decode_trailer(reader.trailer())?;
+            let transfer_result = verify_files(
+                &self.file_storage,
+                &file_transfer.files,
+                &trailer.hashes,
+                &received_files,
+            )?;
+            return Ok((received_files.paths, transfer_result));
+        });
 
-        if all_read < file_transfer.file_size as f64 {
-            let _ = named_file.close();
-            self.update_progress(ReceiveProgressState::Cancelled);
-            return None;
-        }
+        match result {
+            Ok((files, transfer_result)) => {
+                if let Some(checkpoint) = checkpoint {
+                    checkpoint.remove();
+                }
+
+                let _ = reader.confirm_with(transfer_result.encode_to_vec());
+
+                if !transfer_result.corrupt_files.is_empty() {
+                    self.update_progress(ReceiveProgressState::IntegrityCheckFailed {
+                        corrupt_files: transfer_result.corrupt_files,
+                    });
+                    return None;
+                }
 
-        self.update_progress(ReceiveProgressState::Extracting);
-        match unzip_file(zip_file, &self.file_storage) {
-            Ok(files) => {
                 self.update_progress(ReceiveProgressState::Finished);
                 Some(files)
             }
+            Err(error) if is_connection_loss(&error) && checkpoint.is_some() => {
+                println!(
+                    "Connection lost, waiting for the sender to resume: {:?}",
+                    error
+                );
+
+                if let Some(Err(error)) = checkpoint.as_mut().map(Checkpoint::save) {
+                    println!("Failed to save checkpoint: {:?}", error);
+                }
+
+                self.update_progress(ReceiveProgressState::Interrupted);
+                None
+            }
             Err(error) => {
-                println!("Error {:?}", error);
+                if !is_cancellation(&error) {
+                    println!("Error {:?}", error);
+                    let _ = reader.fail(error.to_string());
+                }
+
+                if let Some(checkpoint) = checkpoint {
+                    checkpoint.remove();
+                }
+
                 self.update_progress(ReceiveProgressState::Cancelled);
                 None
             }
         }
     }
+
+    /// Receives the files into the [`TransferSink`] of the app instead of the file storage.
+    fn receive_into_sink<T>(
+        &self,
+        mut reader: DataReader<T>,
+        file_transfer: &FileTransferIntent,
+        transfer_sink: &dyn TransferSink,
+        rate_limit: &TransferRateLimit,
+    ) -> Option<Vec<String>>
+    where
+        T: Read + Write,
+    {
+        let mut receiver = SinkReceiver::new(transfer_sink, &file_transfer.files);
+        let mut budget = self.extraction_limits.budget(file_transfer.file_size);
+        let progress_tracker = self.progress_tracker(file_transfer);
+
+        let update_progress =
+            |received_bytes: u64| self.report_progress(&progress_tracker, received_bytes);
+
+        let result = match ArchiveFormat::try_from(file_transfer.archive_format) {
+            Ok(ArchiveFormat::Framed) => receive_framed_files_into_sink(
+                RateLimited::new(&mut reader, rate_limit),
+                &mut receiver,
+                &mut budget,
+                update_progress,
+            ),
+            Ok(ArchiveFormat::Zip) => unzip_stream_into_sink(
+                RateLimited::new(&mut reader, rate_limit),
+                &mut receiver,
+                &mut budget,
+                update_progress,
+            ),
+            Err(_) => {
+                Err(io::Error::new(io::ErrorKind::Unsupported, "Unsupported archive format").into())
+            }
+        }
+        .map_err(io::Error::from)
+        .and_then(|_| decode_trailer(reader.trailer()));
+
+        let trailer = match result {
+            Ok(trailer) => trailer,
+            Err(error) => {
+                if !is_cancellation(&error) {
+                    println!("Error {:?}", error);
+                    let _ = reader.fail(error.to_string());
+                }
+
+                self.update_progress(ReceiveProgressState::Cancelled);
+                return None;
+            }
+        };
+
+        let (files, transfer_result) = receiver.finish(&trailer.hashes);
+        let _ = reader.confirm_with(transfer_result.encode_to_vec());
+
+        if !transfer_result.corrupt_files.is_empty() {
+            self.update_progress(ReceiveProgressState::IntegrityCheckFailed {
+                corrupt_files: transfer_result.corrupt_files,
+            });
+            return None;
+        }
+
+        self.update_progress(ReceiveProgressState::Finished);
+
+        return Some(files);
+    }
+}
+
+/// Trailer the sender ended the stream with. Senders that don't send one just leave the files
+/// unverified.
+fn decode_trailer(trailer: &[u8]) -> io::Result<TransferTrailer> {
+    return TransferTrailer::decode(trailer)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error));
 }
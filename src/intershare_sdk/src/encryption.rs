@@ -1,10 +1,14 @@
 use chacha20::cipher::{KeyIvInit, StreamCipher};
 use chacha20::XChaCha20;
+use chacha20poly1305::aead::generic_array::GenericArray;
+use chacha20poly1305::aead::stream::{DecryptorBE32, EncryptorBE32};
+use chacha20poly1305::{KeyInit, XChaCha20Poly1305};
+use hkdf::Hkdf;
 use rand_core::OsRng;
+use sha2::Sha256;
 use std::io;
-use std::io::ErrorKind::Other;
+use std::io::ErrorKind::{BrokenPipe, InvalidData, Other, UnexpectedEof};
 use std::io::{Error, Read, Write};
-use std::iter::repeat;
 
 use crate::stream::Close;
 
@@ -47,7 +51,7 @@ where
     TStream: Read + Write,
 {
     fn read(&mut self, read_buffer: &mut [u8]) -> io::Result<usize> {
-        let mut buffer: Vec<u8> = repeat(0).take(read_buffer.len()).collect();
+        let mut buffer = vec![0u8; read_buffer.len()];
         let read_bytes = self
             .raw_stream
             .read(&mut buffer)
@@ -74,7 +78,7 @@ where
     TStream: Read + Write,
 {
     fn write(&mut self, write_buffer: &[u8]) -> io::Result<usize> {
-        let mut buffer: Vec<u8> = repeat(0).take(write_buffer.len()).collect();
+        let mut buffer = vec![0u8; write_buffer.len()];
         let ciphertext = self.cipher.apply_keystream_b2b(write_buffer, &mut buffer);
 
         if let Ok(()) = ciphertext {
@@ -100,8 +104,303 @@ where
     }
 }
 
-pub trait EncryptedReadWrite: Read + Write + Send + Close {}
+/// Maximum amount of plaintext sealed into a single frame of an [`AuthenticatedStream`].
+pub const AEAD_CHUNK_SIZE: usize = 64 * 1024;
+
+pub const AEAD_STREAM_NONCE_SIZE: usize = 19;
+const AEAD_TAG_SIZE: usize = 16;
+
+/// Set in the length prefix of the frame sealed with the STREAM last-block flag.
+const LAST_FRAME_FLAG: u32 = 1 << 31;
+
+const SENDER_KEY_INFO: &[u8] = b"InterShare sender key";
+const SENDER_NONCE_INFO: &[u8] = b"InterShare sender nonce";
+const RECEIVER_KEY_INFO: &[u8] = b"InterShare receiver key";
+const RECEIVER_NONCE_INFO: &[u8] = b"InterShare receiver nonce";
+
+pub enum HandshakeRole {
+    Sender,
+    Receiver,
+}
+
+/// Keys and STREAM nonces for both directions of a session, seen from one peer.
+///
+/// The sender's `send_*` values are the receiver's `receive_*` values and vice versa, so the two
+/// directions never share a keystream.
+#[derive(Clone)]
+pub struct SessionKeys {
+    pub send_key: [u8; 32],
+    pub send_nonce: [u8; AEAD_STREAM_NONCE_SIZE],
+    pub receive_key: [u8; 32],
+    pub receive_nonce: [u8; AEAD_STREAM_NONCE_SIZE],
+}
+
+impl SessionKeys {
+    /// Runs HKDF-SHA256 over the X25519 shared secret, salted with the handshake transcript hash.
+    pub fn derive(
+        shared_secret: &[u8; 32],
+        transcript_hash: &[u8; 32],
+        role: HandshakeRole,
+    ) -> Self {
+        let hkdf = Hkdf::<Sha256>::new(Some(transcript_hash), shared_secret);
+
+        let mut sender_key = [0u8; 32];
+        let mut sender_nonce = [0u8; AEAD_STREAM_NONCE_SIZE];
+        let mut receiver_key = [0u8; 32];
+        let mut receiver_nonce = [0u8; AEAD_STREAM_NONCE_SIZE];
+
+        hkdf.expand(SENDER_KEY_INFO, &mut sender_key)
+            .expect("Invalid HKDF output length");
+        hkdf.expand(SENDER_NONCE_INFO, &mut sender_nonce)
+            .expect("Invalid HKDF output length");
+        hkdf.expand(RECEIVER_KEY_INFO, &mut receiver_key)
+            .expect("Invalid HKDF output length");
+        hkdf.expand(RECEIVER_NONCE_INFO, &mut receiver_nonce)
+            .expect("Invalid HKDF output length");
+
+        return match role {
+            HandshakeRole::Sender => Self {
+                send_key: sender_key,
+                send_nonce: sender_nonce,
+                receive_key: receiver_key,
+                receive_nonce: receiver_nonce,
+            },
+            HandshakeRole::Receiver => Self {
+                send_key: receiver_key,
+                send_nonce: receiver_nonce,
+                receive_key: sender_key,
+                receive_nonce: sender_nonce,
+            },
+        };
+    }
+}
+
+/// Stream wrapper sealing every write into a length-prefixed XChaCha20-Poly1305 frame
+/// (STREAM construction, big-endian 32 bit counter).
+///
+/// Each frame on the wire consists of a big-endian `u32` ciphertext length followed by the
+/// ciphertext and its tag. A frame that fails authentication is surfaced as an
+/// [`io::ErrorKind::InvalidData`] error instead of being handed to the reader.
+///
+/// [`Self::finish`] seals a last frame, the highest bit of its length is set. The peer only
+/// reports the end of the stream after that frame, a connection cut between two frames is an
+/// [`io::ErrorKind::UnexpectedEof`] error.
+pub struct AuthenticatedStream<TStream>
+where
+    TStream: Read + Write,
+{
+    /// `None` once the last frame was written.
+    encryptor: Option<EncryptorBE32<XChaCha20Poly1305>>,
+    /// `None` once the last frame was read.
+    decryptor: Option<DecryptorBE32<XChaCha20Poly1305>>,
+    read_buffer: Vec<u8>,
+    read_position: usize,
+    chunk_size: usize,
+    pub raw_stream: TStream,
+}
+
+impl<TStream> AuthenticatedStream<TStream>
+where
+    TStream: Read + Write,
+{
+    pub fn new(session_keys: SessionKeys, stream: TStream) -> Self {
+        let send_cipher = XChaCha20Poly1305::new(&session_keys.send_key.into());
+        let receive_cipher = XChaCha20Poly1305::new(&session_keys.receive_key.into());
+
+        Self {
+            encryptor: Some(EncryptorBE32::from_aead(
+                send_cipher,
+                GenericArray::from_slice(&session_keys.send_nonce),
+            )),
+            decryptor: Some(DecryptorBE32::from_aead(
+                receive_cipher,
+                GenericArray::from_slice(&session_keys.receive_nonce),
+            )),
+            read_buffer: Vec::new(),
+            read_position: 0,
+            chunk_size: AEAD_CHUNK_SIZE,
+            raw_stream: stream,
+        }
+    }
+
+    /// Limits the amount of plaintext sealed into a single frame, e.g. to the chunk size
+    /// negotiated with the peer. Values above [`AEAD_CHUNK_SIZE`] are capped.
+    pub fn set_chunk_size(&mut self, chunk_size: usize) {
+        self.chunk_size = chunk_size.clamp(1, AEAD_CHUNK_SIZE);
+    }
+
+    /// Writes the last frame, nothing can be written afterwards. Does nothing if the stream
+    /// was already finished.
+    pub fn finish(&mut self) -> io::Result<()> {
+        let Some(encryptor) = self.encryptor.take() else {
+            return Ok(());
+        };
+
+        let ciphertext = match encryptor.encrypt_last(&[][..]) {
+            Ok(ciphertext) => ciphertext,
+            Err(error) => return Err(Error::new(Other, error.to_string())),
+        };
+
+        self.write_frame(&ciphertext, LAST_FRAME_FLAG)?;
+
+        return self.raw_stream.flush();
+    }
+
+    fn write_frame(&mut self, ciphertext: &[u8], flags: u32) -> io::Result<()> {
+        let mut frame = Vec::with_capacity(4 + ciphertext.len());
+        frame.extend_from_slice(&(ciphertext.len() as u32 | flags).to_be_bytes());
+        frame.extend_from_slice(ciphertext);
+
+        return self.raw_stream.write_all(&frame);
+    }
+
+    /// Reads and opens the next frame. Returns `false` once the last frame was read.
+    fn read_frame(&mut self) -> io::Result<bool> {
+        if self.decryptor.is_none() {
+            return Ok(false);
+        }
+
+        let mut length_bytes = [0u8; 4];
+        let mut filled = 0;
+
+        while filled < length_bytes.len() {
+            match self.raw_stream.read(&mut length_bytes[filled..])? {
+                0 if filled == 0 => {
+                    return Err(Error::new(
+                        UnexpectedEof,
+                        "Stream ended before its last frame",
+                    ))
+                }
+                0 => {
+                    return Err(Error::new(
+                        UnexpectedEof,
+                        "Stream ended inside a frame header",
+                    ))
+                }
+                read_bytes => filled += read_bytes,
+            }
+        }
+
+        let length_prefix = u32::from_be_bytes(length_bytes);
+        let is_last_frame = length_prefix & LAST_FRAME_FLAG != 0;
+        let length = (length_prefix & !LAST_FRAME_FLAG) as usize;
+
+        if !(AEAD_TAG_SIZE..=AEAD_CHUNK_SIZE + AEAD_TAG_SIZE).contains(&length) {
+            return Err(Error::new(InvalidData, "Invalid encrypted frame length"));
+        }
+
+        let mut ciphertext = vec![0u8; length];
+        self.raw_stream.read_exact(&mut ciphertext)?;
+
+        let plaintext = if is_last_frame {
+            self.decryptor
+                .take()
+                .map(|decryptor| decryptor.decrypt_last(ciphertext.as_slice()))
+        } else {
+            self.decryptor
+                .as_mut()
+                .map(|decryptor| decryptor.decrypt_next(ciphertext.as_slice()))
+        };
+
+        self.read_buffer = match plaintext {
+            Some(Ok(plaintext)) => plaintext,
+            _ => {
+                return Err(Error::new(
+                    InvalidData,
+                    "Encrypted frame failed authentication",
+                ))
+            }
+        };
+        self.read_position = 0;
+
+        return Ok(true);
+    }
+}
+
+impl<TStream> Read for AuthenticatedStream<TStream>
+where
+    TStream: Read + Write,
+{
+    fn read(&mut self, read_buffer: &mut [u8]) -> io::Result<usize> {
+        if read_buffer.is_empty() {
+            return Ok(0);
+        }
+
+        while self.read_position >= self.read_buffer.len() {
+            if !self.read_frame()? {
+                return Ok(0);
+            }
+        }
+
+        let available = &self.read_buffer[self.read_position..];
+        let length = std::cmp::min(available.len(), read_buffer.len());
+        read_buffer[..length].copy_from_slice(&available[..length]);
+        self.read_position += length;
+
+        return Ok(length);
+    }
+}
+
+impl<TStream> Write for AuthenticatedStream<TStream>
+where
+    TStream: Read + Write,
+{
+    fn write(&mut self, write_buffer: &[u8]) -> io::Result<usize> {
+        if write_buffer.is_empty() {
+            return Ok(0);
+        }
+
+        let plaintext = &write_buffer[..std::cmp::min(write_buffer.len(), self.chunk_size)];
+
+        let Some(encryptor) = &mut self.encryptor else {
+            return Err(Error::new(BrokenPipe, "Stream was already finished"));
+        };
+
+        let ciphertext = match encryptor.encrypt_next(plaintext) {
+            Ok(ciphertext) => ciphertext,
+            Err(error) => return Err(Error::new(Other, error.to_string())),
+        };
+
+        self.write_frame(&ciphertext, 0)?;
+
+        return Ok(plaintext.len());
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        return self.raw_stream.flush();
+    }
+}
+
+impl<TStream> Close for AuthenticatedStream<TStream>
+where
+    TStream: Close + Read + Write,
+{
+    fn close(&self) {
+        self.raw_stream.close();
+    }
+}
+
+pub trait EncryptedReadWrite: Read + Write + Send + Close {
+    /// Lets the peer know that nothing more is written, then closes the connection.
+    fn shutdown(&mut self) {
+        self.close();
+    }
+}
+
 impl<TStream> EncryptedReadWrite for EncryptedStream<TStream> where
     TStream: Read + Write + Send + Close
 {
 }
+
+impl<TStream> EncryptedReadWrite for AuthenticatedStream<TStream>
+where
+    TStream: Read + Write + Send + Close,
+{
+    fn shutdown(&mut self) {
+        if let Err(error) = self.finish() {
+            println!("Failed to finish encrypted stream: {:?}", error);
+        }
+
+        self.close();
+    }
+}
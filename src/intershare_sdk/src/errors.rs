@@ -2,6 +2,8 @@ use std::io;
 use std::string::FromUtf8Error;
 use thiserror::Error;
 
+use crate::connection_request::DeclineReason;
+
 #[derive(Error, Debug)]
 pub enum ConnectErrors {
     #[error("Peripheral is unreachable")]
@@ -16,6 +18,10 @@ pub enum ConnectErrors {
     #[error("Peripheral declined the connection")]
     Declined,
 
+    /// The receiver declined without asking its user, e.g. because the files don't fit.
+    #[error("Peripheral declined the connection automatically ({reason:?})")]
+    DeclinedAutomatically { reason: DeclineReason },
+
     #[error("Failed to get TCP connection details")]
     FailedToGetTcpDetails,
 
@@ -42,6 +48,40 @@ pub enum ConnectErrors {
 
     #[error("Failed to get transfer request response: {error}")]
     FailedToGetTransferRequestResponse { error: String },
+
+    #[error("Failed to verify the identity of the peripheral")]
+    FailedToVerifyPeerIdentity,
+
+    #[error("Peripheral identity does not match the requested device")]
+    PeerIdentityMismatch,
+
+    #[error("Incompatible protocol version (local: {local_version}, remote: {remote_version})")]
+    IncompatibleProtocolVersion {
+        local_version: i32,
+        remote_version: i32,
+    },
+
+    #[error("Peripheral does not support this kind of transfer")]
+    UnsupportedIntent,
+
+    #[error("Transfer failed: {error}")]
+    TransferFailed { error: String },
+
+    #[error("Clipboard content exceeds the size limit")]
+    ClipboardTooLarge,
+
+    #[error("Files were corrupted during the transfer: {corrupt_files:?}")]
+    IntegrityCheckFailed { corrupt_files: Vec<String> },
+}
+
+impl ConnectErrors {
+    /// `Declined` if the user of the receiver declined, so that apps matching it keep working.
+    pub(crate) fn declined(reason: DeclineReason) -> Self {
+        return match reason {
+            DeclineReason::User => ConnectErrors::Declined,
+            reason => ConnectErrors::DeclinedAutomatically { reason },
+        };
+    }
 }
 
 #[derive(Error, Debug)]
@@ -64,6 +104,12 @@ pub enum IncomingErrors {
     #[error("Invalid foreign public key")]
     InvalidForeignPublicKey,
 
+    #[error("Invalid identity signature")]
+    InvalidSignature,
+
+    #[error("Handshake failed: {0}")]
+    HandshakeFailed(String),
+
     #[error("Error sending public key")]
     ErrorSendingPublicKey,
 
@@ -83,6 +129,18 @@ pub enum IncomingErrors {
     Rejected,
 }
 
+#[derive(Error, Debug)]
+pub enum ChannelError {
+    #[error("Transfer was cancelled")]
+    Cancelled,
+
+    #[error("Peer reported an error: {message}")]
+    RemoteError { message: String },
+
+    #[error("Unexpected frame of type {frame_type}")]
+    UnexpectedFrame { frame_type: i32 },
+}
+
 #[derive(Error, Debug)]
 pub enum DiscoverySetupError {
     #[error("Unable to setup UDP Discovery")]
@@ -91,3 +149,73 @@ pub enum DiscoverySetupError {
     #[error("Unable to setup MDNS-SD Discovery")]
     UnableToSetupMdns,
 }
+
+#[derive(Error, Debug)]
+pub enum IdentityError {
+    #[error("Failed to access identity key file: {error}")]
+    FailedToAccessKeyFile { error: String },
+
+    #[error("Invalid identity key")]
+    InvalidKey,
+}
+
+#[derive(Error, Debug)]
+pub enum StorageError {
+    #[error("Failed to access storage file: {error}")]
+    FailedToAccessFile { error: String },
+
+    #[error("Storage file is corrupt: {error}")]
+    CorruptFile { error: String },
+}
+
+/// Why received files were rejected instead of being written.
+#[derive(Error, Debug)]
+pub enum ExtractionError {
+    #[error("{path} would be written outside of the destination")]
+    PathOutsideDestination { path: String },
+
+    #[error("{path} would be written through a symbolic link")]
+    SymlinkInPath { path: String },
+
+    #[error("{path} is a symbolic link")]
+    SymlinkEntry { path: String },
+
+    #[error("Received data exceeds the limit of {limit} bytes")]
+    SizeLimitExceeded { limit: u64 },
+
+    #[error("Transfer contains more than {limit} files")]
+    TooManyEntries { limit: u64 },
+
+    #[error("Failed to write received files: {0}")]
+    Io(#[from] io::Error),
+}
+
+impl From<ExtractionError> for io::Error {
+    fn from(error: ExtractionError) -> Self {
+        return match error {
+            ExtractionError::Io(error) => error,
+            error => io::Error::new(io::ErrorKind::InvalidData, error),
+        };
+    }
+}
+
+/// Why a [`crate::transfer_source::TransferSource`] couldn't provide its data.
+#[derive(Error, Debug)]
+pub enum TransferSourceError {
+    #[error("Failed to read the source: {error}")]
+    FailedToRead { error: String },
+}
+
+impl From<io::Error> for TransferSourceError {
+    fn from(error: io::Error) -> Self {
+        return TransferSourceError::FailedToRead {
+            error: error.to_string(),
+        };
+    }
+}
+
+impl From<TransferSourceError> for io::Error {
+    fn from(error: TransferSourceError) -> Self {
+        return io::Error::other(error);
+    }
+}
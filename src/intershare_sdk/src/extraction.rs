@@ -0,0 +1,137 @@
+//! Guards the receiver against what a sender puts into a transfer: paths that escape the
+//! destination, and data expanding far beyond the announced size.
+
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use protocol::communication::FileManifestEntry;
+
+use crate::errors::ExtractionError;
+
+pub const DEFAULT_MAX_SIZE_RATIO: f64 = 2.0;
+pub const DEFAULT_MAX_ENTRIES: u64 = 100_000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ExtractionLimits {
+    /// Received data may be at most this many times the announced size.
+    pub max_size_ratio: f64,
+    pub max_entries: u64,
+}
+
+impl Default for ExtractionLimits {
+    fn default() -> Self {
+        return Self {
+            max_size_ratio: DEFAULT_MAX_SIZE_RATIO,
+            max_entries: DEFAULT_MAX_ENTRIES,
+        };
+    }
+}
+
+impl ExtractionLimits {
+    /// Budget of a transfer announcing `announced_size` bytes.
+    pub fn budget(&self, announced_size: u64) -> ExtractionBudget {
+        return ExtractionBudget {
+            max_size: (announced_size as f64 * self.max_size_ratio) as u64,
+            max_entries: self.max_entries,
+            size: 0,
+            entries: 0,
+        };
+    }
+}
+
+/// Counts what has been received of a transfer, failing once a limit is exceeded.
+#[derive(Clone)]
+pub struct ExtractionBudget {
+    max_size: u64,
+    max_entries: u64,
+    size: u64,
+    entries: u64,
+}
+
+impl ExtractionBudget {
+    pub fn add_entry(&mut self) -> Result<(), ExtractionError> {
+        self.entries += 1;
+
+        if self.entries > self.max_entries {
+            return Err(ExtractionError::TooManyEntries {
+                limit: self.max_entries,
+            });
+        }
+
+        return Ok(());
+    }
+
+    pub fn add_bytes(&mut self, bytes: u64) -> Result<(), ExtractionError> {
+        self.size = self.size.saturating_add(bytes);
+
+        if self.size > self.max_size {
+            return Err(ExtractionError::SizeLimitExceeded {
+                limit: self.max_size,
+            });
+        }
+
+        return Ok(());
+    }
+
+    /// Counts the files of a manifest, for transfers that only write what the manifest lists.
+    pub fn add_manifest(&mut self, files: &[FileManifestEntry]) -> Result<(), ExtractionError> {
+        for file in files {
+            self.add_entry()?;
+            self.add_bytes(file.size)?;
+        }
+
+        return Ok(());
+    }
+}
+
+/// Checks that a path sent by the peer stays inside whatever it's written to: relative, and
+/// without `..` components.
+pub fn validate_relative_path(path: &str) -> Result<PathBuf, ExtractionError> {
+    let mut relative_path = PathBuf::new();
+
+    for component in Path::new(path).components() {
+        match component {
+            Component::Normal(name) => relative_path.push(name),
+            Component::CurDir => continue,
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => {
+                return Err(ExtractionError::PathOutsideDestination {
+                    path: path.to_string(),
+                })
+            }
+        }
+    }
+
+    if relative_path.as_os_str().is_empty() {
+        return Err(ExtractionError::PathOutsideDestination {
+            path: path.to_string(),
+        });
+    }
+
+    return Ok(relative_path);
+}
+
+/// Where a file sent as `path` is written inside `destination`.
+///
+/// Besides rejecting escaping paths, directories or files on the way that are symbolic links are
+/// rejected, so a link placed in the destination earlier can't redirect the file elsewhere.
+pub fn destination_path(destination: &Path, path: &str) -> Result<PathBuf, ExtractionError> {
+    let relative_path = validate_relative_path(path)?;
+    let mut existing_path = destination.to_path_buf();
+
+    for component in relative_path.components() {
+        existing_path.push(component);
+
+        match fs::symlink_metadata(&existing_path) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                return Err(ExtractionError::SymlinkInPath {
+                    path: path.to_string(),
+                })
+            }
+            Ok(_) => continue,
+            // Nothing on disk yet, so nothing further down either.
+            Err(_) => break,
+        }
+    }
+
+    return Ok(destination.join(relative_path));
+}
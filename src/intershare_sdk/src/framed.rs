@@ -0,0 +1,419 @@
+//! Archive-free file transfers.
+//!
+//! Every file is sent as a length-delimited [`FileHeader`] followed by its content: exactly
+//! `size - offset` bytes, or a single zstd frame of them if the header says so. The stream ends
+//! after the last file.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use protocol::communication::{FileHeader, FileManifestEntry};
+use protocol::prost::Message;
+
+use crate::capabilities::Compression;
+use crate::compression::{zstd_level, CompressionPolicy};
+use crate::convert_os_str;
+use crate::errors::ExtractionError;
+use crate::extraction::{destination_path, validate_relative_path, ExtractionBudget};
+use crate::manifest::{hash_prefix, Hashing, ReceivedFiles, SourceFile};
+use crate::transfer_sink::SinkReceiver;
+
+const BUFFER_SIZE: usize = 32 * 1024;
+
+/// Upper limit for an encoded [`FileHeader`] or [`FileChunk`], they only hold a path and sizes.
+///
+/// [`FileChunk`]: protocol::communication::FileChunk
+const MAX_HEADER_SIZE: usize = 64 * 1024;
+
+/// Reads the next length-delimited header, or `None` if the stream ended in between files.
+pub(crate) fn read_header<M, R>(reader: &mut R) -> io::Result<Option<M>>
+where
+    M: Message + Default,
+    R: Read,
+{
+    let mut length_delimiter = vec![];
+    let mut byte = [0u8; 1];
+
+    loop {
+        if reader.read(&mut byte)? == 0 {
+            if length_delimiter.is_empty() {
+                return Ok(None);
+            }
+
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        length_delimiter.push(byte[0]);
+
+        if byte[0] & 0x80 == 0 {
+            break;
+        }
+
+        if length_delimiter.len() >= 10 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Invalid file header length",
+            ));
+        }
+    }
+
+    let length = protocol::prost::decode_length_delimiter(length_delimiter.as_slice())
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+
+    if length > MAX_HEADER_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "File header is too large",
+        ));
+    }
+
+    let mut encoded_header = vec![0u8; length];
+    reader.read_exact(&mut encoded_header)?;
+
+    return M::decode(encoded_header.as_slice())
+        .map(Some)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error));
+}
+
+/// Copies exactly `size` bytes, calling `progress` with the number of bytes copied so far.
+fn copy_exact<R, W, F>(reader: &mut R, writer: &mut W, size: u64, mut progress: F) -> io::Result<()>
+where
+    R: Read,
+    W: Write,
+    F: FnMut(u64),
+{
+    let mut buffer = vec![0u8; BUFFER_SIZE];
+    let mut remaining = size;
+
+    while remaining > 0 {
+        let maximum = std::cmp::min(remaining, BUFFER_SIZE as u64) as usize;
+        let read_size = reader.read(&mut buffer[..maximum])?;
+
+        if read_size == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        writer.write_all(&buffer[..read_size])?;
+        remaining -= read_size as u64;
+        progress(read_size as u64);
+    }
+
+    return Ok(());
+}
+
+/// Writes `size` bytes of `source`, as a single zstd frame if a level is given.
+pub(crate) fn write_content<R, W, F>(
+    source: &mut R,
+    writer: &mut W,
+    size: u64,
+    zstd_level: Option<i32>,
+    progress: F,
+) -> io::Result<()>
+where
+    R: Read,
+    W: Write,
+    F: FnMut(u64),
+{
+    let Some(level) = zstd_level else {
+        return copy_exact(source, writer, size, progress);
+    };
+
+    let mut encoder = zstd::Encoder::new(writer, level)?;
+    copy_exact(source, &mut encoder, size, progress)?;
+    encoder.finish()?;
+
+    return Ok(());
+}
+
+/// Reads content written by [`write_content`]. The reader has to be buffered, so a zstd frame can
+/// end without reading into whatever follows it.
+pub(crate) fn read_content<R, W, F>(
+    reader: &mut R,
+    writer: &mut W,
+    size: u64,
+    compression: i32,
+    progress: F,
+) -> io::Result<()>
+where
+    R: BufRead,
+    W: Write,
+    F: FnMut(u64),
+{
+    return match Compression::try_from(compression) {
+        Ok(Compression::None) => copy_exact(reader, writer, size, progress),
+        Ok(Compression::Zstd) => {
+            let mut decoder = zstd::Decoder::with_buffer(reader)?.single_frame();
+            copy_exact(&mut decoder, writer, size, progress)?;
+
+            // Consumes the end of the frame, which must not hold any further data.
+            if decoder.read(&mut [0u8; 1])? != 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "File is larger than announced",
+                ));
+            }
+
+            Ok(())
+        }
+        _ => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Unsupported compression of framed file",
+        )),
+    };
+}
+
+/// Sends the files one after another, starting each one at its offset in `resume_offsets`.
+/// Compressible files are compressed with zstd, if the peer supports it.
+///
+/// `progress` is called with the number of uncompressed bytes the receiver has so far. Files
+/// that changed their size since they were collected fail the transfer.
+///
+/// Returns the writer and the BLAKE3 hash of every file, computed while the files are read.
+pub fn send_framed_files<W, F>(
+    files: &[SourceFile],
+    mut writer: W,
+    resume_offsets: &[u64],
+    policy: CompressionPolicy,
+    negotiated_compression: Compression,
+    mut progress: F,
+) -> io::Result<(W, Vec<Vec<u8>>)>
+where
+    W: Write,
+    F: FnMut(u64),
+{
+    let mut sent_bytes: u64 = resume_offsets.iter().sum();
+    let mut hashes = vec![];
+
+    for (file_index, file) in files.iter().enumerate() {
+        let offset = resume_offsets.get(file_index).copied().unwrap_or(0);
+
+        let Some(remaining) = file.size.checked_sub(offset) else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Resume offset exceeds the file size",
+            ));
+        };
+
+        let zstd_level = zstd_level(policy, negotiated_compression, &*file.source);
+
+        let header = FileHeader {
+            path: file.relative_path.clone(),
+            size: file.size,
+            offset,
+            compression: match zstd_level {
+                Some(_) => Compression::Zstd as i32,
+                None => Compression::None as i32,
+            },
+        };
+
+        writer.write_all(&header.encode_length_delimited_to_vec())?;
+
+        // The part sent by an earlier attempt isn't read again otherwise.
+        let hasher = match offset {
+            0 => blake3::Hasher::new(),
+            _ => hash_prefix(file.source.open(0)?, offset)?,
+        };
+        let mut source = Hashing::new(file.source.open(offset)?, hasher);
+
+        write_content(&mut source, &mut writer, remaining, zstd_level, |copied| {
+            sent_bytes += copied;
+            progress(sent_bytes);
+        })?;
+
+        hashes.push(source.hash());
+    }
+
+    writer.flush()?;
+
+    return Ok((writer, hashes));
+}
+
+/// Checks that a header announces the file listed at `file_index` of the accepted manifest, so the
+/// sender can't write anything the receiver didn't agree to.
+fn check_header(
+    header: &FileHeader,
+    manifest: &[FileManifestEntry],
+    file_index: usize,
+) -> io::Result<()> {
+    let Some(entry) = manifest.get(file_index) else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "More files were sent than the manifest lists",
+        ));
+    };
+
+    if header.path != entry.path || header.size != entry.size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} does not match the manifest", header.path),
+        ));
+    }
+
+    return Ok(());
+}
+
+/// Checks that the stream didn't end before every file of the manifest arrived.
+fn check_complete(manifest: &[FileManifestEntry], file_index: usize) -> io::Result<()> {
+    if file_index < manifest.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "Stream ended before every file of the manifest arrived",
+        ));
+    }
+
+    return Ok(());
+}
+
+/// Progress of a framed transfer, counting the data of earlier attempts as well.
+pub struct FramedProgress {
+    pub file_index: usize,
+    /// Bytes of the current file that have been written to disk.
+    pub file_bytes: u64,
+    /// Bytes of all files that have been written to disk.
+    pub total_bytes: u64,
+}
+
+/// Writes the received files into `destination`, each one is complete once its last byte arrived.
+///
+/// Files with an offset in `resume_offsets` continue the partial file already on disk. The files
+/// have to be the ones in `manifest`, in the same order, and all of them have to arrive.
+pub fn receive_framed_files<R, F>(
+    reader: R,
+    destination: &str,
+    manifest: &[FileManifestEntry],
+    resume_offsets: &[u64],
+    budget: &mut ExtractionBudget,
+    mut progress: F,
+) -> Result<ReceivedFiles, ExtractionError>
+where
+    R: Read,
+    F: FnMut(&FramedProgress),
+{
+    // Buffered, so a zstd frame can end without reading into the next header.
+    let mut reader = BufReader::with_capacity(BUFFER_SIZE, reader);
+    let mut received_files = ReceivedFiles::default();
+    let mut total_bytes: u64 = resume_offsets.iter().sum();
+    let mut file_index = 0;
+
+    while let Some(header) = read_header::<FileHeader, _>(&mut reader)? {
+        check_header(&header, manifest, file_index)?;
+
+        let offset = resume_offsets.get(file_index).copied().unwrap_or(0);
+
+        if header.offset != offset || header.offset > header.size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "File does not continue where the previous attempt stopped",
+            )
+            .into());
+        }
+
+        budget.add_entry()?;
+        budget.add_bytes(header.size - offset)?;
+
+        let out_path = destination_path(Path::new(destination), &header.path)?;
+
+        if let Some(parent) = out_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let (out_file, hasher) = if offset == 0 {
+            (File::create(&out_path)?, blake3::Hasher::new())
+        } else {
+            let hasher = hash_prefix(File::open(&out_path)?, offset)?;
+            let mut out_file = OpenOptions::new().write(true).open(&out_path)?;
+            out_file.set_len(offset)?;
+            out_file.seek(SeekFrom::End(0))?;
+            (out_file, hasher)
+        };
+        let mut out_file = Hashing::new(out_file, hasher);
+
+        let mut file_bytes = offset;
+        let remaining = header.size - offset;
+
+        let update_progress = |copied| {
+            file_bytes += copied;
+            total_bytes += copied;
+
+            progress(&FramedProgress {
+                file_index,
+                file_bytes,
+                total_bytes,
+            });
+        };
+
+        read_content(
+            &mut reader,
+            &mut out_file,
+            remaining,
+            header.compression,
+            update_progress,
+        )?;
+
+        println!("Received file {:?}", out_path);
+
+        if let Some(path) = convert_os_str(out_path.as_os_str()) {
+            received_files.paths.push(path);
+            received_files.hashes.push(out_file.hash());
+        }
+
+        file_index += 1;
+    }
+
+    check_complete(manifest, file_index)?;
+
+    return Ok(received_files);
+}
+
+/// Hands the received files to a sink, `progress` is called with the bytes received so far.
+pub(crate) fn receive_framed_files_into_sink<R, F>(
+    reader: R,
+    receiver: &mut SinkReceiver,
+    budget: &mut ExtractionBudget,
+    mut progress: F,
+) -> Result<(), ExtractionError>
+where
+    R: Read,
+    F: FnMut(u64),
+{
+    let mut reader = BufReader::with_capacity(BUFFER_SIZE, reader);
+    let mut total_bytes: u64 = 0;
+    let mut file_index = 0;
+
+    while let Some(header) = read_header::<FileHeader, _>(&mut reader)? {
+        check_header(&header, receiver.manifest(), file_index)?;
+
+        if header.offset != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Files written into a sink can't be resumed",
+            )
+            .into());
+        }
+
+        validate_relative_path(&header.path)?;
+        budget.add_entry()?;
+        budget.add_bytes(header.size)?;
+
+        let mut file = receiver.open_file(&header.path, header.size)?;
+
+        read_content(
+            &mut reader,
+            &mut file,
+            header.size,
+            header.compression,
+            |copied| {
+                total_bytes += copied;
+                progress(total_bytes);
+            },
+        )?;
+
+        receiver.finish_file(file)?;
+        file_index += 1;
+    }
+
+    check_complete(receiver.manifest(), file_index)?;
+
+    return Ok(());
+}
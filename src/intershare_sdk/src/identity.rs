@@ -0,0 +1,116 @@
+use std::fs;
+use std::path::Path;
+
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use rand_core::OsRng;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::errors::IdentityError;
+
+/// Long-term Ed25519 key of this device.
+///
+/// The public half is exchanged and signed during every handshake, and the advertised
+/// [`Device`](protocol::discovery::Device) id is derived from it, so a peer can't claim the id of
+/// another device without owning its key.
+pub struct DeviceIdentity {
+    signing_key: SigningKey,
+}
+
+impl DeviceIdentity {
+    pub fn generate() -> Self {
+        Self {
+            signing_key: SigningKey::generate(&mut OsRng),
+        }
+    }
+
+    pub fn from_bytes(secret_key: &[u8]) -> Result<Self, IdentityError> {
+        let secret_key: [u8; 32] = match secret_key.try_into() {
+            Ok(secret_key) => secret_key,
+            Err(_) => return Err(IdentityError::InvalidKey),
+        };
+
+        return Ok(Self {
+            signing_key: SigningKey::from_bytes(&secret_key),
+        });
+    }
+
+    /// Loads the identity stored at `path`, or generates a new one and writes it there.
+    pub fn load_or_create(path: &Path) -> Result<Self, IdentityError> {
+        if path.exists() {
+            return match fs::read(path) {
+                Ok(secret_key) => Self::from_bytes(&secret_key),
+                Err(error) => Err(IdentityError::FailedToAccessKeyFile {
+                    error: error.to_string(),
+                }),
+            };
+        }
+
+        let identity = Self::generate();
+
+        if let Err(error) = identity.write_to(path) {
+            return Err(IdentityError::FailedToAccessKeyFile {
+                error: error.to_string(),
+            });
+        }
+
+        return Ok(identity);
+    }
+
+    fn write_to(&self, path: &Path) -> std::io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        fs::write(path, self.signing_key.to_bytes())?;
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+        }
+
+        return Ok(());
+    }
+
+    pub fn to_bytes(&self) -> [u8; 32] {
+        return self.signing_key.to_bytes();
+    }
+
+    pub fn public_key(&self) -> [u8; 32] {
+        return self.signing_key.verifying_key().to_bytes();
+    }
+
+    pub fn device_id(&self) -> String {
+        return derive_device_id(&self.public_key());
+    }
+
+    pub fn sign(&self, message: &[u8]) -> Vec<u8> {
+        return self.signing_key.sign(message).to_bytes().to_vec();
+    }
+}
+
+/// Derives the device id belonging to an identity public key, formatted as a UUID.
+pub fn derive_device_id(public_key: &[u8]) -> String {
+    let digest = Sha256::digest(public_key);
+    let mut id = [0u8; 16];
+    id.copy_from_slice(&digest[..16]);
+
+    return Uuid::from_bytes(id).to_string();
+}
+
+pub fn verify_signature(public_key: &[u8], message: &[u8], signature: &[u8]) -> bool {
+    let Ok(public_key) = <[u8; 32]>::try_from(public_key) else {
+        return false;
+    };
+
+    let Ok(verifying_key) = VerifyingKey::from_bytes(&public_key) else {
+        return false;
+    };
+
+    let Ok(signature) = Signature::from_slice(signature) else {
+        return false;
+    };
+
+    return verifying_key.verify_strict(message, &signature).is_ok();
+}
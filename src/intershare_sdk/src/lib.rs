@@ -10,14 +10,33 @@ pub use protocol::communication::ClipboardTransferIntent;
 pub use protocol::discovery::Device;
 pub use protocol::DiscoveryDelegate;
 
+pub mod capabilities;
+pub mod channel;
+pub mod checkpoint;
+pub mod clipboard;
 pub mod communication;
+pub mod compression;
 pub mod connection_request;
 pub mod discovery;
 pub mod encryption;
 pub mod errors;
+pub mod extraction;
+pub mod framed;
+pub mod identity;
+pub mod manifest;
 pub mod nearby;
+pub mod parallel;
+pub mod progress;
+pub mod rate_limit;
+pub mod storage;
+pub mod storage_quota;
 pub mod stream;
+pub mod transfer_history;
+pub mod transfer_queue;
+pub mod transfer_sink;
+pub mod transfer_source;
 pub mod transmission;
+pub mod trust_store;
 mod zip;
 
 pub const BLE_SERVICE_UUID: &str = "68D60EB2-8AAA-4D72-8851-BD6D64E169B7";
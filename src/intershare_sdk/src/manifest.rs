@@ -0,0 +1,281 @@
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+use protocol::communication::{FileManifestEntry, TransferResult};
+
+use crate::convert_os_str;
+use crate::errors::ExtractionError;
+use crate::extraction::destination_path;
+use crate::transfer_source::{FileSource, TransferSource};
+
+/// A file picked for sending, with the path it gets inside the transfer.
+#[derive(Clone, Debug)]
+pub struct SourceFile {
+    pub source: Arc<dyn TransferSource>,
+    /// Path relative to the transfer root, separated by `/`. Selected directories keep their name
+    /// as the first component.
+    pub relative_path: String,
+    pub size: u64,
+    pub modified: u64,
+}
+
+impl SourceFile {
+    fn new(path: PathBuf, relative_path: String, metadata: &fs::Metadata) -> Self {
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0);
+
+        Self {
+            source: Arc::new(FileSource::new(path)),
+            relative_path,
+            size: metadata.len(),
+            modified,
+        }
+    }
+
+    /// File that isn't read from a path, named as the source says.
+    pub fn from_source(source: Arc<dyn TransferSource>) -> io::Result<Self> {
+        return Ok(Self {
+            relative_path: source.get_name(),
+            size: source.get_size()?,
+            modified: 0,
+            source,
+        });
+    }
+
+    pub fn manifest_entry(&self) -> FileManifestEntry {
+        return FileManifestEntry {
+            path: self.relative_path.clone(),
+            size: self.size,
+            mime_type: guess_mime_type(&self.relative_path),
+            modified: self.modified,
+        };
+    }
+}
+
+pub fn guess_mime_type(path: &str) -> String {
+    return mime_guess::from_path(path)
+        .first_or_octet_stream()
+        .essence_str()
+        .to_string();
+}
+
+/// Collects all regular files of the selected paths, walking directories recursively.
+///
+/// Symlinked directories are skipped, so cycles can't make the walk run forever.
+pub fn collect_files(file_paths: &[String]) -> io::Result<Vec<SourceFile>> {
+    let mut files = vec![];
+
+    for file_path in file_paths {
+        let path = Path::new(file_path);
+
+        let Some(name) = path.file_name().and_then(convert_os_str) else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Path has no file name: {:?}", path),
+            ));
+        };
+
+        let metadata = fs::metadata(path)?;
+
+        if metadata.is_dir() {
+            collect_directory(path, name, &mut files)?;
+        } else {
+            files.push(SourceFile::new(path.to_path_buf(), name, &metadata));
+        }
+    }
+
+    return Ok(files);
+}
+
+fn collect_directory(
+    directory: &Path,
+    relative_path: String,
+    files: &mut Vec<SourceFile>,
+) -> io::Result<()> {
+    let mut entries = fs::read_dir(directory)?.collect::<io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let Some(name) = convert_os_str(&entry.file_name()) else {
+            println!("Skipping file with a non UTF-8 name: {:?}", entry.path());
+            continue;
+        };
+
+        let path = entry.path();
+        let entry_relative_path = format!("{}/{}", relative_path, name);
+
+        if entry.file_type()?.is_symlink() && path.is_dir() {
+            println!("Skipping symlinked directory: {:?}", path);
+            continue;
+        }
+
+        let metadata = fs::metadata(&path)?;
+
+        if metadata.is_dir() {
+            collect_directory(&path, entry_relative_path, files)?;
+        } else {
+            files.push(SourceFile::new(path, entry_relative_path, &metadata));
+        }
+    }
+
+    return Ok(());
+}
+
+pub fn create_manifest(files: &[SourceFile]) -> Vec<FileManifestEntry> {
+    return files.iter().map(SourceFile::manifest_entry).collect();
+}
+
+pub fn hash_file(path: &Path) -> io::Result<Vec<u8>> {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update_reader(fs::File::open(path)?)?;
+
+    return Ok(hasher.finalize().as_bytes().to_vec());
+}
+
+/// Hashes the content of every file. Transfers that read the files in order hash them on the
+/// way instead, see [`Hashing`].
+pub fn hash_files(files: &[SourceFile]) -> io::Result<Vec<Vec<u8>>> {
+    let mut hashes = vec![];
+
+    for file in files {
+        let hasher = hash_prefix(file.source.open(0)?, file.size)?;
+        hashes.push(hasher.finalize().as_bytes().to_vec());
+    }
+
+    return Ok(hashes);
+}
+
+/// Hashes the first `length` bytes of `reader`, e.g. the part of a file an earlier attempt
+/// already transferred.
+pub fn hash_prefix<R: Read>(reader: R, length: u64) -> io::Result<blake3::Hasher> {
+    let mut hasher = blake3::Hasher::new();
+    let hashed_bytes = io::copy(&mut reader.take(length), &mut hasher)?;
+
+    if hashed_bytes != length {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "File is smaller than announced",
+        ));
+    }
+
+    return Ok(hasher);
+}
+
+/// Reader or writer hashing the data that passes through it.
+pub struct Hashing<T> {
+    inner: T,
+    hasher: blake3::Hasher,
+}
+
+impl<T> Hashing<T> {
+    /// Continues `hasher`, which already hashed whatever came before the data of `inner`.
+    pub fn new(inner: T, hasher: blake3::Hasher) -> Self {
+        return Self { inner, hasher };
+    }
+
+    pub fn hash(&self) -> Vec<u8> {
+        return self.hasher.finalize().as_bytes().to_vec();
+    }
+}
+
+impl<T: Read> Read for Hashing<T> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let read_size = self.inner.read(buffer)?;
+        self.hasher.update(&buffer[..read_size]);
+
+        return Ok(read_size);
+    }
+}
+
+impl<T: Write> Write for Hashing<T> {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buffer)?;
+        self.hasher.update(&buffer[..written]);
+
+        return Ok(written);
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        return self.inner.flush();
+    }
+}
+
+/// Files a receiver wrote to disk.
+#[derive(Debug, Default)]
+pub struct ReceivedFiles {
+    pub paths: Vec<String>,
+    /// BLAKE3 hashes of the files in `paths`, if they were computed while writing. Files without
+    /// one are hashed from disk when they are verified.
+    pub hashes: Vec<Vec<u8>>,
+}
+
+/// Checks the files this transfer wrote to `destination` against the hashes the sender sent along.
+/// Files that don't match are deleted.
+///
+/// The manifest comes from the sender, so nothing is touched if one of its paths would lead
+/// outside of `destination`. Entries of the manifest that weren't written by this transfer are
+/// reported as corrupt, whatever is at their path stays as it is.
+pub fn verify_files(
+    destination: &str,
+    manifest: &[FileManifestEntry],
+    expected_hashes: &[Vec<u8>],
+    received_files: &ReceivedFiles,
+) -> Result<TransferResult, ExtractionError> {
+    let paths = manifest
+        .iter()
+        .map(|entry| destination_path(Path::new(destination), &entry.path))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut result = TransferResult {
+        verified: !manifest.is_empty(),
+        corrupt_files: vec![],
+    };
+
+    for (index, (entry, path)) in manifest.iter().zip(paths).enumerate() {
+        let received_index = convert_os_str(path.as_os_str()).and_then(|path| {
+            received_files
+                .paths
+                .iter()
+                .position(|received_path| *received_path == path)
+        });
+
+        let Some(received_index) = received_index else {
+            println!("File was not received: {:?}", path);
+            result.verified = false;
+            result.corrupt_files.push(entry.path.clone());
+            continue;
+        };
+
+        let Some(expected_hash) = expected_hashes.get(index) else {
+            result.verified = false;
+            continue;
+        };
+
+        let hash = match received_files.hashes.get(received_index) {
+            Some(hash) => Ok(hash.clone()),
+            None => hash_file(&path),
+        };
+
+        match hash {
+            Ok(hash) if hash == *expected_hash => continue,
+            Ok(_) => println!("File does not match its hash: {:?}", path),
+            Err(error) => println!("Failed to verify {:?}: {:?}", path, error),
+        }
+
+        if let Err(error) = fs::remove_file(&path) {
+            println!("Failed to delete corrupt file {:?}: {:?}", path, error);
+        }
+
+        result.verified = false;
+        result.corrupt_files.push(entry.path.clone());
+    }
+
+    return Ok(result);
+}
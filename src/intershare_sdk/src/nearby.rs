@@ -1,35 +1,92 @@
 use std::collections::HashMap;
 use std::fmt::Debug;
-use std::fs::File;
-use std::io::{Read, Write};
-use std::net::ToSocketAddrs;
-use std::path::{Path, PathBuf};
+use std::io::{self, BufWriter, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::{fs, thread};
+use std::thread;
+use std::time::Duration;
 
 use local_ip_address::local_ip;
-use prost_stream::Stream;
+use protocol::communication::message_header::MessageTypes;
 use protocol::communication::transfer_request::Intent;
-use protocol::communication::{FileTransferIntent, TransferRequest, TransferRequestResponse};
+use protocol::communication::transfer_request_response;
+use protocol::communication::{
+    FileManifestEntry, FileTransferIntent, Frame, JoinTransferRequest, TransferRequest,
+    TransferRequestResponse, TransferResult, TransferTrailer,
+};
 use protocol::discovery::{
     BluetoothLeConnectionInfo, Device, DeviceConnectionInfo, TcpConnectionInfo,
 };
-use tempfile::NamedTempFile;
+use protocol::prost::Message;
 use tokio::sync::oneshot::{self, Sender};
 use tokio::sync::RwLock;
 use uuid::Uuid;
-use zip::write::SimpleFileOptions;
-use zip::ZipWriter;
 
-use crate::communication::{initiate_receiver_communication, initiate_sender_communication};
-use crate::connection_request::ConnectionRequest;
+use crate::capabilities::{self, NegotiatedCapabilities};
+use crate::channel::{
+    decode_payload, is_cancellation, is_connection_loss, Channel, DataWriter, TransferControl,
+    CONTROL_STREAM_ID, DATA_FRAME_SIZE, TRANSFER_STREAM_ID,
+};
+use crate::clipboard::{
+    create_clipboard_intent, is_valid_clipboard_intent, ClipboardRepresentation,
+};
+use crate::communication::{
+    initiate_receiver_communication, initiate_sender_communication, Session,
+};
+use crate::compression::CompressionPolicy;
+use crate::connection_request::{ConnectionRequest, DeclineReason};
 use crate::discovery::Discovery;
-use crate::encryption::{EncryptedReadWrite, EncryptedStream};
-use crate::errors::ConnectErrors;
-use crate::stream::NativeStreamDelegate;
+use crate::encryption::EncryptedReadWrite;
+use crate::errors::StorageError;
+use crate::errors::{ConnectErrors, IdentityError, IncomingErrors};
+use crate::extraction::ExtractionLimits;
+use crate::framed::send_framed_files;
+use crate::identity::{derive_device_id, DeviceIdentity};
+use crate::manifest::{collect_files, create_manifest, hash_files, SourceFile};
+use crate::parallel::{
+    ParallelSender, ParallelTransfers, CHUNK_SIZE, DEFAULT_PARALLEL_CONNECTIONS,
+    MAX_PARALLEL_CONNECTIONS,
+};
+use crate::progress::{ProgressTracker, TransferProgress, DEFAULT_PROGRESS_INTERVAL};
+use crate::rate_limit::{RateLimited, RateLimits, TransferRateLimit};
+use crate::storage_quota::StorageQuotas;
+use crate::stream::{Close, NativeStreamDelegate};
+use crate::transfer_history::{
+    record_transfer, PendingRecord, TransferDirection, TransferHistory, TransferHistoryQuery,
+    TransferOutcome, TransferRecord,
+};
+use crate::transfer_queue::{
+    notify_queue_changed, QueuedTransfer, TransferPriority, TransferQueue, TransferQueueDelegate,
+};
+use crate::transfer_sink::TransferSink;
+use crate::transfer_source::TransferSource;
 use crate::transmission::tcp::{TcpClient, TcpServer};
+use crate::trust_store::{TrustStore, TrustedDevice};
+use crate::zip::zip_files;
 use crate::{convert_os_str, init_logger};
 
+/// How often a file transfer is resumed after losing the connection before giving up.
+const MAX_RESUME_ATTEMPTS: u32 = 3;
+
+/// Time for the receiver to notice the connection loss before reconnecting.
+const RESUME_DELAY: Duration = Duration::from_secs(1);
+
+/// Waits for [`RESUME_DELAY`] without blocking the executor. Queued and multi-recipient sessions
+/// run through `futures::executor::block_on` on threads of their own, where there is no tokio
+/// timer, so the delay is kept on a thread as well.
+async fn wait_before_resuming() {
+    let (sender, receiver) = oneshot::channel();
+
+    thread::spawn(move || {
+        thread::sleep(RESUME_DELAY);
+        let _ = sender.send(());
+    });
+
+    let _ = receiver.await;
+}
+
 pub trait BleServerImplementationDelegate: Send + Sync + Debug {
     fn start_server(&self);
     fn stop_server(&self);
@@ -44,6 +101,7 @@ pub enum ConnectionIntentType {
     Clipboard,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum ConnectionMedium {
     BLE,
     WiFi,
@@ -53,18 +111,130 @@ pub enum SendProgressState {
     Unknown,
     Connecting,
     Requesting,
-    ConnectionMediumUpdate { medium: ConnectionMedium },
+    ConnectionMediumUpdate {
+        medium: ConnectionMedium,
+    },
+    /// Short authentication string the user can compare with the one shown on the receiver.
+    VerificationCode {
+        code: String,
+    },
     Compressing,
-    Transferring { progress: f64 },
+    Transferring {
+        progress: TransferProgress,
+    },
     Cancelled,
+    /// The receiver checked every file against its hash, `Finished` follows.
+    Verified,
     Finished,
     Declined,
+    /// The receiver declined without asking its user, see [`DeclineReason`].
+    DeclinedAutomatically {
+        reason: DeclineReason,
+    },
+}
+
+impl SendProgressState {
+    /// `Declined` if the user of the receiver declined, so that apps matching it keep working.
+    fn declined(reason: DeclineReason) -> Self {
+        return match reason {
+            DeclineReason::User => SendProgressState::Declined,
+            reason => SendProgressState::DeclinedAutomatically { reason },
+        };
+    }
 }
 
 pub trait SendProgressDelegate: Send + Sync + Debug {
     fn progress_changed(&self, progress: SendProgressState);
 }
 
+/// Progress of a transfer to several receivers, reported for each receiver on its own.
+pub trait RecipientProgressDelegate: Send + Sync + Debug {
+    fn progress_changed(&self, device_id: String, progress: SendProgressState);
+}
+
+#[derive(Debug)]
+struct RecipientProgress {
+    device_id: String,
+    delegate: Arc<dyn RecipientProgressDelegate>,
+}
+
+impl SendProgressDelegate for RecipientProgress {
+    fn progress_changed(&self, progress: SendProgressState) {
+        self.delegate
+            .progress_changed(self.device_id.clone(), progress);
+    }
+}
+
+/// What the transfer history needs to know about an outgoing transfer, taken from its progress.
+#[derive(Debug, Default)]
+struct SendObservation {
+    medium: std::sync::Mutex<Option<ConnectionMedium>>,
+    cancelled: AtomicBool,
+}
+
+#[derive(Debug)]
+struct ObservedProgress {
+    delegate: Option<Box<dyn SendProgressDelegate>>,
+    observation: Arc<SendObservation>,
+}
+
+impl SendProgressDelegate for ObservedProgress {
+    fn progress_changed(&self, progress: SendProgressState) {
+        match &progress {
+            SendProgressState::ConnectionMediumUpdate { medium } => {
+                *self
+                    .observation
+                    .medium
+                    .lock()
+                    .expect("Failed to lock medium") = Some(*medium);
+            }
+            SendProgressState::Cancelled => {
+                self.observation.cancelled.store(true, Ordering::Relaxed);
+            }
+            _ => {}
+        }
+
+        if let Some(delegate) = &self.delegate {
+            delegate.progress_changed(progress);
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecipientOutcome {
+    Finished,
+    Declined,
+    Failed,
+}
+
+/// How sending to one of several receivers ended.
+#[derive(Clone, Debug)]
+pub struct RecipientResult {
+    pub device_id: String,
+    pub outcome: RecipientOutcome,
+    /// Why sending `Failed`, or why the receiver declined if it wasn't the user.
+    pub error: Option<String>,
+}
+
+impl RecipientResult {
+    fn new(device_id: String, result: Result<(), ConnectErrors>) -> Self {
+        let (outcome, error) = match result {
+            Ok(()) => (RecipientOutcome::Finished, None),
+            Err(ConnectErrors::Declined) => (RecipientOutcome::Declined, None),
+            Err(error @ ConnectErrors::DeclinedAutomatically { .. }) => {
+                (RecipientOutcome::Declined, Some(error.to_string()))
+            }
+            Err(error) => (RecipientOutcome::Failed, Some(error.to_string())),
+        };
+
+        return Self {
+            device_id,
+            outcome,
+            error,
+        };
+    }
+}
+
 pub trait NearbyConnectionDelegate: Send + Sync + Debug {
     fn received_connection_request(&self, request: Arc<ConnectionRequest>);
 }
@@ -78,29 +248,88 @@ pub struct NearbyServerLockedVariables {
     pub advertise: bool,
     file_storage: String,
     l2cap_connections: HashMap<String, Sender<Box<dyn NativeStreamDelegate>>>,
+    identity: Arc<DeviceIdentity>,
+    trust_store: Arc<std::sync::Mutex<TrustStore>>,
+    auto_accept_trusted: bool,
+    compression_policy: CompressionPolicy,
+    max_parallel_connections: u32,
+    parallel_transfers: ParallelTransfers,
+    rate_limits: RateLimits,
+    transfer_queue: Arc<std::sync::Mutex<TransferQueue>>,
+    transfer_sink: Option<Arc<dyn TransferSink>>,
+    extraction_limits: ExtractionLimits,
+    storage_quotas: Arc<std::sync::Mutex<StorageQuotas>>,
+    progress_interval: Duration,
+    transfer_history: Arc<std::sync::Mutex<Option<TransferHistory>>>,
 }
 
 pub struct NearbyServer {
     pub variables: Arc<RwLock<NearbyServerLockedVariables>>,
 }
 
+/// Files collected for sending, together with what's announced about them.
+struct PreparedFiles {
+    files: Vec<SourceFile>,
+    file_size: u64,
+    file_name: Option<String>,
+    manifest: Vec<FileManifestEntry>,
+}
+
+/// Files of an outgoing transfer and how to send them, the same across reconnections.
+struct OutgoingFiles<'a> {
+    files: &'a [SourceFile],
+    file_size: u64,
+    compression_policy: CompressionPolicy,
+    rate_limit: &'a TransferRateLimit,
+    progress_interval: Duration,
+}
+
+impl OutgoingFiles<'_> {
+    fn progress_tracker(&self) -> ProgressTracker {
+        let files = self
+            .files
+            .iter()
+            .map(|file| (file.relative_path.clone(), file.size))
+            .collect();
+
+        return ProgressTracker::new(files, self.file_size, self.progress_interval);
+    }
+}
+
+/// Encrypted connection to a receiver, together with the features negotiated for it.
+struct OutgoingConnection {
+    stream: Box<dyn EncryptedReadWrite>,
+    capabilities: NegotiatedCapabilities,
+    medium: ConnectionMedium,
+}
+
 impl NearbyServer {
+    /// Creates the server with the persistent identity key stored at `identity_key_path`,
+    /// generating and storing a new one on first use.
+    ///
+    /// The id of `my_device` is replaced by the one derived from the identity key, so it stays
+    /// the same across restarts as long as the key file is kept.
     pub fn new(
         my_device: Device,
         file_storage: String,
+        identity_key_path: String,
         delegate: Option<Box<dyn NearbyConnectionDelegate>>,
-    ) -> Self {
+    ) -> Result<Self, IdentityError> {
         init_logger();
 
+        let identity = DeviceIdentity::load_or_create(Path::new(&identity_key_path))?;
+        let mut my_device = my_device;
+        my_device.id = identity.device_id();
+
         let device_connection_info = DeviceConnectionInfo {
-            device: Some(my_device.clone()),
+            device: Some(my_device),
             ble: None,
             tcp: None,
         };
 
         let nearby_connection_delegate = delegate.map(|d| Arc::new(std::sync::Mutex::new(d)));
 
-        return Self {
+        return Ok(Self {
             variables: Arc::new(RwLock::new(NearbyServerLockedVariables {
                 device_connection_info,
                 tcp_server: None,
@@ -110,8 +339,21 @@ impl NearbyServer {
                 advertise: false,
                 file_storage,
                 l2cap_connections: HashMap::new(),
+                identity: Arc::new(identity),
+                trust_store: Arc::new(std::sync::Mutex::new(TrustStore::in_memory())),
+                auto_accept_trusted: false,
+                compression_policy: CompressionPolicy::default(),
+                max_parallel_connections: DEFAULT_PARALLEL_CONNECTIONS,
+                parallel_transfers: ParallelTransfers::default(),
+                rate_limits: RateLimits::default(),
+                transfer_queue: Arc::new(std::sync::Mutex::new(TransferQueue::default())),
+                transfer_sink: None,
+                extraction_limits: ExtractionLimits::default(),
+                storage_quotas: Arc::new(std::sync::Mutex::new(StorageQuotas::default())),
+                progress_interval: DEFAULT_PROGRESS_INTERVAL,
+                transfer_history: Arc::new(std::sync::Mutex::new(None)),
             })),
-        };
+        });
     }
 
     pub fn add_l2_cap_client(&self, delegate: Box<dyn L2CapDelegate>) {
@@ -125,11 +367,344 @@ impl NearbyServer {
         self.variables.blocking_write().ble_server_implementation = Some(implementation)
     }
 
+    /// Replaces the advertised device. The id is always derived from the identity key.
     pub fn change_device(&self, new_device: Device) {
+        let mut variables = self.variables.blocking_write();
+        let mut new_device = new_device;
+        new_device.id = variables.identity.device_id();
+
+        variables.device_connection_info.device = Some(new_device);
+    }
+
+    pub fn get_device_id(&self) -> String {
+        return self.variables.blocking_read().identity.device_id();
+    }
+
+    /// Loads the trust store persisted at `path`. It replaces the store in memory, so devices pinned
+    /// before are dropped.
+    pub fn load_trust_store(&self, path: String) -> Result<(), StorageError> {
+        let trust_store = TrustStore::load(Path::new(&path))?;
+
+        *self
+            .variables
+            .blocking_read()
+            .trust_store
+            .lock()
+            .expect("Failed to lock trust store") = trust_store;
+
+        return Ok(());
+    }
+
+    pub fn get_trusted_devices(&self) -> Vec<TrustedDevice> {
+        return self
+            .variables
+            .blocking_read()
+            .trust_store
+            .lock()
+            .expect("Failed to lock trust store")
+            .get_devices();
+    }
+
+    pub fn revoke_device(&self, device_id: String) -> Result<bool, StorageError> {
+        return self
+            .variables
+            .blocking_read()
+            .trust_store
+            .lock()
+            .expect("Failed to lock trust store")
+            .revoke(&device_id);
+    }
+
+    pub fn forget_device(&self, device_id: String) -> Result<(), StorageError> {
+        return self
+            .variables
+            .blocking_read()
+            .trust_store
+            .lock()
+            .expect("Failed to lock trust store")
+            .forget(&device_id);
+    }
+
+    /// Accept incoming transfers from trusted devices without waiting for the user.
+    ///
+    /// The delegate is still informed about such requests (see
+    /// [`ConnectionRequest::is_auto_accepted`]) to be able to attach a progress delegate.
+    pub fn set_auto_accept_trusted(&self, enabled: bool) {
+        self.variables.blocking_write().auto_accept_trusted = enabled;
+    }
+
+    /// Applies to transfers started afterwards. Already compressed files are never compressed.
+    pub fn set_compression_policy(&self, policy: CompressionPolicy) {
+        self.variables.blocking_write().compression_policy = policy;
+    }
+
+    /// Most connections a file transfer over WiFi is spread over, in either direction. The peer's
+    /// limit applies as well, `1` sends everything over a single connection.
+    pub fn set_max_parallel_connections(&self, connections: u32) {
+        self.variables.blocking_write().max_parallel_connections =
+            connections.clamp(1, MAX_PARALLEL_CONNECTIONS);
+    }
+
+    /// Caps the bandwidth of all incoming and outgoing transfers together, in bytes per second.
+    /// `0` removes the cap. Running transfers are slowed down or sped up right away.
+    pub fn set_rate_limit(&self, bytes_per_second: u64) {
         self.variables
-            .blocking_write()
-            .device_connection_info
-            .device = Some(new_device);
+            .blocking_read()
+            .rate_limits
+            .set_global_limit(bytes_per_second);
+    }
+
+    /// Caps the bandwidth of every single transfer, in bytes per second. `0` removes the cap.
+    /// Running transfers are slowed down or sped up right away.
+    pub fn set_transfer_rate_limit(&self, bytes_per_second: u64) {
+        self.variables
+            .blocking_read()
+            .rate_limits
+            .set_transfer_limit(bytes_per_second);
+    }
+
+    /// Limits what an incoming transfer may write: at most `max_size_ratio` times the size the
+    /// sender announced, and at most `max_entries` files. Transfers exceeding them fail.
+    pub fn set_extraction_limits(&self, max_size_ratio: f64, max_entries: u64) {
+        self.variables.blocking_write().extraction_limits = ExtractionLimits {
+            max_size_ratio,
+            max_entries,
+        };
+    }
+
+    /// Loads the transfer history persisted at `path`, and records every file transfer from now
+    /// on. Without it, no history is kept.
+    pub fn load_transfer_history(&self, path: String) -> Result<(), StorageError> {
+        let transfer_history = TransferHistory::load(Path::new(&path))?;
+
+        *self
+            .variables
+            .blocking_read()
+            .transfer_history
+            .lock()
+            .expect("Failed to lock transfer history") = Some(transfer_history);
+
+        return Ok(());
+    }
+
+    /// Recorded transfers matching `query`, newest first.
+    pub fn get_transfer_history(&self, query: TransferHistoryQuery) -> Vec<TransferRecord> {
+        return self
+            .variables
+            .blocking_read()
+            .transfer_history
+            .lock()
+            .expect("Failed to lock transfer history")
+            .as_ref()
+            .map(|transfer_history| transfer_history.query(&query))
+            .unwrap_or_default();
+    }
+
+    pub fn delete_transfer_record(&self, id: String) -> Result<bool, StorageError> {
+        let variables = self.variables.blocking_read();
+        let mut transfer_history = variables
+            .transfer_history
+            .lock()
+            .expect("Failed to lock transfer history");
+
+        return match transfer_history.as_mut() {
+            Some(transfer_history) => transfer_history.delete(&id),
+            None => Ok(false),
+        };
+    }
+
+    pub fn clear_transfer_history(&self) -> Result<(), StorageError> {
+        let variables = self.variables.blocking_read();
+        let mut transfer_history = variables
+            .transfer_history
+            .lock()
+            .expect("Failed to lock transfer history");
+
+        return match transfer_history.as_mut() {
+            Some(transfer_history) => transfer_history.clear(),
+            None => Ok(()),
+        };
+    }
+
+    /// Reports the progress of transfers at most once every `milliseconds`, `0` reports every
+    /// change.
+    pub fn set_progress_interval(&self, milliseconds: u64) {
+        self.variables.blocking_write().progress_interval = Duration::from_millis(milliseconds);
+    }
+
+    /// Limits the space all files in the file storage may take up together, `0` for no limit.
+    /// Transfers that don't fit are declined, like transfers exceeding the free space.
+    pub fn set_storage_quota(&self, bytes: u64) {
+        self.variables
+            .blocking_read()
+            .storage_quotas
+            .lock()
+            .expect("Failed to lock storage quotas")
+            .set_storage_quota(bytes);
+    }
+
+    /// Limits how many bytes a single sender may transfer, `0` for no limit. Usage is counted
+    /// while the server runs, [`Self::reset_sender_usage`] gives a sender its quota back.
+    pub fn set_sender_quota(&self, bytes: u64) {
+        self.variables
+            .blocking_read()
+            .storage_quotas
+            .lock()
+            .expect("Failed to lock storage quotas")
+            .set_sender_quota(bytes);
+    }
+
+    pub fn get_sender_usage(&self, device_id: String) -> u64 {
+        return self
+            .variables
+            .blocking_read()
+            .storage_quotas
+            .lock()
+            .expect("Failed to lock storage quotas")
+            .sender_usage(&device_id);
+    }
+
+    pub fn reset_sender_usage(&self, device_id: String) {
+        self.variables
+            .blocking_read()
+            .storage_quotas
+            .lock()
+            .expect("Failed to lock storage quotas")
+            .reset_sender_usage(&device_id);
+    }
+
+    /// Hands received files to `sink` instead of writing them into the file storage. `None`
+    /// restores the file storage, which is also the only place transfers can be resumed in and
+    /// received over parallel connections.
+    pub fn set_transfer_sink(&self, sink: Option<Box<dyn TransferSink>>) {
+        self.variables.blocking_write().transfer_sink = sink.map(Arc::from);
+    }
+
+    /// Queues files to be sent to `receiver` and returns the id of the queued transfer.
+    ///
+    /// Unlike [`NearbyServer::send_files`], the transfer waits until the limits set with
+    /// [`NearbyServer::set_max_active_transfers`] and
+    /// [`NearbyServer::set_max_active_transfers_per_device`] allow it to start.
+    pub fn enqueue_files(
+        &self,
+        receiver: Device,
+        file_paths: Vec<String>,
+        priority: TransferPriority,
+        progress_delegate: Option<Box<dyn SendProgressDelegate>>,
+    ) -> String {
+        let id = self
+            .transfer_queue()
+            .lock()
+            .expect("Failed to lock transfer queue")
+            .enqueue(receiver, file_paths, priority, progress_delegate);
+
+        self.run_transfer_queue();
+
+        return id;
+    }
+
+    pub fn get_transfer_queue(&self) -> Vec<QueuedTransfer> {
+        return self
+            .transfer_queue()
+            .lock()
+            .expect("Failed to lock transfer queue")
+            .transfers();
+    }
+
+    /// Removes a queued or finished transfer. Active transfers can't be removed.
+    pub fn remove_queued_transfer(&self, id: String) -> bool {
+        let transfer_queue = self.transfer_queue();
+        let removed = transfer_queue
+            .lock()
+            .expect("Failed to lock transfer queue")
+            .remove(&id);
+
+        if removed {
+            notify_queue_changed(&transfer_queue);
+        }
+
+        return removed;
+    }
+
+    /// Removes all transfers that are done or failed from the queue.
+    pub fn clear_finished_transfers(&self) {
+        let transfer_queue = self.transfer_queue();
+
+        transfer_queue
+            .lock()
+            .expect("Failed to lock transfer queue")
+            .clear_finished();
+
+        notify_queue_changed(&transfer_queue);
+    }
+
+    pub fn set_transfer_queue_delegate(&self, delegate: Option<Box<dyn TransferQueueDelegate>>) {
+        self.transfer_queue()
+            .lock()
+            .expect("Failed to lock transfer queue")
+            .set_delegate(delegate);
+    }
+
+    /// Most queued transfers that are sent at the same time, at least `1`.
+    pub fn set_max_active_transfers(&self, transfers: u32) {
+        self.transfer_queue()
+            .lock()
+            .expect("Failed to lock transfer queue")
+            .set_max_active(transfers);
+
+        self.run_transfer_queue();
+    }
+
+    /// Most queued transfers that are sent to the same device at the same time, at least `1`.
+    pub fn set_max_active_transfers_per_device(&self, transfers: u32) {
+        self.transfer_queue()
+            .lock()
+            .expect("Failed to lock transfer queue")
+            .set_max_active_per_device(transfers);
+
+        self.run_transfer_queue();
+    }
+
+    fn transfer_queue(&self) -> Arc<std::sync::Mutex<TransferQueue>> {
+        return self.variables.blocking_read().transfer_queue.clone();
+    }
+
+    /// Starts the queued transfers the limits allow, each on its own thread. Once one of them
+    /// is finished, the queue is run again.
+    fn run_transfer_queue(&self) {
+        let transfer_queue = self.transfer_queue();
+        let scheduled = transfer_queue
+            .lock()
+            .expect("Failed to lock transfer queue")
+            .start_next();
+
+        notify_queue_changed(&transfer_queue);
+
+        for transfer in scheduled {
+            let nearby_server = NearbyServer {
+                variables: self.variables.clone(),
+            };
+            let transfer_queue = transfer_queue.clone();
+
+            thread::spawn(move || {
+                let result = futures::executor::block_on(nearby_server.send_files(
+                    transfer.receiver,
+                    transfer.file_paths,
+                    transfer.progress_delegate,
+                ));
+
+                if let Err(error) = &result {
+                    println!("Queued transfer {} failed: {:?}", transfer.id, error);
+                }
+
+                transfer_queue
+                    .lock()
+                    .expect("Failed to lock transfer queue")
+                    .finish(&transfer.id, &result);
+
+                nearby_server.run_transfer_queue();
+            });
+        }
     }
 
     pub fn set_bluetooth_le_details(&self, ble_info: BluetoothLeConnectionInfo) {
@@ -160,12 +735,11 @@ impl NearbyServer {
                 .nearby_connection_delegate
                 .clone();
 
-            let Some(delegate) = delegate else {
+            if delegate.is_none() {
                 return;
-            };
+            }
 
-            let file_storage = self.variables.read().await.file_storage.clone();
-            let tcp_server = TcpServer::new(delegate, file_storage).await;
+            let tcp_server = TcpServer::new(self.variables.clone()).await;
 
             if let Ok(tcp_server) = tcp_server {
                 let ip = self.get_current_ip();
@@ -203,18 +777,204 @@ impl NearbyServer {
         self.start().await;
     }
 
-    async fn initiate_sender<T>(&self, raw_stream: T) -> Result<EncryptedStream<T>, ConnectErrors>
+    async fn initiate_sender<T>(
+        &self,
+        raw_stream: T,
+        receiver: &Device,
+    ) -> Result<Session<T>, ConnectErrors>
     where
         T: Read + Write,
     {
-        return Ok(match initiate_sender_communication(raw_stream).await {
-            Ok(stream) => stream,
+        let identity = self.variables.read().await.identity.clone();
+        let session = initiate_sender_communication(raw_stream, &identity).await?;
+
+        if derive_device_id(&session.peer_identity_key) != receiver.id {
+            return Err(ConnectErrors::PeerIdentityMismatch);
+        }
+
+        return Ok(session);
+    }
+
+    /// Runs the receiving side of the handshake on a freshly opened stream and hands the
+    /// resulting [`ConnectionRequest`] to the [`NearbyConnectionDelegate`].
+    pub(crate) fn receive_connection_request<T>(
+        variables: &Arc<RwLock<NearbyServerLockedVariables>>,
+        raw_stream: T,
+        medium: ConnectionMedium,
+    ) where
+        T: Read + Write + Send + Close + 'static,
+    {
+        let (
+            delegate,
+            file_storage,
+            identity,
+            trust_store,
+            auto_accept_trusted,
+            parallel_transfers,
+            max_parallel_connections,
+            rate_limits,
+            transfer_sink,
+            extraction_limits,
+            storage_quotas,
+            progress_interval,
+            transfer_history,
+        ) = {
+            let variables = variables.blocking_read();
+
+            (
+                variables.nearby_connection_delegate.clone(),
+                variables.file_storage.clone(),
+                variables.identity.clone(),
+                variables.trust_store.clone(),
+                variables.auto_accept_trusted,
+                variables.parallel_transfers.clone(),
+                variables.max_parallel_connections,
+                variables.rate_limits.clone(),
+                variables.transfer_sink.clone(),
+                variables.extraction_limits,
+                variables.storage_quotas.clone(),
+                variables.progress_interval,
+                variables.transfer_history.clone(),
+            )
+        };
+
+        let Some(delegate) = delegate else {
+            return;
+        };
+
+        let mut session = match initiate_receiver_communication(raw_stream, &identity) {
+            Ok(session) => session,
             Err(error) => {
-                return Err(ConnectErrors::FailedToEncryptStream {
-                    error: error.to_string(),
-                })
+                println!("Encryption error {:}", error);
+                return;
             }
-        });
+        };
+
+        let frame = match Channel::new(&mut session.stream).receive_frame() {
+            Ok(frame) => frame,
+            Err(error) => {
+                println!("Error {:}", error);
+                return;
+            }
+        };
+
+        let transfer_request = match frame.r#type() {
+            MessageTypes::TransferRequest => decode_payload::<TransferRequest>(&frame),
+            MessageTypes::JoinTransfer => {
+                NearbyServer::receive_joined_connection(&parallel_transfers, session, &frame);
+                return;
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Expected a transfer request",
+            )),
+        };
+
+        let transfer_request = match transfer_request {
+            Ok(message) => message,
+            Err(error) => {
+                println!("Error {:}", error);
+                return;
+            }
+        };
+
+        let claimed_id = transfer_request
+            .device
+            .as_ref()
+            .map(|device| device.id.as_str());
+
+        if claimed_id != Some(derive_device_id(&session.peer_identity_key).as_str()) {
+            println!("Error {:}", IncomingErrors::InvalidSenderId);
+            return;
+        }
+
+        if let Some(Intent::Clipboard(clipboard_intent)) = &transfer_request.intent {
+            if !is_valid_clipboard_intent(clipboard_intent) {
+                println!("Declining clipboard transfer exceeding the size limits");
+
+                let _ = Channel::new(&mut session.stream).send_message(
+                    MessageTypes::TransferResponse,
+                    CONTROL_STREAM_ID,
+                    &TransferRequestResponse {
+                        accepted: false,
+                        resume_offsets: vec![],
+                        parallel_connections: 1,
+                        decline_reason: transfer_request_response::DeclineReason::TooLarge as i32,
+                    },
+                );
+                return;
+            }
+        }
+
+        let verification_code = session.verification_code();
+        let mut connection_request = ConnectionRequest::new(
+            transfer_request,
+            Box::new(session.stream),
+            file_storage,
+            session.peer_identity_key,
+            verification_code,
+            trust_store,
+            auto_accept_trusted,
+        );
+        connection_request.set_parallel_transfers(parallel_transfers, max_parallel_connections);
+        connection_request.set_rate_limits(rate_limits);
+        connection_request.set_transfer_sink(transfer_sink);
+        connection_request.set_extraction_limits(extraction_limits);
+        connection_request.set_storage_quotas(storage_quotas);
+        connection_request.set_progress_interval(progress_interval);
+        connection_request.set_transfer_history(transfer_history, medium);
+        let connection_request = Arc::new(connection_request);
+
+        delegate
+            .lock()
+            .expect("Failed to lock delegate")
+            .received_connection_request(connection_request.clone());
+
+        if connection_request.is_auto_accepted() {
+            connection_request.accept();
+        }
+    }
+
+    /// Hands an additional connection of a sender to the accepted transfer it joins.
+    fn receive_joined_connection<T>(
+        parallel_transfers: &ParallelTransfers,
+        session: Session<T>,
+        frame: &Frame,
+    ) where
+        T: Read + Write + Send + Close + 'static,
+    {
+        let parallel_receiver = decode_payload::<JoinTransferRequest>(frame)
+            .ok()
+            .and_then(|join_request| {
+                parallel_transfers
+                    .lock()
+                    .expect("Failed to lock parallel transfers")
+                    .get(&join_request.transfer_id)
+                    .cloned()
+            })
+            .filter(|parallel_receiver| {
+                parallel_receiver.add_connection(&session.peer_identity_key)
+            });
+
+        let stream: Box<dyn EncryptedReadWrite> = Box::new(session.stream);
+        let mut channel = Channel::new(stream);
+
+        let _ = channel.send_message(
+            MessageTypes::TransferResponse,
+            CONTROL_STREAM_ID,
+            &TransferRequestResponse {
+                accepted: parallel_receiver.is_some(),
+                resume_offsets: vec![],
+                parallel_connections: 1,
+                decline_reason: transfer_request_response::DeclineReason::User as i32,
+            },
+        );
+
+        if let Some(parallel_receiver) = parallel_receiver {
+            parallel_receiver.receive_connection(&mut channel);
+        }
+
+        channel.get_mut().shutdown();
     }
 
     pub fn handle_incoming_ble_connection(
@@ -236,7 +996,8 @@ impl NearbyServer {
     async fn connect_tcp(
         &self,
         connection_details: &DeviceConnectionInfo,
-    ) -> Result<Box<dyn EncryptedReadWrite>, ConnectErrors> {
+        receiver: &Device,
+    ) -> Result<Session<TcpStream>, ConnectErrors> {
         let Some(tcp_connection_details) = &connection_details.tcp else {
             return Err(ConnectErrors::FailedToGetTcpDetails);
         };
@@ -260,8 +1021,7 @@ impl NearbyServer {
         let tcp_stream = TcpClient::connect(socket_address);
 
         if let Ok(raw_stream) = tcp_stream {
-            let encrypted_stream = self.initiate_sender(raw_stream).await?;
-            return Ok(Box::new(encrypted_stream));
+            return self.initiate_sender(raw_stream, receiver).await;
         }
 
         println!("{:?}", tcp_stream.unwrap_err());
@@ -272,25 +1032,35 @@ impl NearbyServer {
         &self,
         device: Device,
         progress_delegate: &Option<Box<dyn SendProgressDelegate>>,
-    ) -> Result<Box<dyn EncryptedReadWrite>, ConnectErrors> {
-        let Some(connection_details) = Discovery::get_connection_details(device) else {
+    ) -> Result<OutgoingConnection, ConnectErrors> {
+        let Some(connection_details) = Discovery::get_connection_details(device.clone()) else {
             return Err(ConnectErrors::FailedToGetConnectionDetails);
         };
 
-        let encrypted_stream = self.connect_tcp(&connection_details).await;
+        let session = self.connect_tcp(&connection_details, &device).await;
 
-        if let Ok(encrypted_stream) = encrypted_stream {
+        if let Ok(session) = session {
             NearbyServer::update_progress(
                 progress_delegate,
                 SendProgressState::ConnectionMediumUpdate {
                     medium: ConnectionMedium::WiFi,
                 },
             );
+            NearbyServer::update_progress(
+                progress_delegate,
+                SendProgressState::VerificationCode {
+                    code: session.verification_code(),
+                },
+            );
 
-            return Ok(encrypted_stream);
+            return Ok(OutgoingConnection {
+                stream: Box::new(session.stream),
+                capabilities: session.capabilities,
+                medium: ConnectionMedium::WiFi,
+            });
         }
 
-        if let Err(error) = encrypted_stream {
+        if let Err(error) = session {
             println!("{:?}", error)
         }
 
@@ -324,15 +1094,66 @@ impl NearbyServer {
             return Err(ConnectErrors::FailedToEstablishBleConnection);
         };
 
-        let encrypted_stream = self.initiate_sender(connection).await?;
+        let session = self.initiate_sender(connection, &device).await?;
         NearbyServer::update_progress(
             progress_delegate,
             SendProgressState::ConnectionMediumUpdate {
                 medium: ConnectionMedium::BLE,
             },
         );
+        NearbyServer::update_progress(
+            progress_delegate,
+            SendProgressState::VerificationCode {
+                code: session.verification_code(),
+            },
+        );
 
-        return Ok(Box::new(encrypted_stream));
+        return Ok(OutgoingConnection {
+            stream: Box::new(session.stream),
+            capabilities: session.capabilities,
+            medium: ConnectionMedium::BLE,
+        });
+    }
+
+    /// Opens another TCP connection to the receiver and adds it to an accepted transfer.
+    async fn join_transfer(
+        &self,
+        receiver: &Device,
+        transfer_id: &str,
+    ) -> Result<Channel<Box<dyn EncryptedReadWrite>>, ConnectErrors> {
+        let Some(connection_details) = Discovery::get_connection_details(receiver.clone()) else {
+            return Err(ConnectErrors::FailedToGetConnectionDetails);
+        };
+
+        let session = self.connect_tcp(&connection_details, receiver).await?;
+        let stream: Box<dyn EncryptedReadWrite> = Box::new(session.stream);
+        let mut channel = Channel::new(stream);
+
+        let _ = channel.send_message(
+            MessageTypes::JoinTransfer,
+            CONTROL_STREAM_ID,
+            &JoinTransferRequest {
+                transfer_id: transfer_id.to_string(),
+            },
+        );
+
+        let response = match channel
+            .receive_message::<TransferRequestResponse>(MessageTypes::TransferResponse)
+        {
+            Ok(message) => message,
+            Err(error) => {
+                return Err(ConnectErrors::FailedToGetTransferRequestResponse {
+                    error: error.to_string(),
+                })
+            }
+        };
+
+        if !response.accepted {
+            channel.get_mut().shutdown();
+            return Err(ConnectErrors::declined(response.decline_reason().into()));
+        }
+
+        return Ok(channel);
     }
 
     fn update_progress(
@@ -344,101 +1165,328 @@ impl NearbyServer {
         }
     }
 
-    fn zip_directory(&self, zip: &mut ZipWriter<File>, prefix: String, dir_path: &str) {
-        let path = Path::new(&dir_path);
+    /// Sends the transfer request and waits for the user on the other side to accept it.
+    async fn request_transfer<T>(
+        &self,
+        channel: &mut Channel<T>,
+        intent: Intent,
+        progress_delegate: &Option<Box<dyn SendProgressDelegate>>,
+    ) -> Result<TransferRequestResponse, ConnectErrors>
+    where
+        T: Read + Write,
+    {
+        let transfer_request = TransferRequest {
+            device: self
+                .variables
+                .read()
+                .await
+                .device_connection_info
+                .device
+                .clone(),
+            intent: Some(intent),
+        };
 
-        let Some(directory_name) = path.file_name() else {
-            println!("Path does not have a final component.");
-            return;
+        let _ = channel.send_message(
+            MessageTypes::TransferRequest,
+            CONTROL_STREAM_ID,
+            &transfer_request,
+        );
+
+        let response = match channel
+            .receive_message::<TransferRequestResponse>(MessageTypes::TransferResponse)
+        {
+            Ok(message) => message,
+            Err(error) => {
+                return Err(ConnectErrors::FailedToGetTransferRequestResponse {
+                    error: error.to_string(),
+                })
+            }
         };
 
-        let directory_name =
-            convert_os_str(directory_name).expect("Failed to convert OSString to String");
-        let combined_path = PathBuf::from(prefix).join(directory_name);
-        let dir_name = combined_path.to_str().unwrap();
+        if !response.accepted {
+            let reason = DeclineReason::from(response.decline_reason());
 
-        println!("Directory name: {:?}", dir_name);
+            NearbyServer::update_progress(progress_delegate, SendProgressState::declined(reason));
+            return Err(ConnectErrors::declined(reason));
+        }
 
-        let result = zip.add_directory(dir_name, SimpleFileOptions::default());
+        return Ok(response);
+    }
 
-        for entry in fs::read_dir(dir_path).expect("Failed to read directory.") {
-            let entry = entry.expect("Failed to get entry");
-            let path = entry.path();
+    /// Sends plain text. See [`Self::send_clipboard_representations`] for other content types.
+    pub async fn send_clipboard(
+        &self,
+        receiver: Device,
+        clipboard_content: String,
+        progress_delegate: Option<Box<dyn SendProgressDelegate>>,
+    ) -> Result<(), ConnectErrors> {
+        return self
+            .send_clipboard_representations(
+                receiver,
+                vec![ClipboardRepresentation::plain_text(clipboard_content)],
+                progress_delegate,
+            )
+            .await;
+    }
 
-            if path.is_dir() {
-                self.zip_directory(zip, dir_name.to_string(), path.to_str().unwrap());
-                return;
-            } else {
-                println!("Adding file to ZIP dir: {:?}", path);
-                let file_name = path.file_name().expect("Could not read file name");
-                let zip_file_path = path.join(file_name);
+    /// Sends all representations of the clipboard, e.g. plain text, HTML and an image of the
+    /// same content. The receiver picks the one it's able to handle best.
+    pub async fn send_clipboard_representations(
+        &self,
+        receiver: Device,
+        representations: Vec<ClipboardRepresentation>,
+        progress_delegate: Option<Box<dyn SendProgressDelegate>>,
+    ) -> Result<(), ConnectErrors> {
+        let (clipboard_intent, streamed_data) = create_clipboard_intent(representations)?;
 
-                let _ = zip.start_file(
-                    zip_file_path.to_str().unwrap(),
-                    SimpleFileOptions::default(),
-                );
+        NearbyServer::update_progress(&progress_delegate, SendProgressState::Connecting);
 
-                let mut file = File::open(path).unwrap();
-                let _ = std::io::copy(&mut file, zip);
-            }
+        let connection = self.connect(receiver, &progress_delegate).await?;
+
+        if !connection
+            .capabilities
+            .supports_intent(capabilities::Intent::Clipboard)
+        {
+            return Err(ConnectErrors::UnsupportedIntent);
         }
 
-        if let Err(error) = result {
-            println!("Error while trying to create ZIP directory: {:?}", error);
-            return;
+        let mut channel = Channel::new(connection.stream);
+
+        NearbyServer::update_progress(&progress_delegate, SendProgressState::Requesting);
+
+        let intent = Intent::Clipboard(clipboard_intent);
+
+        if let Err(error) = self
+            .request_transfer(&mut channel, intent, &progress_delegate)
+            .await
+        {
+            channel.get_mut().shutdown();
+            return Err(error);
+        }
+
+        if streamed_data.is_empty() {
+            channel.get_mut().shutdown();
+            NearbyServer::update_progress(&progress_delegate, SendProgressState::Finished);
+            return Ok(());
         }
+
+        let total_size: usize = streamed_data.iter().map(|data| data.len()).sum();
+        let progress_tracker = ProgressTracker::new(
+            vec![],
+            total_size as u64,
+            self.variables.read().await.progress_interval,
+        );
+        let control = TransferControl::new();
+        let mut writer = DataWriter::new(&mut channel, TRANSFER_STREAM_ID, &control);
+        let mut all_written: usize = 0;
+
+        let result = 'transfer: {
+            for data in &streamed_data {
+                for chunk in data.chunks(DATA_FRAME_SIZE) {
+                    if let Err(error) = writer.write_all(chunk) {
+                        break 'transfer Err(error);
+                    }
+
+                    all_written += chunk.len();
+
+                    if let Some(progress) = progress_tracker.update(all_written as u64) {
+                        NearbyServer::update_progress(
+                            &progress_delegate,
+                            SendProgressState::Transferring { progress },
+                        );
+                    }
+                }
+            }
+
+            writer.finish().map(drop)
+        };
+
+        channel.get_mut().shutdown();
+
+        return NearbyServer::finish_transfer(result, &progress_delegate);
     }
 
-    pub async fn send_files(
-        &self,
-        receiver: Device,
-        file_paths: Vec<String>,
-        progress_delegate: Option<Box<dyn SendProgressDelegate>>,
+    fn finish_transfer(
+        result: std::io::Result<()>,
+        progress_delegate: &Option<Box<dyn SendProgressDelegate>>,
     ) -> Result<(), ConnectErrors> {
-        NearbyServer::update_progress(&progress_delegate, SendProgressState::Connecting);
+        return match result {
+            Ok(()) => {
+                NearbyServer::update_progress(progress_delegate, SendProgressState::Finished);
+                Ok(())
+            }
+            Err(error) if is_cancellation(&error) => {
+                NearbyServer::update_progress(progress_delegate, SendProgressState::Cancelled);
+                Ok(())
+            }
+            Err(error) => {
+                NearbyServer::update_progress(progress_delegate, SendProgressState::Cancelled);
+                Err(ConnectErrors::TransferFailed {
+                    error: error.to_string(),
+                })
+            }
+        };
+    }
 
-        let mut encrypted_stream = match self.connect(receiver, &progress_delegate).await {
-            Ok(connection) => connection,
-            Err(error) => return Err(error),
+    /// Sends the file data once the receiver accepted, continuing at the offsets the receiver
+    /// reported for a resumed transfer.
+    fn transfer_files<T>(
+        channel: &mut Channel<T>,
+        outgoing: &OutgoingFiles,
+        capabilities: &NegotiatedCapabilities,
+        resume_offsets: &[u64],
+        progress_delegate: &Option<Box<dyn SendProgressDelegate>>,
+    ) -> std::io::Result<TransferResult>
+    where
+        T: Read + Write,
+    {
+        let control = TransferControl::new();
+        let mut writer = DataWriter::new(channel, TRANSFER_STREAM_ID, &control);
+        let buffered_writer = BufWriter::with_capacity(
+            DATA_FRAME_SIZE,
+            RateLimited::new(&mut writer, outgoing.rate_limit),
+        );
+
+        let progress_tracker = outgoing.progress_tracker();
+        let update_progress = |sent_bytes: u64| {
+            if let Some(progress) = progress_tracker.update(sent_bytes) {
+                NearbyServer::update_progress(
+                    progress_delegate,
+                    SendProgressState::Transferring { progress },
+                );
+            }
         };
 
-        let mut proto_stream = Stream::new(&mut encrypted_stream);
+        update_progress(resume_offsets.iter().sum());
+
+        // Zip entries carry their own checksums, only framed files are hashed for the trailer.
+        let trailer = match capabilities.archive_format {
+            capabilities::ArchiveFormat::Framed => {
+                let (buffered_writer, hashes) = send_framed_files(
+                    outgoing.files,
+                    buffered_writer,
+                    resume_offsets,
+                    outgoing.compression_policy,
+                    capabilities.compression,
+                    update_progress,
+                )?;
+                drop(buffered_writer);
+
+                TransferTrailer { hashes }
+            }
+            capabilities::ArchiveFormat::Zip => {
+                // Peers without deflate support get stored entries.
+                let policy = match capabilities.compression {
+                    capabilities::Compression::None => CompressionPolicy::Off,
+                    _ => outgoing.compression_policy,
+                };
 
-        NearbyServer::update_progress(&progress_delegate, SendProgressState::Compressing);
-        println!("Compressing");
+                zip_files(outgoing.files, buffered_writer, policy, update_progress)?;
 
-        let mut tmp_file = NamedTempFile::new().expect("Failed to create temporary ZIP file.");
-        let mut zip = zip::ZipWriter::new(tmp_file.reopen().expect("Failed to reopen tmp file"));
+                TransferTrailer::default()
+            }
+        };
 
-        for file_path in &file_paths {
-            let file = Path::new(file_path);
+        let payload = writer.finish_with(trailer.encode_to_vec())?;
 
-            if file.is_dir() {
-                self.zip_directory(&mut zip, "".to_string(), file_path);
-            } else {
-                println!("Compressing file: {:?}", file);
-                zip.start_file(
-                    convert_os_str(file.file_name().unwrap()).unwrap(),
-                    SimpleFileOptions::default(),
-                )
-                .unwrap();
+        return TransferResult::decode(payload.as_slice())
+            .map_err(|error| std::io::Error::new(std::io::ErrorKind::InvalidData, error));
+    }
 
-                let mut file = File::open(file_path).unwrap();
-                let _ = std::io::copy(&mut file, &mut zip);
+    /// Sends the file data as chunks spread over all connections. The first connection ends last,
+    /// once every other one got its chunks confirmed, and receives the result of the transfer.
+    fn transfer_chunks(
+        channels: &mut [Channel<Box<dyn EncryptedReadWrite>>],
+        outgoing: &OutgoingFiles,
+        capabilities: &NegotiatedCapabilities,
+        resume_offsets: &[u64],
+        progress_delegate: &Option<Box<dyn SendProgressDelegate>>,
+    ) -> io::Result<TransferResult> {
+        let Some((primary_channel, joined_channels)) = channels.split_first_mut() else {
+            return Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "No connection to send the file chunks over",
+            ));
+        };
+
+        let sender = ParallelSender::new(
+            outgoing.files,
+            resume_offsets,
+            outgoing.compression_policy,
+            capabilities.compression,
+        );
+        let control = TransferControl::new();
+
+        let progress_tracker = outgoing.progress_tracker();
+        let update_progress = |sent_bytes: u64| {
+            if let Some(progress) = progress_tracker.update(sent_bytes) {
+                NearbyServer::update_progress(
+                    progress_delegate,
+                    SendProgressState::Transferring { progress },
+                );
             }
-        }
+        };
 
-        let zip_result = zip.finish().expect("Failed to finish the ZIP");
+        update_progress(sender.sent_bytes());
+
+        return thread::scope(|scope| {
+            // Chunks are read out of order, so the files are hashed alongside.
+            let hashing_thread = scope.spawn(|| hash_files(outgoing.files));
+
+            let joined_threads: Vec<_> = joined_channels
+                .iter_mut()
+                .map(|channel| {
+                    let (sender, control, update_progress) = (&sender, &control, &update_progress);
+
+                    scope.spawn(move || {
+                        let mut writer = DataWriter::new(channel, TRANSFER_STREAM_ID, control);
+                        let mut sent_chunks = vec![];
+
+                        let result = sender.send_chunks(
+                            &mut BufWriter::with_capacity(
+                                DATA_FRAME_SIZE,
+                                RateLimited::new(&mut writer, outgoing.rate_limit),
+                            ),
+                            &mut sent_chunks,
+                            update_progress,
+                        );
+
+                        if let Err(error) = result.and_then(|_| writer.finish()) {
+                            println!("Additional connection failed: {:?}", error);
+                            sender.requeue(sent_chunks);
+                        }
+                    })
+                })
+                .collect();
 
-        let file_size = zip_result
-            .metadata()
-            .expect("Failed to retrieve metadata from ZIP")
-            .len();
+            let mut writer = DataWriter::new(primary_channel, TRANSFER_STREAM_ID, &control);
+            let mut buffered_writer = BufWriter::with_capacity(
+                DATA_FRAME_SIZE,
+                RateLimited::new(&mut writer, outgoing.rate_limit),
+            );
 
-        println!("Finished ZIP with a size of: {:?}", file_size);
+            sender.send_chunks(&mut buffered_writer, &mut vec![], update_progress)?;
 
-        NearbyServer::update_progress(&progress_delegate, SendProgressState::Requesting);
+            for joined_thread in joined_threads {
+                let _ = joined_thread.join();
+            }
+
+            // Chunks of connections that failed in the meantime.
+            sender.send_chunks(&mut buffered_writer, &mut vec![], update_progress)?;
+            drop(buffered_writer);
+
+            let hashes = hashing_thread
+                .join()
+                .map_err(|_| io::Error::other("Failed to hash the files"))??;
+            let payload = writer.finish_with(TransferTrailer { hashes }.encode_to_vec())?;
+
+            return TransferResult::decode(payload.as_slice())
+                .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error));
+        });
+    }
 
+    /// Collects the files to send, once for all receivers.
+    fn prepare_files(file_paths: &[String]) -> Result<PreparedFiles, ConnectErrors> {
         let file_name = {
             if file_paths.len() == 1 {
                 let path = Path::new(file_paths.first().unwrap());
@@ -451,121 +1499,365 @@ impl NearbyServer {
             }
         };
 
-        let transfer_request = TransferRequest {
-            device: self
-                .variables
-                .read()
-                .await
-                .device_connection_info
-                .device
-                .clone(),
-            intent: Some(Intent::FileTransfer(FileTransferIntent {
-                file_name,
-                file_size,
-                file_count: file_paths.len() as u64,
-            })),
+        return NearbyServer::prepare(collect_files(file_paths), file_name);
+    }
+
+    fn prepare_sources(
+        sources: Vec<Box<dyn TransferSource>>,
+    ) -> Result<PreparedFiles, ConnectErrors> {
+        let files: io::Result<Vec<SourceFile>> = sources
+            .into_iter()
+            .map(|source| SourceFile::from_source(Arc::from(source)))
+            .collect();
+
+        let file_name = match files.as_deref() {
+            Ok([file]) => Some(file.relative_path.clone()),
+            _ => None,
         };
 
-        let _ = proto_stream.send(&transfer_request);
+        return NearbyServer::prepare(files, file_name);
+    }
 
-        let response = match proto_stream.recv::<TransferRequestResponse>() {
-            Ok(message) => message,
+    fn prepare(
+        files: io::Result<Vec<SourceFile>>,
+        file_name: Option<String>,
+    ) -> Result<PreparedFiles, ConnectErrors> {
+        let files = match files {
+            Ok(files) => files,
             Err(error) => {
-                return Err(ConnectErrors::FailedToGetTransferRequestResponse {
+                return Err(ConnectErrors::FailedToDetermineFileSize {
                     error: error.to_string(),
                 })
             }
         };
 
-        if !response.accepted {
-            NearbyServer::update_progress(&progress_delegate, SendProgressState::Declined);
-            return Err(ConnectErrors::Declined);
-        }
+        return Ok(PreparedFiles {
+            file_size: files.iter().map(|file| file.size).sum(),
+            manifest: create_manifest(&files),
+            file_name,
+            files,
+        });
+    }
 
-        let mut buffer = [0; 1024];
+    /// Sends files and directories. If the connection drops during a framed transfer, the
+    /// sender reconnects, over BLE if WiFi is gone, and continues where the receiver stopped.
+    pub async fn send_files(
+        &self,
+        receiver: Device,
+        file_paths: Vec<String>,
+        progress_delegate: Option<Box<dyn SendProgressDelegate>>,
+    ) -> Result<(), ConnectErrors> {
+        NearbyServer::update_progress(&progress_delegate, SendProgressState::Connecting);
 
-        NearbyServer::update_progress(
-            &progress_delegate,
-            SendProgressState::Transferring { progress: 0.0 },
-        );
+        let prepared = NearbyServer::prepare_files(&file_paths)?;
 
-        let mut all_written: usize = 0;
+        return self
+            .send_prepared_files(receiver, &prepared, progress_delegate)
+            .await;
+    }
 
-        while let Ok(read_size) = tmp_file.read(&mut buffer) {
-            if read_size == 0 {
-                break;
-            }
+    /// Sends data that isn't necessarily a file on disk, see [`TransferSource`]. Like with
+    /// [`NearbyServer::send_files`], an interrupted transfer is resumed.
+    pub async fn send_sources(
+        &self,
+        receiver: Device,
+        sources: Vec<Box<dyn TransferSource>>,
+        progress_delegate: Option<Box<dyn SendProgressDelegate>>,
+    ) -> Result<(), ConnectErrors> {
+        NearbyServer::update_progress(&progress_delegate, SendProgressState::Connecting);
 
-            let written_bytes = encrypted_stream
-                .write(&buffer[..read_size])
-                .expect("Failed to write file buffer");
+        if sources.is_empty() {
+            return Err(ConnectErrors::NoFilesProvided);
+        }
 
-            if written_bytes == 0 {
-                break;
-            }
+        let prepared = NearbyServer::prepare_sources(sources)?;
 
-            all_written += written_bytes;
+        return self
+            .send_prepared_files(receiver, &prepared, progress_delegate)
+            .await;
+    }
 
-            NearbyServer::update_progress(
-                &progress_delegate,
-                SendProgressState::Transferring {
-                    progress: (all_written as f64 / file_size as f64),
-                },
-            );
+    /// Sends the same files to several receivers at the same time.
+    ///
+    /// The files are collected once. Every receiver gets its own session, so one
+    /// declining or failing doesn't affect the others. The result lists every receiver in the
+    /// given order.
+    pub async fn send_files_to_many(
+        &self,
+        receivers: Vec<Device>,
+        file_paths: Vec<String>,
+        progress_delegate: Option<Box<dyn RecipientProgressDelegate>>,
+    ) -> Result<Vec<RecipientResult>, ConnectErrors> {
+        let progress_delegate: Option<Arc<dyn RecipientProgressDelegate>> =
+            progress_delegate.map(Arc::from);
+
+        let recipient_delegates: Vec<Option<Box<dyn SendProgressDelegate>>> = receivers
+            .iter()
+            .map(|receiver| {
+                progress_delegate.clone().map(|delegate| {
+                    Box::new(RecipientProgress {
+                        device_id: receiver.id.clone(),
+                        delegate,
+                    }) as Box<dyn SendProgressDelegate>
+                })
+            })
+            .collect();
+
+        for recipient_delegate in &recipient_delegates {
+            NearbyServer::update_progress(recipient_delegate, SendProgressState::Connecting);
         }
 
-        let _ = tmp_file.close();
+        let prepared = Arc::new(NearbyServer::prepare_files(&file_paths)?);
+
+        // Each recipient gets its own thread, as the transfers block while they run.
+        let sessions =
+            receivers
+                .into_iter()
+                .zip(recipient_delegates)
+                .map(|(receiver, recipient_delegate)| {
+                    let nearby_server = NearbyServer {
+                        variables: self.variables.clone(),
+                    };
+                    let prepared = prepared.clone();
+                    let device_id = receiver.id.clone();
+                    let (result_sender, result_receiver) = oneshot::channel();
+
+                    thread::spawn(move || {
+                        let result =
+                            futures::executor::block_on(nearby_server.send_prepared_files(
+                                receiver,
+                                &prepared,
+                                recipient_delegate,
+                            ));
+
+                        let _ = result_sender.send(result);
+                    });
+
+                    return async move {
+                        let result = result_receiver.await.unwrap_or_else(|_| {
+                            Err(ConnectErrors::TransferFailed {
+                                error: "Session ended unexpectedly".to_string(),
+                            })
+                        });
 
-        if (all_written as f64) < (file_size as f64) {
-            NearbyServer::update_progress(&progress_delegate, SendProgressState::Cancelled);
-        } else {
-            NearbyServer::update_progress(&progress_delegate, SendProgressState::Finished);
-        }
+                        RecipientResult::new(device_id, result)
+                    };
+                });
 
-        return Ok(());
+        return Ok(futures::future::join_all(sessions).await);
     }
 
-    pub fn handle_incoming_connection(&self, native_stream_handle: Box<dyn NativeStreamDelegate>) {
-        let delegate = self
-            .variables
-            .blocking_read()
-            .nearby_connection_delegate
-            .clone();
+    /// Sends the files and records the transfer in the history.
+    async fn send_prepared_files(
+        &self,
+        receiver: Device,
+        prepared: &PreparedFiles,
+        progress_delegate: Option<Box<dyn SendProgressDelegate>>,
+    ) -> Result<(), ConnectErrors> {
+        let pending_record = PendingRecord::start(
+            TransferDirection::Sent,
+            receiver.clone(),
+            prepared.manifest.clone(),
+            prepared.file_size,
+        );
 
-        let Some(delegate) = delegate else {
-            return;
+        let observation = Arc::new(SendObservation::default());
+        let progress_delegate: Option<Box<dyn SendProgressDelegate>> =
+            Some(Box::new(ObservedProgress {
+                delegate: progress_delegate,
+                observation: observation.clone(),
+            }));
+
+        let result = self
+            .deliver_prepared_files(receiver, prepared, progress_delegate)
+            .await;
+
+        let (outcome, error) = match &result {
+            Ok(()) if observation.cancelled.load(Ordering::Relaxed) => {
+                (TransferOutcome::Cancelled, None)
+            }
+            Ok(()) => (TransferOutcome::Completed, None),
+            Err(ConnectErrors::Declined | ConnectErrors::DeclinedAutomatically { .. }) => {
+                (TransferOutcome::Declined, None)
+            }
+            Err(error @ ConnectErrors::IntegrityCheckFailed { .. }) => (
+                TransferOutcome::IntegrityCheckFailed,
+                Some(error.to_string()),
+            ),
+            Err(error) => (TransferOutcome::Failed, Some(error.to_string())),
         };
 
-        let file_storage = self.variables.blocking_read().file_storage.clone();
+        let medium = *observation.medium.lock().expect("Failed to lock medium");
+        let transfer_history = self.variables.read().await.transfer_history.clone();
+        record_transfer(
+            &transfer_history,
+            pending_record.finish(medium, outcome, error),
+        );
 
-        thread::spawn(move || {
-            let mut encrypted_stream = match initiate_receiver_communication(native_stream_handle) {
-                Ok(request) => request,
-                Err(error) => {
-                    println!("Encryption error {:}", error);
-                    return;
+        return result;
+    }
+
+    async fn deliver_prepared_files(
+        &self,
+        receiver: Device,
+        prepared: &PreparedFiles,
+        progress_delegate: Option<Box<dyn SendProgressDelegate>>,
+    ) -> Result<(), ConnectErrors> {
+        let files = &prepared.files;
+        let file_size = prepared.file_size;
+
+        let transfer_id = Uuid::new_v4().to_string();
+        let (compression_policy, max_parallel_connections, rate_limit, progress_interval) = {
+            let variables = self.variables.read().await;
+
+            (
+                variables.compression_policy,
+                variables.max_parallel_connections,
+                variables.rate_limits.for_transfer(),
+                variables.progress_interval,
+            )
+        };
+
+        let outgoing = OutgoingFiles {
+            files,
+            file_size,
+            compression_policy,
+            rate_limit: &rate_limit,
+            progress_interval,
+        };
+
+        let chunk_count = std::cmp::max(file_size.div_ceil(CHUNK_SIZE), 1);
+        let mut resume_attempts = 0;
+
+        loop {
+            let connection = self.connect(receiver.clone(), &progress_delegate).await?;
+
+            if !connection
+                .capabilities
+                .supports_intent(capabilities::Intent::FileTransfer)
+            {
+                return Err(ConnectErrors::UnsupportedIntent);
+            }
+
+            let mut channel = Channel::new(connection.stream);
+
+            NearbyServer::update_progress(&progress_delegate, SendProgressState::Requesting);
+
+            // BLE is slow enough for a single connection to keep up.
+            let parallel_connections = match connection.medium {
+                ConnectionMedium::WiFi => [
+                    max_parallel_connections,
+                    connection.capabilities.max_parallel_connections,
+                    chunk_count as u32,
+                ]
+                .into_iter()
+                .min()
+                .unwrap_or(1),
+                ConnectionMedium::BLE => 1,
+            };
+
+            let intent = Intent::FileTransfer(FileTransferIntent {
+                file_name: prepared.file_name.clone(),
+                file_size,
+                file_count: files.len() as u64,
+                files: prepared.manifest.clone(),
+                archive_format: connection.capabilities.archive_format as i32,
+                transfer_id: transfer_id.clone(),
+                parallel_connections,
+            });
+
+            let response = self
+                .request_transfer(&mut channel, intent, &progress_delegate)
+                .await?;
+
+            let mut channels = vec![channel];
+            let parallel_connections =
+                std::cmp::min(response.parallel_connections, parallel_connections);
+
+            // The transfer is split into chunks even if no additional connection succeeds.
+            while parallel_connections > 1 && channels.len() < parallel_connections as usize {
+                match self.join_transfer(&receiver, &transfer_id).await {
+                    Ok(channel) => channels.push(channel),
+                    Err(error) => {
+                        println!("Failed to open an additional connection: {:?}", error);
+                        break;
+                    }
                 }
+            }
+
+            let result = if parallel_connections > 1 {
+                NearbyServer::transfer_chunks(
+                    &mut channels,
+                    &outgoing,
+                    &connection.capabilities,
+                    &response.resume_offsets,
+                    &progress_delegate,
+                )
+            } else {
+                NearbyServer::transfer_files(
+                    &mut channels[0],
+                    &outgoing,
+                    &connection.capabilities,
+                    &response.resume_offsets,
+                    &progress_delegate,
+                )
             };
 
-            let mut prost_stream = Stream::new(&mut encrypted_stream);
-            let transfer_request = match prost_stream.recv::<TransferRequest>() {
-                Ok(message) => message,
-                Err(error) => {
-                    println!("Error {:}", error);
-                    return;
+            for channel in &mut channels {
+                channel.get_mut().shutdown();
+            }
+
+            let resumable = connection.capabilities.archive_format
+                == capabilities::ArchiveFormat::Framed
+                && resume_attempts < MAX_RESUME_ATTEMPTS;
+
+            if let Err(error) = &result {
+                if resumable && is_connection_loss(error) {
+                    resume_attempts += 1;
+                    println!(
+                        "Connection lost, resuming transfer ({}/{}): {:?}",
+                        resume_attempts, MAX_RESUME_ATTEMPTS, error
+                    );
+
+                    NearbyServer::update_progress(
+                        &progress_delegate,
+                        SendProgressState::Connecting,
+                    );
+                    wait_before_resuming().await;
+                    continue;
                 }
+            }
+
+            let transfer_result = match result {
+                Ok(transfer_result) => transfer_result,
+                Err(error) => return NearbyServer::finish_transfer(Err(error), &progress_delegate),
             };
 
-            let connection_request = ConnectionRequest::new(
-                transfer_request,
-                Box::new(encrypted_stream),
-                file_storage.clone(),
-            );
+            if !transfer_result.corrupt_files.is_empty() {
+                NearbyServer::update_progress(&progress_delegate, SendProgressState::Cancelled);
+
+                return Err(ConnectErrors::IntegrityCheckFailed {
+                    corrupt_files: transfer_result.corrupt_files,
+                });
+            }
+
+            if transfer_result.verified {
+                NearbyServer::update_progress(&progress_delegate, SendProgressState::Verified);
+            }
 
-            delegate
-                .lock()
-                .expect("Failed to lock delegate")
-                .received_connection_request(Arc::new(connection_request));
+            return NearbyServer::finish_transfer(Ok(()), &progress_delegate);
+        }
+    }
+
+    pub fn handle_incoming_connection(&self, native_stream_handle: Box<dyn NativeStreamDelegate>) {
+        let variables = self.variables.clone();
+
+        thread::spawn(move || {
+            NearbyServer::receive_connection_request(
+                &variables,
+                native_stream_handle,
+                ConnectionMedium::BLE,
+            );
         });
     }
 
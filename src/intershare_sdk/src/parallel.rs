@@ -0,0 +1,411 @@
+//! File transfers spread over several connections, to make use of fast local networks.
+//!
+//! The content of a framed transfer is split into chunks of up to [`CHUNK_SIZE`] bytes. Every
+//! chunk is sent as a length-delimited [`FileChunk`] followed by its content, on whichever
+//! connection is free. The connection that carried the request is the primary one, the others
+//! join the transfer with a `JoinTransferRequest`. The receiver writes every chunk at its offset.
+
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use protocol::communication::{FileChunk, FileManifestEntry};
+use protocol::prost::Message;
+
+use crate::capabilities::Compression;
+use crate::channel::{
+    is_cancellation, is_connection_loss, Channel, DataReader, TransferControl, TRANSFER_STREAM_ID,
+};
+use crate::checkpoint::Checkpoint;
+use crate::compression::{zstd_level, CompressionPolicy};
+use crate::convert_os_str;
+use crate::extraction::destination_path;
+use crate::framed::{read_content, read_header, write_content};
+use crate::manifest::SourceFile;
+use crate::rate_limit::{RateLimited, TransferRateLimit};
+
+pub const CHUNK_SIZE: u64 = 4 * 1024 * 1024;
+
+/// Most connections a transfer is spread over, as advertised during the handshake.
+pub const MAX_PARALLEL_CONNECTIONS: u32 = 8;
+
+pub const DEFAULT_PARALLEL_CONNECTIONS: u32 = 4;
+
+/// Transfers accepting additional connections, by transfer id.
+pub type ParallelTransfers = Arc<Mutex<HashMap<String, Arc<ParallelReceiver>>>>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Chunk {
+    pub file_index: usize,
+    pub offset: u64,
+    pub size: u64,
+}
+
+/// Splits the content that's left into chunks, every file continues at its resume offset.
+pub fn split_chunks(file_sizes: &[u64], resume_offsets: &[u64]) -> Vec<Chunk> {
+    let mut chunks = vec![];
+
+    for (file_index, file_size) in file_sizes.iter().enumerate() {
+        let mut offset = resume_offsets.get(file_index).copied().unwrap_or(0);
+
+        while offset < *file_size {
+            let size = std::cmp::min(CHUNK_SIZE, file_size - offset);
+
+            chunks.push(Chunk {
+                file_index,
+                offset,
+                size,
+            });
+
+            offset += size;
+        }
+    }
+
+    return chunks;
+}
+
+/// Sending side of a transfer, shared by the threads of all its connections.
+pub struct ParallelSender<'a> {
+    files: &'a [SourceFile],
+    policy: CompressionPolicy,
+    compression: Compression,
+    queue: Mutex<VecDeque<Chunk>>,
+    sent_bytes: AtomicU64,
+}
+
+impl<'a> ParallelSender<'a> {
+    pub fn new(
+        files: &'a [SourceFile],
+        resume_offsets: &[u64],
+        policy: CompressionPolicy,
+        compression: Compression,
+    ) -> Self {
+        let file_sizes: Vec<u64> = files.iter().map(|file| file.size).collect();
+
+        return Self {
+            files,
+            policy,
+            compression,
+            queue: Mutex::new(split_chunks(&file_sizes, resume_offsets).into()),
+            sent_bytes: AtomicU64::new(resume_offsets.iter().sum()),
+        };
+    }
+
+    /// Bytes the receiver has, or is about to receive, of all files.
+    pub fn sent_bytes(&self) -> u64 {
+        return self.sent_bytes.load(Ordering::Relaxed);
+    }
+
+    fn next_chunk(&self) -> Option<Chunk> {
+        return self
+            .queue
+            .lock()
+            .expect("Failed to lock chunk queue")
+            .pop_front();
+    }
+
+    fn send_chunk<W: Write>(&self, writer: &mut W, chunk: &Chunk) -> io::Result<()> {
+        let file = &self.files[chunk.file_index];
+        let zstd_level = zstd_level(self.policy, self.compression, &*file.source);
+
+        let header = FileChunk {
+            file_index: chunk.file_index as u32,
+            offset: chunk.offset,
+            size: chunk.size,
+            compression: match zstd_level {
+                Some(_) => Compression::Zstd as i32,
+                None => Compression::None as i32,
+            },
+        };
+
+        writer.write_all(&header.encode_length_delimited_to_vec())?;
+
+        return write_content(
+            &mut file.source.open(chunk.offset)?,
+            writer,
+            chunk.size,
+            zstd_level,
+            |_| {},
+        );
+    }
+
+    /// Sends chunks until there are none left, recording them in `sent_chunks`. `progress` is
+    /// called with [`Self::sent_bytes`] after every chunk.
+    pub fn send_chunks<W, F>(
+        &self,
+        writer: &mut W,
+        sent_chunks: &mut Vec<Chunk>,
+        progress: F,
+    ) -> io::Result<()>
+    where
+        W: Write,
+        F: Fn(u64),
+    {
+        while let Some(chunk) = self.next_chunk() {
+            sent_chunks.push(chunk);
+            self.sent_bytes.fetch_add(chunk.size, Ordering::Relaxed);
+
+            self.send_chunk(writer, &chunk)?;
+            progress(self.sent_bytes());
+        }
+
+        return writer.flush();
+    }
+
+    /// Puts back the chunks of a failed connection. The receiver only has them for sure once it
+    /// confirmed the end of the stream.
+    pub fn requeue(&self, chunks: Vec<Chunk>) {
+        let size: u64 = chunks.iter().map(|chunk| chunk.size).sum();
+        self.sent_bytes.fetch_sub(size, Ordering::Relaxed);
+
+        self.queue
+            .lock()
+            .expect("Failed to lock chunk queue")
+            .extend(chunks);
+    }
+}
+
+struct ReceiveState {
+    /// Offsets of the chunks written behind the complete part, by file.
+    completed_chunks: Vec<BTreeSet<u64>>,
+    /// Bytes from the start of every file that are complete.
+    file_bytes: Vec<u64>,
+    total_bytes: u64,
+    joined_connections: u32,
+    checkpoint: Option<Checkpoint>,
+}
+
+/// Receiving side of a transfer, shared by the threads of all its connections.
+pub struct ParallelReceiver {
+    destination: String,
+    sender_identity_key: [u8; 32],
+    files: Vec<FileManifestEntry>,
+    resume_offsets: Vec<u64>,
+    control: Arc<TransferControl>,
+    rate_limit: Arc<TransferRateLimit>,
+    connections: u32,
+    state: Mutex<ReceiveState>,
+}
+
+impl ParallelReceiver {
+    /// `connections` is the number accepted for the transfer, including the primary one. The
+    /// checkpoint, if any, is updated as the files complete.
+    pub fn new(
+        destination: String,
+        sender_identity_key: [u8; 32],
+        files: Vec<FileManifestEntry>,
+        checkpoint: Option<Checkpoint>,
+        control: Arc<TransferControl>,
+        rate_limit: Arc<TransferRateLimit>,
+        connections: u32,
+    ) -> Self {
+        let resume_offsets = match &checkpoint {
+            Some(checkpoint) => checkpoint.resume_offsets(),
+            None => vec![0; files.len()],
+        };
+
+        return Self {
+            destination,
+            sender_identity_key,
+            state: Mutex::new(ReceiveState {
+                completed_chunks: vec![BTreeSet::new(); files.len()],
+                file_bytes: resume_offsets.clone(),
+                total_bytes: resume_offsets.iter().sum(),
+                joined_connections: 0,
+                checkpoint,
+            }),
+            files,
+            resume_offsets,
+            control,
+            rate_limit,
+            connections,
+        };
+    }
+
+    /// Whether another connection of the sender may join the transfer.
+    pub fn add_connection(&self, sender_identity_key: &[u8; 32]) -> bool {
+        if *sender_identity_key != self.sender_identity_key {
+            return false;
+        }
+
+        let mut state = self.state.lock().expect("Failed to lock transfer state");
+
+        if state.joined_connections + 1 >= self.connections {
+            return false;
+        }
+
+        state.joined_connections += 1;
+
+        return true;
+    }
+
+    /// Size of the chunk at this position, `None` if the sender wouldn't send one there.
+    fn expected_chunk_size(&self, file_index: usize, offset: u64) -> Option<u64> {
+        let file_size = self.files.get(file_index)?.size;
+        let resume_offset = self.resume_offsets.get(file_index).copied().unwrap_or(0);
+
+        if offset < resume_offset
+            || offset >= file_size
+            || !(offset - resume_offset).is_multiple_of(CHUNK_SIZE)
+        {
+            return None;
+        }
+
+        return Some(std::cmp::min(CHUNK_SIZE, file_size - offset));
+    }
+
+    /// Records a chunk that was written to disk and returns the bytes of all files received.
+    fn complete_chunk(&self, file_index: usize, offset: u64, size: u64) -> u64 {
+        let mut state = self.state.lock().expect("Failed to lock transfer state");
+        let state = &mut *state;
+
+        // Chunks of failed connections are sent again, they are only counted once.
+        if offset >= state.file_bytes[file_index]
+            && state.completed_chunks[file_index].insert(offset)
+        {
+            state.total_bytes += size;
+        }
+
+        let file_size = self.files[file_index].size;
+        let mut file_bytes = state.file_bytes[file_index];
+
+        while file_bytes < file_size && state.completed_chunks[file_index].remove(&file_bytes) {
+            file_bytes += std::cmp::min(CHUNK_SIZE, file_size - file_bytes);
+        }
+
+        if file_bytes != state.file_bytes[file_index] {
+            state.file_bytes[file_index] = file_bytes;
+
+            if let Some(checkpoint) = &mut state.checkpoint {
+                checkpoint.update(file_index, file_bytes);
+            }
+        }
+
+        return state.total_bytes;
+    }
+
+    /// Writes the chunks of one connection until its stream ends. `progress` is called with the
+    /// bytes of all files received on any connection.
+    pub fn receive_chunks<R, F>(&self, reader: R, mut progress: F) -> io::Result<()>
+    where
+        R: Read,
+        F: FnMut(u64),
+    {
+        let mut reader = BufReader::new(RateLimited::new(reader, &self.rate_limit));
+
+        while let Some(chunk) = read_header::<FileChunk, _>(&mut reader)? {
+            let file_index = chunk.file_index as usize;
+
+            if self.expected_chunk_size(file_index, chunk.offset) != Some(chunk.size) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Received an unexpected file chunk",
+                ));
+            }
+
+            let out_path =
+                destination_path(Path::new(&self.destination), &self.files[file_index].path)?;
+
+            if let Some(parent) = out_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+
+            let mut out_file = OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(&out_path)?;
+            out_file.seek(SeekFrom::Start(chunk.offset))?;
+
+            read_content(
+                &mut reader,
+                &mut out_file,
+                chunk.size,
+                chunk.compression,
+                |_| {},
+            )?;
+
+            progress(self.complete_chunk(file_index, chunk.offset, chunk.size));
+        }
+
+        return Ok(());
+    }
+
+    /// Receives the chunks sent on a connection that joined the transfer.
+    pub fn receive_connection<T>(&self, channel: &mut Channel<T>)
+    where
+        T: Read + Write,
+    {
+        let mut reader = DataReader::new(channel, TRANSFER_STREAM_ID, &self.control);
+
+        match self.receive_chunks(&mut reader, |_| {}) {
+            Ok(()) => {
+                let _ = reader.confirm();
+            }
+            Err(error) => {
+                // The sender sends the chunks of this connection on another one.
+                if !is_cancellation(&error) && !is_connection_loss(&error) {
+                    println!("Error {:?}", error);
+                    let _ = reader.fail(error.to_string());
+                }
+            }
+        }
+    }
+
+    /// Called once the primary connection ended, by then the sender got every other connection's
+    /// chunks confirmed. Gives every file its final size, which also creates empty files.
+    pub fn finish(&self) -> io::Result<Vec<String>> {
+        let is_complete = {
+            let state = self.state.lock().expect("Failed to lock transfer state");
+
+            self.files
+                .iter()
+                .zip(&state.file_bytes)
+                .all(|(file, file_bytes)| file.size == *file_bytes)
+        };
+
+        if !is_complete {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Transfer ended before all file chunks arrived",
+            ));
+        }
+
+        let mut written_files = vec![];
+
+        for file in &self.files {
+            let out_path = destination_path(Path::new(&self.destination), &file.path)?;
+
+            if let Some(parent) = out_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+
+            OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(&out_path)?
+                .set_len(file.size)?;
+
+            println!("Received file {:?}", out_path);
+
+            if let Some(path) = convert_os_str(out_path.as_os_str()) {
+                written_files.push(path);
+            }
+        }
+
+        return Ok(written_files);
+    }
+
+    pub fn take_checkpoint(&self) -> Option<Checkpoint> {
+        return self
+            .state
+            .lock()
+            .expect("Failed to lock transfer state")
+            .checkpoint
+            .take();
+    }
+}
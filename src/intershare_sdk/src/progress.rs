@@ -0,0 +1,140 @@
+//! Progress of running transfers, together with the throughput and the remaining time.
+//!
+//! Transfers report their progress after every few kilobytes. [`ProgressTracker`] only passes it
+//! on once per interval, so delegates on the other side of the FFI aren't flooded.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Weight of the newest sample in the smoothed throughput.
+const SMOOTHING_FACTOR: f64 = 0.3;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TransferProgress {
+    /// Share of the transfer that's done, between `0` and `1`.
+    pub fraction: f64,
+    pub transferred_bytes: u64,
+    pub total_bytes: u64,
+    /// Throughput, smoothed over the recent reports.
+    pub bytes_per_second: f64,
+    /// Estimated time until the transfer is done, `None` until the throughput is known.
+    pub remaining_seconds: Option<f64>,
+    /// Position of the current file in the manifest.
+    pub file_index: u64,
+    /// Path of the current file, `None` for transfers that aren't made of files.
+    pub file_name: Option<String>,
+}
+
+struct TrackerState {
+    reported_at: Option<Instant>,
+    reported_bytes: u64,
+    bytes_per_second: Option<f64>,
+}
+
+/// Turns the byte counts of a transfer into [`TransferProgress`] reports.
+///
+/// Safe to share between the connections of a transfer.
+pub struct ProgressTracker {
+    /// Path and size of every file, in the order they are transferred.
+    files: Vec<(String, u64)>,
+    total_bytes: u64,
+    interval: Duration,
+    state: Mutex<TrackerState>,
+}
+
+impl ProgressTracker {
+    pub fn new(files: Vec<(String, u64)>, total_bytes: u64, interval: Duration) -> Self {
+        return Self {
+            files,
+            total_bytes,
+            interval,
+            state: Mutex::new(TrackerState {
+                reported_at: None,
+                reported_bytes: 0,
+                bytes_per_second: None,
+            }),
+        };
+    }
+
+    /// Progress to report once `transferred_bytes` are done. Returns `None` if the last report
+    /// is more recent than the interval. The first report and the one completing the transfer
+    /// are never held back.
+    pub fn update(&self, transferred_bytes: u64) -> Option<TransferProgress> {
+        let now = Instant::now();
+        let mut state = self.state.lock().expect("Failed to lock progress");
+        let complete = transferred_bytes >= self.total_bytes;
+
+        if let Some(reported_at) = state.reported_at {
+            if complete && state.reported_bytes >= self.total_bytes {
+                return None;
+            }
+
+            let elapsed = now.duration_since(reported_at);
+
+            if elapsed < self.interval && !complete {
+                return None;
+            }
+
+            if !elapsed.is_zero() {
+                let sample = transferred_bytes.saturating_sub(state.reported_bytes) as f64
+                    / elapsed.as_secs_f64();
+
+                state.bytes_per_second = Some(match state.bytes_per_second {
+                    Some(bytes_per_second) => {
+                        SMOOTHING_FACTOR * sample + (1.0 - SMOOTHING_FACTOR) * bytes_per_second
+                    }
+                    None => sample,
+                });
+            }
+        }
+
+        state.reported_at = Some(now);
+        state.reported_bytes = transferred_bytes;
+
+        return Some(self.progress(transferred_bytes, state.bytes_per_second));
+    }
+
+    fn progress(&self, transferred_bytes: u64, bytes_per_second: Option<f64>) -> TransferProgress {
+        let remaining_bytes = self.total_bytes.saturating_sub(transferred_bytes);
+
+        let remaining_seconds = match bytes_per_second {
+            _ if remaining_bytes == 0 => Some(0.0),
+            Some(bytes_per_second) if bytes_per_second > 0.0 => {
+                Some(remaining_bytes as f64 / bytes_per_second)
+            }
+            _ => None,
+        };
+
+        let (file_index, file_name) = self.current_file(transferred_bytes);
+
+        return TransferProgress {
+            fraction: f64::min(
+                transferred_bytes as f64 / std::cmp::max(self.total_bytes, 1) as f64,
+                1.0,
+            ),
+            transferred_bytes,
+            total_bytes: self.total_bytes,
+            bytes_per_second: bytes_per_second.unwrap_or(0.0),
+            remaining_seconds,
+            file_index,
+            file_name,
+        };
+    }
+
+    /// The file the byte at `transferred_bytes` belongs to, the last one once all are done.
+    fn current_file(&self, transferred_bytes: u64) -> (u64, Option<String>) {
+        let mut file_end = 0;
+
+        for (index, (path, size)) in self.files.iter().enumerate() {
+            file_end += size;
+
+            if transferred_bytes < file_end || index == self.files.len() - 1 {
+                return (index as u64, Some(path.clone()));
+            }
+        }
+
+        return (0, None);
+    }
+}
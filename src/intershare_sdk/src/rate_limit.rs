@@ -0,0 +1,164 @@
+//! Bandwidth limits, so large transfers don't saturate a shared network.
+//!
+//! Limits are given in bytes per second, `0` means unlimited. They are read on every read or
+//! write, so changing them takes effect on running transfers right away.
+
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const UNLIMITED: u64 = 0;
+
+/// A bucket holds at most the tokens of this many seconds, which limits bursts after idle times.
+const BURST_SECONDS: f64 = 0.25;
+
+/// Waiting is split into steps of at most this long, so a new limit is picked up quickly.
+const MAX_WAIT: Duration = Duration::from_millis(100);
+
+struct BucketState {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+/// Token bucket refilled at a rate that may change at any time.
+pub struct TokenBucket {
+    rate: Arc<AtomicU64>,
+    state: Mutex<BucketState>,
+}
+
+impl TokenBucket {
+    pub fn new(rate: Arc<AtomicU64>) -> Self {
+        return Self {
+            rate,
+            state: Mutex::new(BucketState {
+                tokens: 0.0,
+                refilled_at: Instant::now(),
+            }),
+        };
+    }
+
+    /// Blocks until `bytes` may be transferred.
+    ///
+    /// The bytes are taken as soon as the bucket isn't in debt, so large reads and writes don't
+    /// need to fit into the bucket. Later calls wait until the debt is paid off.
+    pub fn acquire(&self, bytes: u64) {
+        loop {
+            let rate = self.rate.load(Ordering::Relaxed);
+            let mut state = self.state.lock().expect("Failed to lock token bucket");
+
+            let now = Instant::now();
+            let elapsed = now.duration_since(state.refilled_at).as_secs_f64();
+            state.refilled_at = now;
+
+            if rate == UNLIMITED {
+                state.tokens = 0.0;
+                return;
+            }
+
+            state.tokens = f64::min(
+                state.tokens + elapsed * rate as f64,
+                rate as f64 * BURST_SECONDS,
+            );
+
+            if state.tokens >= 0.0 {
+                state.tokens -= bytes as f64;
+                return;
+            }
+
+            let wait = Duration::from_secs_f64(-state.tokens / rate as f64);
+            drop(state);
+
+            thread::sleep(std::cmp::min(wait, MAX_WAIT));
+        }
+    }
+}
+
+/// Bandwidth limits of a `NearbyServer`, shared with the transfers it runs.
+#[derive(Clone)]
+pub struct RateLimits {
+    global_rate: Arc<AtomicU64>,
+    global: Arc<TokenBucket>,
+    transfer_rate: Arc<AtomicU64>,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        let global_rate = Arc::new(AtomicU64::new(UNLIMITED));
+
+        return Self {
+            global: Arc::new(TokenBucket::new(global_rate.clone())),
+            global_rate,
+            transfer_rate: Arc::new(AtomicU64::new(UNLIMITED)),
+        };
+    }
+}
+
+impl RateLimits {
+    /// Caps the bandwidth of all transfers together.
+    pub fn set_global_limit(&self, bytes_per_second: u64) {
+        self.global_rate.store(bytes_per_second, Ordering::Relaxed);
+    }
+
+    /// Caps the bandwidth of every single transfer.
+    pub fn set_transfer_limit(&self, bytes_per_second: u64) {
+        self.transfer_rate
+            .store(bytes_per_second, Ordering::Relaxed);
+    }
+
+    /// Limit of a new transfer, shared by all of its connections.
+    pub fn for_transfer(&self) -> TransferRateLimit {
+        return TransferRateLimit {
+            global: self.global.clone(),
+            transfer: TokenBucket::new(self.transfer_rate.clone()),
+        };
+    }
+}
+
+/// Limits a single transfer, as well as all transfers together.
+pub struct TransferRateLimit {
+    global: Arc<TokenBucket>,
+    transfer: TokenBucket,
+}
+
+impl TransferRateLimit {
+    pub fn acquire(&self, bytes: u64) {
+        self.transfer.acquire(bytes);
+        self.global.acquire(bytes);
+    }
+}
+
+/// Reader or writer that's slowed down to a [`TransferRateLimit`].
+pub struct RateLimited<'a, T> {
+    inner: T,
+    limit: &'a TransferRateLimit,
+}
+
+impl<'a, T> RateLimited<'a, T> {
+    pub fn new(inner: T, limit: &'a TransferRateLimit) -> Self {
+        return Self { inner, limit };
+    }
+}
+
+impl<T: Read> Read for RateLimited<'_, T> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let read_size = self.inner.read(buffer)?;
+        self.limit.acquire(read_size as u64);
+
+        return Ok(read_size);
+    }
+}
+
+impl<T: Write> Write for RateLimited<'_, T> {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buffer)?;
+        self.limit.acquire(written as u64);
+
+        return Ok(written);
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        return self.inner.flush();
+    }
+}
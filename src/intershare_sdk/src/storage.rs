@@ -0,0 +1,63 @@
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use protocol::prost::Message;
+
+use crate::errors::StorageError;
+
+/// Reads a protobuf message persisted with [`save_message`]. Returns `None` if the file doesn't
+/// exist yet.
+pub fn load_message<M>(path: &Path) -> Result<Option<M>, StorageError>
+where
+    M: Message + Default,
+{
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(error) => {
+            return Err(StorageError::FailedToAccessFile {
+                error: error.to_string(),
+            })
+        }
+    };
+
+    return match M::decode(data.as_slice()) {
+        Ok(message) => Ok(Some(message)),
+        Err(error) => Err(StorageError::CorruptFile {
+            error: error.to_string(),
+        }),
+    };
+}
+
+/// Persists a protobuf message. The data is written to a temporary file first and then renamed,
+/// so a crash never leaves a half written file behind.
+pub fn save_message<M>(path: &Path, message: &M) -> Result<(), StorageError>
+where
+    M: Message,
+{
+    let temporary_path = path.with_extension("tmp");
+
+    let result = (|| {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        fs::write(&temporary_path, message.encode_to_vec())?;
+        fs::rename(&temporary_path, path)
+    })();
+
+    return result.map_err(|error| StorageError::FailedToAccessFile {
+        error: error.to_string(),
+    });
+}
+
+pub(crate) fn unix_timestamp() -> u64 {
+    return SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+}
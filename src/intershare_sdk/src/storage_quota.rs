@@ -0,0 +1,216 @@
+//! Checks whether the files of an incoming transfer fit before it's accepted.
+//!
+//! Transfers count against the free space and the quotas from the moment they are accepted, so
+//! several transfers accepted at the same time can't promise the same space twice.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use crate::connection_request::DeclineReason;
+
+pub const NO_QUOTA: u64 = 0;
+
+/// Limits for the space received files take up, `0` meaning no limit.
+#[derive(Default)]
+pub struct StorageQuotas {
+    /// Space all files in the file storage may take up together.
+    storage_quota: u64,
+    /// Bytes a single sender may transfer.
+    sender_quota: u64,
+    /// Bytes received from every sender, by device id. Counted while the `NearbyServer` runs.
+    sender_usage: HashMap<String, u64>,
+    /// Bytes promised to transfers that are still being received.
+    reserved: u64,
+}
+
+impl StorageQuotas {
+    pub fn set_storage_quota(&mut self, bytes: u64) {
+        self.storage_quota = bytes;
+    }
+
+    pub fn set_sender_quota(&mut self, bytes: u64) {
+        self.sender_quota = bytes;
+    }
+
+    /// Bytes received from the sender, including the transfers that are still running.
+    pub fn sender_usage(&self, sender_id: &str) -> u64 {
+        return self.sender_usage.get(sender_id).copied().unwrap_or(0);
+    }
+
+    pub fn reset_sender_usage(&mut self, sender_id: &str) {
+        self.sender_usage.remove(sender_id);
+    }
+}
+
+/// Space set aside for an accepted transfer. It's given back when dropped, unless the transfer
+/// was received, in which case the files now take up the space themselves.
+pub struct StorageReservation {
+    quotas: Arc<Mutex<StorageQuotas>>,
+    sender_id: String,
+    size: u64,
+    received: bool,
+}
+
+impl StorageReservation {
+    /// The files were received, they count towards the sender's usage from now on.
+    pub fn finish(mut self) {
+        self.received = true;
+    }
+}
+
+impl Drop for StorageReservation {
+    fn drop(&mut self) {
+        let mut quotas = self.quotas.lock().expect("Failed to lock storage quotas");
+        quotas.reserved = quotas.reserved.saturating_sub(self.size);
+
+        if !self.received {
+            if let Some(usage) = quotas.sender_usage.get_mut(&self.sender_id) {
+                *usage = usage.saturating_sub(self.size);
+            }
+        }
+    }
+}
+
+/// Sets aside `size` bytes for a transfer from `sender_id`.
+///
+/// Without a `file_storage`, the files don't end up on disk and only the sender quota applies.
+pub fn reserve_storage(
+    quotas: &Arc<Mutex<StorageQuotas>>,
+    file_storage: Option<&Path>,
+    sender_id: &str,
+    size: u64,
+) -> Result<StorageReservation, DeclineReason> {
+    let mut locked_quotas = quotas.lock().expect("Failed to lock storage quotas");
+
+    // The size is announced by the sender, it must not wrap past the limits.
+    let (Some(sender_usage), Some(reserved)) = (
+        locked_quotas.sender_usage(sender_id).checked_add(size),
+        locked_quotas.reserved.checked_add(size),
+    ) else {
+        return Err(DeclineReason::TooLarge);
+    };
+
+    if locked_quotas.sender_quota != NO_QUOTA && sender_usage > locked_quotas.sender_quota {
+        return Err(DeclineReason::SenderQuotaExceeded);
+    }
+
+    if let Some(file_storage) = file_storage {
+        if locked_quotas.storage_quota != NO_QUOTA {
+            let used = directory_size(file_storage)
+                .unwrap_or(0)
+                .checked_add(reserved);
+
+            if !matches!(used, Some(used) if used <= locked_quotas.storage_quota) {
+                return Err(DeclineReason::StorageQuotaExceeded);
+            }
+        }
+
+        if let Some(available) = available_space(file_storage) {
+            if reserved > available {
+                return Err(DeclineReason::InsufficientStorage);
+            }
+        }
+    }
+
+    locked_quotas.reserved = reserved;
+    locked_quotas
+        .sender_usage
+        .insert(sender_id.to_string(), sender_usage);
+
+    return Ok(StorageReservation {
+        quotas: quotas.clone(),
+        sender_id: sender_id.to_string(),
+        size,
+        received: false,
+    });
+}
+
+/// Combined size of all files below `path`.
+fn directory_size(path: &Path) -> io::Result<u64> {
+    let mut size = 0;
+
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+
+        if file_type.is_dir() {
+            size += directory_size(&entry.path())?;
+        } else if file_type.is_file() {
+            size += entry.metadata()?.len();
+        }
+    }
+
+    return Ok(size);
+}
+
+/// Bytes that can still be written to the file system `path` is on, `None` if that's unknown.
+///
+/// The file storage may not have been created yet, the closest existing parent is on the same
+/// file system then.
+pub fn available_space(path: &Path) -> Option<u64> {
+    let existing_path = path.ancestors().find(|ancestor| ancestor.exists())?;
+
+    return file_system_available_space(existing_path);
+}
+
+#[cfg(unix)]
+fn file_system_available_space(path: &Path) -> Option<u64> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stats: libc::statvfs = unsafe { std::mem::zeroed() };
+
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stats) } != 0 {
+        return None;
+    }
+
+    #[allow(clippy::unnecessary_cast)]
+    return Some(stats.f_bavail as u64 * stats.f_frsize as u64);
+}
+
+#[cfg(windows)]
+fn file_system_available_space(path: &Path) -> Option<u64> {
+    use std::os::windows::ffi::OsStrExt;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetDiskFreeSpaceExW(
+            directory_name: *const u16,
+            free_bytes_available_to_caller: *mut u64,
+            total_number_of_bytes: *mut u64,
+            total_number_of_free_bytes: *mut u64,
+        ) -> i32;
+    }
+
+    let path: Vec<u16> = path
+        .as_os_str()
+        .encode_wide()
+        .chain(std::iter::once(0))
+        .collect();
+    let mut available: u64 = 0;
+
+    let result = unsafe {
+        GetDiskFreeSpaceExW(
+            path.as_ptr(),
+            &mut available,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+        )
+    };
+
+    if result == 0 {
+        return None;
+    }
+
+    return Some(available);
+}
+
+/// Other platforms don't get the free space check, only the quotas apply there.
+#[cfg(not(any(unix, windows)))]
+fn file_system_available_space(_path: &Path) -> Option<u64> {
+    return None;
+}
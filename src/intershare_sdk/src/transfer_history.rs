@@ -0,0 +1,301 @@
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use protocol::communication::FileManifestEntry;
+use protocol::discovery::Device;
+use protocol::storage::transfer_record;
+use protocol::storage::{TransferHistoryContents, TransferRecord as StoredTransferRecord};
+use uuid::Uuid;
+
+use crate::errors::StorageError;
+use crate::nearby::ConnectionMedium;
+use crate::storage::{load_message, save_message, unix_timestamp};
+
+/// Oldest records are dropped once the history grows beyond this.
+pub const MAX_HISTORY_RECORDS: usize = 10_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransferDirection {
+    Sent,
+    Received,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransferOutcome {
+    Completed,
+    Declined,
+    Cancelled,
+    /// The connection was lost. A resumed transfer gets a record of its own.
+    Interrupted,
+    /// Some files didn't match the hash the sender computed.
+    IntegrityCheckFailed,
+    Failed,
+}
+
+#[derive(Clone, Debug)]
+pub struct TransferRecord {
+    pub id: String,
+    pub direction: TransferDirection,
+    pub peer: Device,
+    pub files: Vec<FileManifestEntry>,
+    pub total_size: u64,
+    /// `None` if no connection was established.
+    pub medium: Option<ConnectionMedium>,
+    /// Seconds since the unix epoch.
+    pub started_at: u64,
+    pub duration_millis: u64,
+    pub outcome: TransferOutcome,
+    pub error: Option<String>,
+}
+
+/// Filters for [`TransferHistory::query`], unset fields match every record.
+#[derive(Clone, Debug, Default)]
+pub struct TransferHistoryQuery {
+    pub device_id: Option<String>,
+    pub direction: Option<TransferDirection>,
+    /// Transfers started at or after, in seconds since the unix epoch.
+    pub since: Option<u64>,
+    /// Transfers started before, in seconds since the unix epoch.
+    pub until: Option<u64>,
+    pub limit: Option<u32>,
+}
+
+impl TransferHistoryQuery {
+    fn matches(&self, record: &StoredTransferRecord) -> bool {
+        let device_id = record.peer.as_ref().map(|peer| peer.id.as_str());
+
+        return self
+            .device_id
+            .as_ref()
+            .is_none_or(|id| device_id == Some(id.as_str()))
+            && self
+                .direction
+                .is_none_or(|direction| record.direction() == direction.into())
+            && self.since.is_none_or(|since| record.started_at >= since)
+            && self.until.is_none_or(|until| record.started_at < until);
+    }
+}
+
+/// Transfers this device sent and received, persisted as protobuf.
+///
+/// Recording is opt-in, nothing is kept before the history was loaded on the `NearbyServer`.
+pub struct TransferHistory {
+    path: PathBuf,
+    contents: TransferHistoryContents,
+}
+
+impl TransferHistory {
+    pub fn load(path: &Path) -> Result<Self, StorageError> {
+        let contents = load_message::<TransferHistoryContents>(path)?.unwrap_or_default();
+
+        return Ok(Self {
+            path: path.to_path_buf(),
+            contents,
+        });
+    }
+
+    pub fn record(&mut self, record: TransferRecord) -> Result<(), StorageError> {
+        self.contents.records.push(record.into());
+
+        let excess = self
+            .contents
+            .records
+            .len()
+            .saturating_sub(MAX_HISTORY_RECORDS);
+        self.contents.records.drain(..excess);
+
+        return self.save();
+    }
+
+    /// Matching records, newest first.
+    pub fn query(&self, query: &TransferHistoryQuery) -> Vec<TransferRecord> {
+        let limit = query.limit.map_or(usize::MAX, |limit| limit as usize);
+
+        return self
+            .contents
+            .records
+            .iter()
+            .rev()
+            .filter(|record| query.matches(record))
+            .take(limit)
+            .cloned()
+            .map(TransferRecord::from)
+            .collect();
+    }
+
+    /// Returns `false` if there is no record with the id.
+    pub fn delete(&mut self, id: &str) -> Result<bool, StorageError> {
+        let count = self.contents.records.len();
+        self.contents.records.retain(|record| record.id != id);
+
+        if self.contents.records.len() == count {
+            return Ok(false);
+        }
+
+        self.save()?;
+
+        return Ok(true);
+    }
+
+    pub fn clear(&mut self) -> Result<(), StorageError> {
+        self.contents.records.clear();
+
+        return self.save();
+    }
+
+    fn save(&self) -> Result<(), StorageError> {
+        return save_message(&self.path, &self.contents);
+    }
+}
+
+/// Adds the record to the history, if one was loaded.
+pub(crate) fn record_transfer(history: &Mutex<Option<TransferHistory>>, record: TransferRecord) {
+    let mut history = history.lock().expect("Failed to lock transfer history");
+
+    let Some(history) = history.as_mut() else {
+        return;
+    };
+
+    if let Err(error) = history.record(record) {
+        println!("Failed to update transfer history: {:?}", error);
+    }
+}
+
+/// Transfer that's being recorded, started once the peer is known.
+pub(crate) struct PendingRecord {
+    direction: TransferDirection,
+    peer: Device,
+    files: Vec<FileManifestEntry>,
+    total_size: u64,
+    started_at: u64,
+    started: Instant,
+}
+
+impl PendingRecord {
+    pub(crate) fn start(
+        direction: TransferDirection,
+        peer: Device,
+        files: Vec<FileManifestEntry>,
+        total_size: u64,
+    ) -> Self {
+        return Self {
+            direction,
+            peer,
+            files,
+            total_size,
+            started_at: unix_timestamp(),
+            started: Instant::now(),
+        };
+    }
+
+    pub(crate) fn finish(
+        self,
+        medium: Option<ConnectionMedium>,
+        outcome: TransferOutcome,
+        error: Option<String>,
+    ) -> TransferRecord {
+        return TransferRecord {
+            id: Uuid::new_v4().to_string(),
+            direction: self.direction,
+            peer: self.peer,
+            files: self.files,
+            total_size: self.total_size,
+            medium,
+            started_at: self.started_at,
+            duration_millis: self.started.elapsed().as_millis() as u64,
+            outcome,
+            error,
+        };
+    }
+}
+
+impl From<TransferDirection> for transfer_record::Direction {
+    fn from(direction: TransferDirection) -> Self {
+        return match direction {
+            TransferDirection::Sent => transfer_record::Direction::Sent,
+            TransferDirection::Received => transfer_record::Direction::Received,
+        };
+    }
+}
+
+impl From<transfer_record::Direction> for TransferDirection {
+    fn from(direction: transfer_record::Direction) -> Self {
+        return match direction {
+            transfer_record::Direction::Sent => TransferDirection::Sent,
+            transfer_record::Direction::Received => TransferDirection::Received,
+        };
+    }
+}
+
+impl From<TransferOutcome> for transfer_record::Outcome {
+    fn from(outcome: TransferOutcome) -> Self {
+        return match outcome {
+            TransferOutcome::Completed => transfer_record::Outcome::Completed,
+            TransferOutcome::Declined => transfer_record::Outcome::Declined,
+            TransferOutcome::Cancelled => transfer_record::Outcome::Cancelled,
+            TransferOutcome::Interrupted => transfer_record::Outcome::Interrupted,
+            TransferOutcome::IntegrityCheckFailed => transfer_record::Outcome::IntegrityCheckFailed,
+            TransferOutcome::Failed => transfer_record::Outcome::Failed,
+        };
+    }
+}
+
+impl From<transfer_record::Outcome> for TransferOutcome {
+    fn from(outcome: transfer_record::Outcome) -> Self {
+        return match outcome {
+            transfer_record::Outcome::Completed => TransferOutcome::Completed,
+            transfer_record::Outcome::Declined => TransferOutcome::Declined,
+            transfer_record::Outcome::Cancelled => TransferOutcome::Cancelled,
+            transfer_record::Outcome::Interrupted => TransferOutcome::Interrupted,
+            transfer_record::Outcome::IntegrityCheckFailed => TransferOutcome::IntegrityCheckFailed,
+            transfer_record::Outcome::Failed => TransferOutcome::Failed,
+        };
+    }
+}
+
+impl From<TransferRecord> for StoredTransferRecord {
+    fn from(record: TransferRecord) -> Self {
+        let medium = match record.medium {
+            Some(ConnectionMedium::BLE) => transfer_record::Medium::Ble,
+            Some(ConnectionMedium::WiFi) => transfer_record::Medium::Wifi,
+            None => transfer_record::Medium::Unknown,
+        };
+
+        return StoredTransferRecord {
+            id: record.id,
+            direction: transfer_record::Direction::from(record.direction) as i32,
+            peer: Some(record.peer),
+            files: record.files,
+            total_size: record.total_size,
+            medium: medium as i32,
+            started_at: record.started_at,
+            duration_millis: record.duration_millis,
+            outcome: transfer_record::Outcome::from(record.outcome) as i32,
+            error: record.error,
+        };
+    }
+}
+
+impl From<StoredTransferRecord> for TransferRecord {
+    fn from(record: StoredTransferRecord) -> Self {
+        let medium = match record.medium() {
+            transfer_record::Medium::Ble => Some(ConnectionMedium::BLE),
+            transfer_record::Medium::Wifi => Some(ConnectionMedium::WiFi),
+            transfer_record::Medium::Unknown => None,
+        };
+
+        return TransferRecord {
+            direction: record.direction().into(),
+            outcome: record.outcome().into(),
+            medium,
+            id: record.id,
+            peer: record.peer.unwrap_or_default(),
+            files: record.files,
+            total_size: record.total_size,
+            started_at: record.started_at,
+            duration_millis: record.duration_millis,
+            error: record.error,
+        };
+    }
+}
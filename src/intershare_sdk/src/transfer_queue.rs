@@ -0,0 +1,237 @@
+//! Queue of outgoing file transfers.
+//!
+//! Queued transfers are started by priority, and in the order they were added otherwise, as
+//! long as the number of active transfers stays within the overall and the per device limit.
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+
+use uuid::Uuid;
+
+use crate::errors::ConnectErrors;
+use crate::nearby::SendProgressDelegate;
+use crate::Device;
+
+pub const DEFAULT_MAX_ACTIVE_TRANSFERS: u32 = 3;
+pub const DEFAULT_MAX_ACTIVE_TRANSFERS_PER_DEVICE: u32 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TransferPriority {
+    Low,
+    Normal,
+    High,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QueuedTransferState {
+    Queued,
+    Active,
+    Done,
+    Failed,
+}
+
+#[derive(Clone, Debug)]
+pub struct QueuedTransfer {
+    pub id: String,
+    pub receiver: Device,
+    pub file_paths: Vec<String>,
+    pub priority: TransferPriority,
+    pub state: QueuedTransferState,
+    /// Why a `Failed` transfer failed.
+    pub error: Option<String>,
+}
+
+pub trait TransferQueueDelegate: Send + Sync + Debug {
+    fn queue_changed(&self, transfers: Vec<QueuedTransfer>);
+}
+
+/// Transfer handed out by [`TransferQueue::start_next`], to be sent right away.
+pub struct ScheduledTransfer {
+    pub id: String,
+    pub receiver: Device,
+    pub file_paths: Vec<String>,
+    pub progress_delegate: Option<Box<dyn SendProgressDelegate>>,
+}
+
+struct QueueEntry {
+    transfer: QueuedTransfer,
+    progress_delegate: Option<Box<dyn SendProgressDelegate>>,
+}
+
+pub struct TransferQueue {
+    entries: Vec<QueueEntry>,
+    max_active: u32,
+    max_active_per_device: u32,
+    delegate: Option<Arc<dyn TransferQueueDelegate>>,
+}
+
+impl Default for TransferQueue {
+    fn default() -> Self {
+        return Self {
+            entries: Vec::new(),
+            max_active: DEFAULT_MAX_ACTIVE_TRANSFERS,
+            max_active_per_device: DEFAULT_MAX_ACTIVE_TRANSFERS_PER_DEVICE,
+            delegate: None,
+        };
+    }
+}
+
+impl TransferQueue {
+    /// Adds a transfer and returns its id.
+    pub fn enqueue(
+        &mut self,
+        receiver: Device,
+        file_paths: Vec<String>,
+        priority: TransferPriority,
+        progress_delegate: Option<Box<dyn SendProgressDelegate>>,
+    ) -> String {
+        let id = Uuid::new_v4().to_string();
+
+        self.entries.push(QueueEntry {
+            transfer: QueuedTransfer {
+                id: id.clone(),
+                receiver,
+                file_paths,
+                priority,
+                state: QueuedTransferState::Queued,
+                error: None,
+            },
+            progress_delegate,
+        });
+
+        return id;
+    }
+
+    pub fn set_max_active(&mut self, transfers: u32) {
+        self.max_active = transfers.max(1);
+    }
+
+    pub fn set_max_active_per_device(&mut self, transfers: u32) {
+        self.max_active_per_device = transfers.max(1);
+    }
+
+    pub fn set_delegate(&mut self, delegate: Option<Box<dyn TransferQueueDelegate>>) {
+        self.delegate = delegate.map(Arc::from);
+    }
+
+    pub fn delegate(&self) -> Option<Arc<dyn TransferQueueDelegate>> {
+        return self.delegate.clone();
+    }
+
+    /// All transfers in the order they were added.
+    pub fn transfers(&self) -> Vec<QueuedTransfer> {
+        return self
+            .entries
+            .iter()
+            .map(|entry| entry.transfer.clone())
+            .collect();
+    }
+
+    /// Removes a transfer that isn't active. Returns whether a transfer was removed.
+    pub fn remove(&mut self, id: &str) -> bool {
+        let Some(index) = self.entries.iter().position(|entry| {
+            entry.transfer.id == id && entry.transfer.state != QueuedTransferState::Active
+        }) else {
+            return false;
+        };
+
+        self.entries.remove(index);
+
+        return true;
+    }
+
+    /// Removes all transfers that are done or failed.
+    pub fn clear_finished(&mut self) {
+        self.entries.retain(|entry| {
+            matches!(
+                entry.transfer.state,
+                QueuedTransferState::Queued | QueuedTransferState::Active
+            )
+        });
+    }
+
+    /// Marks the queued transfers that the limits allow to start as active and hands them out.
+    pub fn start_next(&mut self) -> Vec<ScheduledTransfer> {
+        let mut active_per_device: HashMap<String, u32> = HashMap::new();
+
+        for entry in &self.entries {
+            if entry.transfer.state == QueuedTransferState::Active {
+                *active_per_device
+                    .entry(entry.transfer.receiver.id.clone())
+                    .or_default() += 1;
+            }
+        }
+
+        let mut active: u32 = active_per_device.values().sum();
+
+        let mut queued: Vec<usize> = (0..self.entries.len())
+            .filter(|index| self.entries[*index].transfer.state == QueuedTransferState::Queued)
+            .collect();
+
+        // Stable, so transfers of the same priority keep their order.
+        queued.sort_by_key(|index| std::cmp::Reverse(self.entries[*index].transfer.priority));
+
+        let mut scheduled = Vec::new();
+
+        for index in queued {
+            if active >= self.max_active {
+                break;
+            }
+
+            let entry = &mut self.entries[index];
+            let device_active = active_per_device
+                .entry(entry.transfer.receiver.id.clone())
+                .or_default();
+
+            if *device_active >= self.max_active_per_device {
+                continue;
+            }
+
+            *device_active += 1;
+            active += 1;
+            entry.transfer.state = QueuedTransferState::Active;
+
+            scheduled.push(ScheduledTransfer {
+                id: entry.transfer.id.clone(),
+                receiver: entry.transfer.receiver.clone(),
+                file_paths: entry.transfer.file_paths.clone(),
+                progress_delegate: entry.progress_delegate.take(),
+            });
+        }
+
+        return scheduled;
+    }
+
+    /// Records the outcome of an active transfer.
+    pub fn finish(&mut self, id: &str, result: &Result<(), ConnectErrors>) {
+        let Some(entry) = self
+            .entries
+            .iter_mut()
+            .find(|entry| entry.transfer.id == id)
+        else {
+            return;
+        };
+
+        match result {
+            Ok(()) => entry.transfer.state = QueuedTransferState::Done,
+            Err(error) => {
+                entry.transfer.state = QueuedTransferState::Failed;
+                entry.transfer.error = Some(error.to_string());
+            }
+        }
+    }
+}
+
+/// Informs the delegate about the current queue. It's called without holding the lock, so the
+/// delegate may use the queue again.
+pub fn notify_queue_changed(queue: &Mutex<TransferQueue>) {
+    let (transfers, delegate) = {
+        let queue = queue.lock().expect("Failed to lock transfer queue");
+        (queue.transfers(), queue.delegate())
+    };
+
+    if let Some(delegate) = delegate {
+        delegate.queue_changed(transfers);
+    }
+}
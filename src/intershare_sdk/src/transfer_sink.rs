@@ -0,0 +1,168 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::io::{self, Write};
+
+use protocol::communication::{FileManifestEntry, TransferResult};
+
+/// Takes the received files instead of the `file_storage` directory, for example to put them
+/// into a photo library, a database or memory.
+///
+/// Files arrive one after another: each one is opened, written in order, and then either
+/// finished or discarded. Finished files are checked against the hashes of the sender at the end
+/// of the transfer.
+pub trait TransferSink: Send + Sync + Debug {
+    /// A file of `size` bytes starts. `path` is relative to the transfer, with components
+    /// separated by `/`. Returning `false` fails the transfer.
+    fn open_file(&self, path: String, size: u64) -> bool;
+
+    /// Next piece of the open file. Returning `false` fails the transfer.
+    fn write(&self, path: String, data: Vec<u8>) -> bool;
+
+    /// The file is complete.
+    fn finish_file(&self, path: String) -> bool;
+
+    /// The transfer failed while writing the file, or, once all files arrived, the file turned out
+    /// not to match the hash of the sender. It may have been finished already.
+    fn discard_file(&self, path: String);
+}
+
+/// Hands received files to a [`TransferSink`], and checks them against the hashes of the sender
+/// once all of them arrived.
+pub(crate) struct SinkReceiver<'a> {
+    sink: &'a dyn TransferSink,
+    manifest: &'a [FileManifestEntry],
+    received: Vec<String>,
+    /// Hashes of the finished files, by path.
+    hashes: HashMap<String, Vec<u8>>,
+}
+
+impl<'a> SinkReceiver<'a> {
+    pub(crate) fn new(sink: &'a dyn TransferSink, manifest: &'a [FileManifestEntry]) -> Self {
+        return Self {
+            sink,
+            manifest,
+            received: vec![],
+            hashes: HashMap::new(),
+        };
+    }
+
+    pub(crate) fn manifest(&self) -> &'a [FileManifestEntry] {
+        return self.manifest;
+    }
+
+    /// Size of the file according to the manifest, `0` if it isn't listed.
+    pub(crate) fn announced_size(&self, path: &str) -> u64 {
+        return self
+            .manifest
+            .iter()
+            .find(|entry| entry.path == path)
+            .map(|entry| entry.size)
+            .unwrap_or(0);
+    }
+
+    pub(crate) fn open_file(&self, path: &str, size: u64) -> io::Result<SinkFile<'a>> {
+        if !self.sink.open_file(path.to_string(), size) {
+            return Err(io::Error::other(format!("Sink refused to open {}", path)));
+        }
+
+        return Ok(SinkFile {
+            sink: self.sink,
+            path: path.to_string(),
+            hasher: blake3::Hasher::new(),
+            closed: false,
+        });
+    }
+
+    pub(crate) fn finish_file(&mut self, mut file: SinkFile) -> io::Result<()> {
+        file.closed = true;
+
+        if !self.sink.finish_file(file.path.clone()) {
+            return Err(io::Error::other(format!(
+                "Sink failed to finish {}",
+                file.path
+            )));
+        }
+
+        self.received.push(file.path.clone());
+        self.hashes.insert(
+            file.path.clone(),
+            file.hasher.finalize().as_bytes().to_vec(),
+        );
+
+        return Ok(());
+    }
+
+    /// Discards a file that has already been finished.
+    pub(crate) fn discard_file(&mut self, path: &str) {
+        self.sink.discard_file(path.to_string());
+        self.received.retain(|received_path| received_path != path);
+        self.hashes.remove(path);
+    }
+
+    /// Paths of the files that arrived intact, and the result for the sender. `expected_hashes`
+    /// are the hashes the sender sent along, in the order of the manifest. Files that don't match
+    /// are discarded, files of the manifest that never arrived count as corrupt.
+    pub(crate) fn finish(mut self, expected_hashes: &[Vec<u8>]) -> (Vec<String>, TransferResult) {
+        let mut result = TransferResult {
+            verified: !self.manifest.is_empty(),
+            corrupt_files: vec![],
+        };
+
+        for (index, entry) in self.manifest.iter().enumerate() {
+            let Some(hash) = self.hashes.get(&entry.path) else {
+                result.verified = false;
+                result.corrupt_files.push(entry.path.clone());
+                continue;
+            };
+
+            match expected_hashes.get(index) {
+                Some(expected_hash) if expected_hash != hash => {
+                    println!("File does not match its hash: {:?}", entry.path);
+                    self.sink.discard_file(entry.path.clone());
+                    self.received.retain(|path| *path != entry.path);
+                    result.verified = false;
+                    result.corrupt_files.push(entry.path.clone());
+                }
+                Some(_) => {}
+                None => result.verified = false,
+            }
+        }
+
+        return (self.received, result);
+    }
+}
+
+/// File open in a sink. Dropping it before it's finished discards it.
+pub(crate) struct SinkFile<'a> {
+    sink: &'a dyn TransferSink,
+    path: String,
+    hasher: blake3::Hasher,
+    closed: bool,
+}
+
+impl Write for SinkFile<'_> {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        if !self.sink.write(self.path.clone(), buffer.to_vec()) {
+            return Err(io::Error::other(format!(
+                "Sink failed to write {}",
+                self.path
+            )));
+        }
+
+        self.hasher.update(buffer);
+
+        return Ok(buffer.len());
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        return Ok(());
+    }
+}
+
+impl Drop for SinkFile<'_> {
+    fn drop(&mut self) {
+        if !self.closed {
+            self.sink.discard_file(self.path.clone());
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use std::fmt::Debug;
+use std::fs::{self, File};
+use std::io::{self, Cursor, Read, Seek, SeekFrom};
+use std::path::PathBuf;
+
+use crate::convert_os_str;
+use crate::errors::TransferSourceError;
+
+/// Data to send that doesn't have to be a file on disk, like an Android content URI, an iOS
+/// security-scoped resource or a buffer in memory.
+pub trait TransferSource: Send + Sync + Debug {
+    /// Name of the received file. Components separated by `/` place it in directories.
+    fn get_name(&self) -> String;
+
+    /// Exact size of the data in bytes. An error keeps the transfer from starting.
+    fn get_size(&self) -> Result<u64, TransferSourceError>;
+
+    /// Reads up to `length` bytes at `offset`. Returning less is fine, returning nothing means
+    /// the end was reached. An error fails the transfer. Parallel transfers call this from
+    /// several threads at once.
+    fn read_at(&self, offset: u64, length: u64) -> Result<Vec<u8>, TransferSourceError>;
+
+    /// Stream of the data starting at `offset`, reading through [`TransferSource::read_at`]
+    /// unless a source has a stream of its own.
+    fn open(&self, offset: u64) -> io::Result<Box<dyn Read + '_>> {
+        return Ok(Box::new(SourceReader {
+            source: self,
+            offset,
+        }));
+    }
+}
+
+struct SourceReader<'a, S: TransferSource + ?Sized> {
+    source: &'a S,
+    offset: u64,
+}
+
+impl<S: TransferSource + ?Sized> Read for SourceReader<'_, S> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let data = self.source.read_at(self.offset, buffer.len() as u64)?;
+
+        let length = std::cmp::min(buffer.len(), data.len());
+        buffer[..length].copy_from_slice(&data[..length]);
+        self.offset += length as u64;
+
+        return Ok(length);
+    }
+}
+
+/// File on disk.
+#[derive(Debug)]
+pub struct FileSource {
+    path: PathBuf,
+}
+
+impl FileSource {
+    pub fn new(path: PathBuf) -> Self {
+        return Self { path };
+    }
+}
+
+impl TransferSource for FileSource {
+    fn get_name(&self) -> String {
+        return self
+            .path
+            .file_name()
+            .and_then(convert_os_str)
+            .unwrap_or_default();
+    }
+
+    fn get_size(&self) -> Result<u64, TransferSourceError> {
+        return Ok(fs::metadata(&self.path)?.len());
+    }
+
+    fn read_at(&self, offset: u64, length: u64) -> Result<Vec<u8>, TransferSourceError> {
+        let mut data = vec![];
+        self.open(offset)?.take(length).read_to_end(&mut data)?;
+
+        return Ok(data);
+    }
+
+    fn open(&self, offset: u64) -> io::Result<Box<dyn Read + '_>> {
+        let mut file = File::open(&self.path)?;
+        file.seek(SeekFrom::Start(offset))?;
+
+        return Ok(Box::new(file));
+    }
+}
+
+/// Buffer in memory, sent as a file called `name`.
+#[derive(Debug)]
+pub struct BytesSource {
+    name: String,
+    data: Vec<u8>,
+}
+
+impl BytesSource {
+    pub fn new(name: String, data: Vec<u8>) -> Self {
+        return Self { name, data };
+    }
+}
+
+impl TransferSource for BytesSource {
+    fn get_name(&self) -> String {
+        return self.name.clone();
+    }
+
+    fn get_size(&self) -> Result<u64, TransferSourceError> {
+        return Ok(self.data.len() as u64);
+    }
+
+    fn read_at(&self, offset: u64, length: u64) -> Result<Vec<u8>, TransferSourceError> {
+        let start = std::cmp::min(offset, self.data.len() as u64) as usize;
+        let end = std::cmp::min(start as u64 + length, self.data.len() as u64) as usize;
+
+        return Ok(self.data[start..end].to_vec());
+    }
+
+    fn open(&self, offset: u64) -> io::Result<Box<dyn Read + '_>> {
+        let mut cursor = Cursor::new(self.data.as_slice());
+        cursor.set_position(offset);
+
+        return Ok(Box::new(cursor));
+    }
+}
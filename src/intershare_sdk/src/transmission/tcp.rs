@@ -1,27 +1,22 @@
-use prost_stream::Stream;
-use protocol::communication::TransferRequest;
 use std::net::SocketAddr;
 use std::net::{TcpListener, TcpStream};
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 use std::time::Duration;
 use std::{io, thread};
+use tokio::sync::RwLock;
 
-use crate::communication::initiate_receiver_communication;
-use crate::connection_request::ConnectionRequest;
-use crate::nearby::NearbyConnectionDelegate;
+use crate::nearby::{ConnectionMedium, NearbyServer, NearbyServerLockedVariables};
 use crate::stream::Close;
 
 pub struct TcpServer {
     pub port: u16,
     listener: TcpListener,
-    delegate: Arc<Mutex<Box<dyn NearbyConnectionDelegate>>>,
-    file_storage: String,
+    variables: Arc<RwLock<NearbyServerLockedVariables>>,
 }
 
 impl TcpServer {
     pub(crate) async fn new(
-        delegate: Arc<Mutex<Box<dyn NearbyConnectionDelegate>>>,
-        file_storage: String,
+        variables: Arc<RwLock<NearbyServerLockedVariables>>,
     ) -> Result<TcpServer, io::Error> {
         let addresses = [
             SocketAddr::from(([0, 0, 0, 0], 80)),
@@ -38,48 +33,30 @@ impl TcpServer {
         return Ok(Self {
             port,
             listener,
-            delegate,
-            file_storage,
+            variables,
         });
     }
 
     pub fn start_loop(&self) {
         let listener = self.listener.try_clone().expect("Failed to clone listener");
-        let delegate = self.delegate.clone();
-        let file_storage = self.file_storage.clone();
+        let variables = self.variables.clone();
 
         thread::spawn(move || loop {
             let Ok((tcp_stream, _socket_address)) = listener.accept() else {
                 continue;
             };
 
-            let mut encrypted_stream = match initiate_receiver_communication(tcp_stream) {
-                Ok(request) => request,
-                Err(error) => {
-                    println!("Encryption error {:}", error);
-                    continue;
-                }
-            };
-
-            let mut prost_stream = Stream::new(&mut encrypted_stream);
-            let transfer_request = match prost_stream.recv::<TransferRequest>() {
-                Ok(message) => message,
-                Err(error) => {
-                    println!("Error {:}", error);
-                    continue;
-                }
-            };
-
-            let connection_request = ConnectionRequest::new(
-                transfer_request,
-                Box::new(encrypted_stream),
-                file_storage.clone(),
-            );
+            let variables = variables.clone();
 
-            delegate
-                .lock()
-                .expect("Failed to lock")
-                .received_connection_request(Arc::new(connection_request));
+            // Connections are handled concurrently, additional connections of a transfer join
+            // it while it's running.
+            thread::spawn(move || {
+                NearbyServer::receive_connection_request(
+                    &variables,
+                    tcp_stream,
+                    ConnectionMedium::WiFi,
+                );
+            });
         });
     }
 }
@@ -0,0 +1,179 @@
+use std::path::{Path, PathBuf};
+
+use protocol::discovery::Device;
+use protocol::storage::TrustStoreContents;
+pub use protocol::storage::TrustedDevice;
+
+use crate::errors::StorageError;
+use crate::storage::{load_message, save_message, unix_timestamp};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrustStatus {
+    /// The device has never been pinned.
+    Unknown,
+    /// The identity key matches the one pinned on first use.
+    Trusted,
+    /// A pinned device with the same id or name uses a different identity key. Either the device
+    /// was reset, or someone is trying to impersonate it.
+    KeyChanged,
+    /// The user explicitly revoked the trust in this device.
+    Revoked,
+}
+
+/// Trust-on-first-use store of peer identity keys.
+///
+/// A device is pinned the first time the user accepts a transfer from it or confirms its
+/// verification code. Without a storage path, pins only live as long as the store itself.
+pub struct TrustStore {
+    path: Option<PathBuf>,
+    contents: TrustStoreContents,
+}
+
+impl TrustStore {
+    pub fn in_memory() -> Self {
+        Self {
+            path: None,
+            contents: TrustStoreContents::default(),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, StorageError> {
+        let contents = load_message::<TrustStoreContents>(path)?.unwrap_or_default();
+
+        return Ok(Self {
+            path: Some(path.to_path_buf()),
+            contents,
+        });
+    }
+
+    pub fn get_devices(&self) -> Vec<TrustedDevice> {
+        return self.contents.devices.clone();
+    }
+
+    pub fn get_device(&self, device_id: &str) -> Option<&TrustedDevice> {
+        return self
+            .contents
+            .devices
+            .iter()
+            .find(|device| device.device_id == device_id);
+    }
+
+    pub fn status(&self, device: &Device, identity_public_key: &[u8]) -> TrustStatus {
+        if let Some(pinned) = self.get_device(&device.id) {
+            if pinned.identity_public_key != identity_public_key {
+                return TrustStatus::KeyChanged;
+            }
+
+            if pinned.revoked {
+                return TrustStatus::Revoked;
+            }
+
+            return TrustStatus::Trusted;
+        }
+
+        let name_conflict = self.contents.devices.iter().any(|pinned| {
+            !pinned.revoked
+                && pinned.name == device.name
+                && pinned.identity_public_key != identity_public_key
+        });
+
+        if name_conflict {
+            return TrustStatus::KeyChanged;
+        }
+
+        return TrustStatus::Unknown;
+    }
+
+    /// Pins the device on first use. An existing pin is never replaced here, see [`Self::trust`].
+    pub fn pin(&mut self, device: &Device, identity_public_key: &[u8]) -> Result<(), StorageError> {
+        match self.get_device(&device.id) {
+            Some(pinned) if pinned.identity_public_key == identity_public_key => self.touch(device),
+            Some(_) => Ok(()),
+            None => {
+                self.insert(device, identity_public_key);
+                self.save()
+            }
+        }
+    }
+
+    /// Trusts the device after the user verified it, replacing an earlier pin or revocation.
+    pub fn trust(
+        &mut self,
+        device: &Device,
+        identity_public_key: &[u8],
+    ) -> Result<(), StorageError> {
+        let first_seen = self.get_device(&device.id).map(|pinned| pinned.first_seen);
+
+        self.contents
+            .devices
+            .retain(|pinned| pinned.device_id != device.id);
+        self.insert(device, identity_public_key);
+
+        if let (Some(first_seen), Some(pinned)) = (first_seen, self.contents.devices.last_mut()) {
+            pinned.first_seen = first_seen;
+        }
+
+        return self.save();
+    }
+
+    /// Revokes the trust in a device. Returns `false` if the device isn't pinned.
+    pub fn revoke(&mut self, device_id: &str) -> Result<bool, StorageError> {
+        let Some(pinned) = self
+            .contents
+            .devices
+            .iter_mut()
+            .find(|device| device.device_id == device_id)
+        else {
+            return Ok(false);
+        };
+
+        pinned.revoked = true;
+        self.save()?;
+
+        return Ok(true);
+    }
+
+    /// Removes every trace of a device, so its next connection counts as a first contact again.
+    pub fn forget(&mut self, device_id: &str) -> Result<(), StorageError> {
+        self.contents
+            .devices
+            .retain(|device| device.device_id != device_id);
+
+        return self.save();
+    }
+
+    fn insert(&mut self, device: &Device, identity_public_key: &[u8]) {
+        let now = unix_timestamp();
+
+        self.contents.devices.push(TrustedDevice {
+            device_id: device.id.clone(),
+            name: device.name.clone(),
+            identity_public_key: identity_public_key.to_vec(),
+            first_seen: now,
+            last_seen: now,
+            revoked: false,
+        });
+    }
+
+    fn touch(&mut self, device: &Device) -> Result<(), StorageError> {
+        if let Some(pinned) = self
+            .contents
+            .devices
+            .iter_mut()
+            .find(|pinned| pinned.device_id == device.id)
+        {
+            pinned.name = device.name.clone();
+            pinned.last_seen = unix_timestamp();
+        }
+
+        return self.save();
+    }
+
+    fn save(&self) -> Result<(), StorageError> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        return save_message(path, &self.contents);
+    }
+}
@@ -1,44 +1,1132 @@
-use std::{fs::File, io::BufReader, path::Path};
+//! ZIP archives, the archive format of peers that don't support framed transfers.
+//!
+//! Archives are written as a stream, every entry followed by a data descriptor. Received archives
+//! are extracted entry by entry as their local headers arrive, the central directory at the end
+//! is only read to reject symbolic links.
 
-use zip::ZipArchive;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::Path;
 
+use flate2::{Decompress, FlushDecompress, Status};
+use time::OffsetDateTime;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, DateTime, ZipWriter};
+
+use crate::compression::{deflate_level, CompressionPolicy};
 use crate::convert_os_str;
+use crate::errors::ExtractionError;
+use crate::extraction::{destination_path, validate_relative_path, ExtractionBudget};
+use crate::manifest::SourceFile;
+use crate::transfer_sink::{SinkFile, SinkReceiver};
 
-pub fn unzip_file(
-    zip_file: File,
-    destination: &str,
-) -> Result<Vec<String>, Box<dyn std::error::Error>> {
-    // Open the zip file
-    let mut archive = ZipArchive::new(BufReader::new(zip_file))?;
-    let mut written_files = vec![];
-
-    // Iterate over the zip file contents
-    for i in 0..archive.len() {
-        let mut file = archive.by_index(i)?;
-        let out_path = Path::new(destination).join(file.name());
-        written_files.push(
-            convert_os_str(out_path.clone().as_os_str())
-                .expect("Failed to convert file path OS string to string"),
-        );
+const BUFFER_SIZE: usize = 32 * 1024;
+
+const LOCAL_HEADER_SIGNATURE: u32 = 0x04034b50;
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x02014b50;
+const END_OF_CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x06054b50;
+const ZIP64_END_OF_CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x06064b50;
+const DATA_DESCRIPTOR_SIGNATURE: u32 = 0x08074b50;
+
+const FLAG_ENCRYPTED: u16 = 1;
+const FLAG_DATA_DESCRIPTOR: u16 = 1 << 3;
+
+const METHOD_STORED: u16 = 0;
+const METHOD_DEFLATED: u16 = 8;
+
+const ZIP64_EXTRA_FIELD: u16 = 0x0001;
+const SYSTEM_UNIX: u16 = 3;
+const S_IFMT: u32 = 0o170000;
+const S_IFLNK: u32 = 0o120000;
+
+fn invalid_archive(message: &str) -> ExtractionError {
+    return io::Error::new(io::ErrorKind::InvalidData, message.to_string()).into();
+}
+
+/// Little endian number of up to 8 bytes.
+fn read_le(bytes: &[u8]) -> u64 {
+    let mut number = [0u8; 8];
+    number[..bytes.len()].copy_from_slice(bytes);
+    return u64::from_le_bytes(number);
+}
+
+/// Reads the archive front to back, keeping enough of it buffered to look ahead of the data.
+struct ArchiveReader<R> {
+    reader: R,
+    buffer: Vec<u8>,
+    start: usize,
+    end: usize,
+}
+
+impl<R: Read> ArchiveReader<R> {
+    fn new(reader: R) -> Self {
+        return Self {
+            reader,
+            buffer: vec![0u8; BUFFER_SIZE],
+            start: 0,
+            end: 0,
+        };
+    }
+
+    /// Buffers at least `length` bytes, fewer only if the archive ends before.
+    fn fill(&mut self, length: usize) -> io::Result<&[u8]> {
+        if self.end - self.start < length {
+            self.buffer.copy_within(self.start..self.end, 0);
+            self.end -= self.start;
+            self.start = 0;
+
+            if self.buffer.len() < length {
+                self.buffer.resize(length, 0);
+            }
+
+            while self.end < length {
+                let read_size = self.reader.read(&mut self.buffer[self.end..])?;
+
+                if read_size == 0 {
+                    break;
+                }
+
+                self.end += read_size;
+            }
+        }
+
+        return Ok(&self.buffer[self.start..self.end]);
+    }
+
+    fn consume(&mut self, length: usize) {
+        self.start += length;
+    }
+
+    fn read_bytes(&mut self, length: usize) -> io::Result<Vec<u8>> {
+        let data = self.fill(length)?;
+
+        if data.len() < length {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Archive ended unexpectedly",
+            ));
+        }
+
+        let bytes = data[..length].to_vec();
+        self.consume(length);
+
+        return Ok(bytes);
+    }
+
+    fn read_u16(&mut self) -> io::Result<u16> {
+        let bytes = self.read_bytes(2)?;
+        return Ok(u16::from_le_bytes([bytes[0], bytes[1]]));
+    }
+
+    fn read_u32(&mut self) -> io::Result<u32> {
+        let bytes = self.read_bytes(4)?;
+        return Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]));
+    }
+
+    fn read_size(&mut self, zip64: bool) -> io::Result<u64> {
+        if !zip64 {
+            return Ok(self.read_u32()? as u64);
+        }
+
+        return Ok(read_le(&self.read_bytes(8)?));
+    }
+
+    /// Reads the rest of the archive, which is of no further interest.
+    fn drain(&mut self) -> io::Result<()> {
+        loop {
+            let length = self.fill(1)?.len();
+
+            if length == 0 {
+                return Ok(());
+            }
+
+            self.consume(length);
+        }
+    }
+}
+
+/// Whether `signature` starts a record that may follow the data of an entry.
+fn is_record_signature(signature: u32) -> bool {
+    return matches!(
+        signature,
+        LOCAL_HEADER_SIGNATURE
+            | CENTRAL_HEADER_SIGNATURE
+            | END_OF_CENTRAL_DIRECTORY_SIGNATURE
+            | ZIP64_END_OF_CENTRAL_DIRECTORY_SIGNATURE
+    );
+}
+
+/// Local header of an entry, which precedes its data.
+struct LocalHeader {
+    name: String,
+    flags: u16,
+    method: u16,
+    crc32: u32,
+    compressed_size: u64,
+    uncompressed_size: u64,
+    /// Whether the sizes are 8 bytes long, in the header as well as in the data descriptor.
+    zip64: bool,
+}
+
+impl LocalHeader {
+    /// Reads the header, whose signature has already been read.
+    fn read<R: Read>(archive: &mut ArchiveReader<R>) -> Result<Self, ExtractionError> {
+        let _version = archive.read_u16()?;
+        let flags = archive.read_u16()?;
+        let method = archive.read_u16()?;
+        let _modified = archive.read_u32()?;
+        let crc32 = archive.read_u32()?;
+        let mut compressed_size = archive.read_u32()? as u64;
+        let mut uncompressed_size = archive.read_u32()? as u64;
+        let name_length = archive.read_u16()? as usize;
+        let extra_field_length = archive.read_u16()? as usize;
+        let name = String::from_utf8_lossy(&archive.read_bytes(name_length)?).to_string();
+        let extra_field = archive.read_bytes(extra_field_length)?;
+
+        if flags & FLAG_ENCRYPTED != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("{} is encrypted", name),
+            )
+            .into());
+        }
+
+        let zip64_sizes = zip64_extra_field(&extra_field);
+
+        if let Some(sizes) = zip64_sizes {
+            if sizes.len() >= 16 {
+                uncompressed_size = read_le(&sizes[..8]);
+                compressed_size = read_le(&sizes[8..16]);
+            }
+        }
+
+        return Ok(Self {
+            name,
+            flags,
+            method,
+            crc32,
+            compressed_size,
+            uncompressed_size,
+            zip64: zip64_sizes.is_some(),
+        });
+    }
+
+    /// Whether the checksum and sizes only follow the data, as the entry was written as a stream.
+    fn has_data_descriptor(&self) -> bool {
+        return self.flags & FLAG_DATA_DESCRIPTOR != 0;
+    }
+
+    fn is_dir(&self) -> bool {
+        return self.name.ends_with('/');
+    }
+}
+
+/// Data of the ZIP64 extra field, if there is one.
+fn zip64_extra_field(mut extra_field: &[u8]) -> Option<&[u8]> {
+    while extra_field.len() >= 4 {
+        let id = u16::from_le_bytes([extra_field[0], extra_field[1]]);
+        let length = u16::from_le_bytes([extra_field[2], extra_field[3]]) as usize;
+        let data = extra_field.get(4..4 + length)?;
+
+        if id == ZIP64_EXTRA_FIELD {
+            return Some(data);
+        }
+
+        extra_field = &extra_field[4 + length..];
+    }
+
+    return None;
+}
+
+/// Checksum and sizes of an entry, as announced by the archive.
+struct EntrySummary {
+    crc32: u32,
+    compressed_size: u64,
+    uncompressed_size: u64,
+}
+
+/// Writes the data of an entry, counting it against the budget.
+struct EntryWriter<'w, 'p, W, F> {
+    file: &'w mut W,
+    hasher: crc32fast::Hasher,
+    size: u64,
+    progress: &'w mut ExtractionProgress<'p, F>,
+}
 
-        if file.name().ends_with('/') {
-            // It's a directory, create it
-            std::fs::create_dir_all(&out_path)?;
+impl<W, F> EntryWriter<'_, '_, W, F>
+where
+    W: Write,
+    F: FnMut(u64),
+{
+    fn write(&mut self, data: &[u8]) -> Result<(), ExtractionError> {
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        self.progress.budget.add_bytes(data.len() as u64)?;
+        self.file.write_all(data)?;
+        self.hasher.update(data);
+        self.size += data.len() as u64;
+
+        self.progress.extracted_bytes += data.len() as u64;
+        (self.progress.progress)(self.progress.extracted_bytes);
+
+        return Ok(());
+    }
+
+    fn crc32(&self) -> u32 {
+        return self.hasher.clone().finalize();
+    }
+}
+
+/// Extracted bytes of the whole archive.
+struct ExtractionProgress<'a, F> {
+    budget: &'a mut ExtractionBudget,
+    extracted_bytes: u64,
+    progress: F,
+}
+
+/// Copies `length` bytes of stored data.
+fn copy_stored<R, W, F>(
+    archive: &mut ArchiveReader<R>,
+    writer: &mut EntryWriter<W, F>,
+    length: u64,
+) -> Result<(), ExtractionError>
+where
+    R: Read,
+    W: Write,
+    F: FnMut(u64),
+{
+    let mut remaining = length;
+
+    while remaining > 0 {
+        let data = archive.fill(1)?;
+
+        if data.is_empty() {
+            return Err(
+                io::Error::new(io::ErrorKind::UnexpectedEof, "Archive ended unexpectedly").into(),
+            );
+        }
+
+        let length = std::cmp::min(data.len() as u64, remaining) as usize;
+        writer.write(&data[..length])?;
+        archive.consume(length);
+        remaining -= length as u64;
+    }
+
+    return Ok(());
+}
+
+/// Copies stored data whose size only the data descriptor after it tells. The data ends at the
+/// first descriptor signature followed by the checksum and size of the data before it, and by the
+/// next record of the archive.
+fn copy_stored_until_descriptor<R, W, F>(
+    archive: &mut ArchiveReader<R>,
+    writer: &mut EntryWriter<W, F>,
+    zip64: bool,
+) -> Result<EntrySummary, ExtractionError>
+where
+    R: Read,
+    W: Write,
+    F: FnMut(u64),
+{
+    let signature = DATA_DESCRIPTOR_SIGNATURE.to_le_bytes();
+    let size_length = if zip64 { 8 } else { 4 };
+    let descriptor_length = 8 + 2 * size_length;
+
+    loop {
+        let data = archive.fill(descriptor_length + 4)?;
+
+        if data.len() < descriptor_length + 4 {
+            return Err(
+                io::Error::new(io::ErrorKind::UnexpectedEof, "Archive ended unexpectedly").into(),
+            );
+        }
+
+        let Some(position) = data.windows(4).position(|window| window == signature) else {
+            // The signature may start in the last bytes.
+            let length = data.len() - 3;
+            writer.write(&data[..length])?;
+            archive.consume(length);
+            continue;
+        };
+
+        if position > 0 {
+            writer.write(&data[..position])?;
+            archive.consume(position);
+            continue;
+        }
+
+        let summary = EntrySummary {
+            crc32: u32::from_le_bytes([data[4], data[5], data[6], data[7]]),
+            compressed_size: read_le(&data[8..8 + size_length]),
+            uncompressed_size: read_le(&data[8 + size_length..descriptor_length]),
+        };
+
+        let next_signature = read_le(&data[descriptor_length..descriptor_length + 4]) as u32;
+
+        if summary.crc32 == writer.crc32()
+            && summary.compressed_size == writer.size
+            && summary.uncompressed_size == writer.size
+            && is_record_signature(next_signature)
+        {
+            archive.consume(descriptor_length);
+            return Ok(summary);
+        }
+
+        // Just data looking like a signature.
+        writer.write(&data[..1])?;
+        archive.consume(1);
+    }
+}
+
+/// Inflates deflated data up to its end, returning the number of compressed bytes.
+fn inflate<R, W, F>(
+    archive: &mut ArchiveReader<R>,
+    writer: &mut EntryWriter<W, F>,
+) -> Result<u64, ExtractionError>
+where
+    R: Read,
+    W: Write,
+    F: FnMut(u64),
+{
+    let mut decompress = Decompress::new(false);
+    let mut buffer = vec![0u8; BUFFER_SIZE];
+    let mut needed_input = 1;
+
+    loop {
+        let data = archive.fill(needed_input)?;
+
+        if data.len() < needed_input {
+            return Err(
+                io::Error::new(io::ErrorKind::UnexpectedEof, "Archive ended unexpectedly").into(),
+            );
+        }
+
+        let total_in = decompress.total_in();
+        let total_out = decompress.total_out();
+        let status = decompress
+            .decompress(data, &mut buffer, FlushDecompress::None)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+
+        let consumed = (decompress.total_in() - total_in) as usize;
+        let produced = (decompress.total_out() - total_out) as usize;
+
+        // Without any progress, the inflater needs more input at once.
+        needed_input = if consumed == 0 && produced == 0 {
+            data.len() + 1
         } else {
-            // It's a file, create the parent directory if needed
-            if let Some(parent) = out_path.parent() {
-                if !parent.exists() {
-                    std::fs::create_dir_all(parent)?;
+            1
+        };
+
+        archive.consume(consumed);
+        writer.write(&buffer[..produced])?;
+
+        if status == Status::StreamEnd {
+            return Ok(decompress.total_in());
+        }
+    }
+}
+
+/// Reads the data descriptor following the data of an entry. Its signature is optional.
+fn read_data_descriptor<R: Read>(
+    archive: &mut ArchiveReader<R>,
+    zip64: bool,
+) -> io::Result<EntrySummary> {
+    let mut crc32 = archive.read_u32()?;
+
+    if crc32 == DATA_DESCRIPTOR_SIGNATURE {
+        crc32 = archive.read_u32()?;
+    }
+
+    return Ok(EntrySummary {
+        crc32,
+        compressed_size: archive.read_size(zip64)?,
+        uncompressed_size: archive.read_size(zip64)?,
+    });
+}
+
+/// Extracts the data of an entry into `file`, and checks it against its checksum and sizes.
+fn extract_entry<R, W, F>(
+    archive: &mut ArchiveReader<R>,
+    header: &LocalHeader,
+    file: &mut W,
+    progress: &mut ExtractionProgress<F>,
+) -> Result<(), ExtractionError>
+where
+    R: Read,
+    W: Write,
+    F: FnMut(u64),
+{
+    let mut writer = EntryWriter {
+        file,
+        hasher: crc32fast::Hasher::new(),
+        size: 0,
+        progress,
+    };
+
+    let summary = match header.method {
+        METHOD_STORED if header.has_data_descriptor() => {
+            copy_stored_until_descriptor(archive, &mut writer, header.zip64)?
+        }
+        METHOD_STORED => {
+            copy_stored(archive, &mut writer, header.compressed_size)?;
+            EntrySummary {
+                crc32: header.crc32,
+                compressed_size: header.compressed_size,
+                uncompressed_size: header.uncompressed_size,
+            }
+        }
+        METHOD_DEFLATED => {
+            let compressed_size = inflate(archive, &mut writer)?;
+            let summary = if header.has_data_descriptor() {
+                read_data_descriptor(archive, header.zip64)?
+            } else {
+                EntrySummary {
+                    crc32: header.crc32,
+                    compressed_size: header.compressed_size,
+                    uncompressed_size: header.uncompressed_size,
+                }
+            };
+
+            if summary.compressed_size != compressed_size {
+                return Err(invalid_archive(
+                    "Compressed size of the entry doesn't match",
+                ));
+            }
+
+            summary
+        }
+        method => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("Unsupported compression method {}", method),
+            )
+            .into())
+        }
+    };
+
+    if summary.uncompressed_size != writer.size {
+        return Err(invalid_archive("Size of the entry doesn't match"));
+    }
+
+    if summary.crc32 != writer.crc32() {
+        return Err(invalid_archive("Invalid checksum"));
+    }
+
+    return Ok(());
+}
+
+/// Reads the central directory, whose first signature has already been read, and returns the
+/// path of the first symbolic link in it.
+fn find_symlink<R: Read>(archive: &mut ArchiveReader<R>) -> io::Result<Option<String>> {
+    loop {
+        let version_made_by = archive.read_u16()?;
+        archive.read_bytes(22)?;
+        let name_length = archive.read_u16()? as usize;
+        let extra_field_length = archive.read_u16()? as usize;
+        let comment_length = archive.read_u16()? as usize;
+        archive.read_bytes(4)?;
+        let external_attributes = archive.read_u32()?;
+        archive.read_bytes(4)?;
+        let name = String::from_utf8_lossy(&archive.read_bytes(name_length)?).to_string();
+        archive.read_bytes(extra_field_length + comment_length)?;
+
+        if version_made_by >> 8 == SYSTEM_UNIX && (external_attributes >> 16) & S_IFMT == S_IFLNK {
+            return Ok(Some(name));
+        }
+
+        if archive.read_u32()? != CENTRAL_HEADER_SIGNATURE {
+            return Ok(None);
+        }
+    }
+}
+
+/// Where the entries of an archive end up.
+trait ArchiveTarget {
+    type File: Write;
+
+    fn create_directory(&mut self, name: &str) -> Result<(), ExtractionError>;
+
+    fn create_file(&mut self, name: &str) -> Result<Self::File, ExtractionError>;
+
+    fn finish_file(&mut self, file: Self::File) -> Result<(), ExtractionError>;
+
+    /// Removes a file that turned out to be a symbolic link.
+    fn remove_file(&mut self, name: &str);
+}
+
+/// Extracts the entries of an archive as they arrive.
+///
+/// `progress` is called with the number of extracted bytes so far. Entries are rejected if they
+/// are symbolic links or exceed the `budget`. Symbolic links are only listed in the central
+/// directory, so they have been written as regular files by then, and are removed again.
+fn extract_archive<R, T, F>(
+    reader: R,
+    target: &mut T,
+    budget: &mut ExtractionBudget,
+    progress: F,
+) -> Result<(), ExtractionError>
+where
+    R: Read,
+    T: ArchiveTarget,
+    F: FnMut(u64),
+{
+    let mut archive = ArchiveReader::new(reader);
+    let mut progress = ExtractionProgress {
+        budget,
+        extracted_bytes: 0,
+        progress,
+    };
+
+    loop {
+        match archive.read_u32()? {
+            LOCAL_HEADER_SIGNATURE => {}
+            CENTRAL_HEADER_SIGNATURE => {
+                if let Some(name) = find_symlink(&mut archive)? {
+                    target.remove_file(&name);
+                    return Err(ExtractionError::SymlinkEntry { path: name });
                 }
+
+                break;
+            }
+            signature if is_record_signature(signature) => break,
+            _ => return Err(invalid_archive("Not a ZIP archive")),
+        }
+
+        progress.budget.add_entry()?;
+
+        let header = LocalHeader::read(&mut archive)?;
+
+        if header.is_dir() {
+            target.create_directory(&header.name)?;
+            extract_entry(&mut archive, &header, &mut io::sink(), &mut progress)?;
+            continue;
+        }
+
+        let mut file = target.create_file(&header.name)?;
+        extract_entry(&mut archive, &header, &mut file, &mut progress)?;
+        target.finish_file(file)?;
+    }
+
+    archive.drain()?;
+    (progress.progress)(progress.extracted_bytes);
+
+    return Ok(());
+}
+
+/// Writes the entries into a directory.
+struct DirectoryTarget<'a> {
+    destination: &'a Path,
+    written_files: Vec<String>,
+}
+
+impl ArchiveTarget for DirectoryTarget<'_> {
+    type File = File;
+
+    fn create_directory(&mut self, name: &str) -> Result<(), ExtractionError> {
+        fs::create_dir_all(destination_path(self.destination, name)?)?;
+        return Ok(());
+    }
+
+    fn create_file(&mut self, name: &str) -> Result<File, ExtractionError> {
+        let out_path = destination_path(self.destination, name)?;
+
+        if let Some(parent) = out_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let file = File::create(&out_path)?;
+
+        println!("Extracting file to {:?}", out_path);
+
+        if let Some(path) = convert_os_str(out_path.as_os_str()) {
+            self.written_files.push(path);
+        }
+
+        return Ok(file);
+    }
+
+    fn finish_file(&mut self, _file: File) -> Result<(), ExtractionError> {
+        return Ok(());
+    }
+
+    fn remove_file(&mut self, name: &str) {
+        let Ok(out_path) = destination_path(self.destination, name) else {
+            return;
+        };
+
+        if let Some(path) = convert_os_str(out_path.as_os_str()) {
+            if self.written_files.contains(&path) {
+                let _ = fs::remove_file(&out_path);
+                self.written_files
+                    .retain(|written_file| *written_file != path);
+            }
+        }
+    }
+}
+
+/// Hands the entries to a sink.
+struct SinkTarget<'r, 'a> {
+    receiver: &'r mut SinkReceiver<'a>,
+}
+
+impl<'a> ArchiveTarget for SinkTarget<'_, 'a> {
+    type File = SinkFile<'a>;
+
+    fn create_directory(&mut self, _name: &str) -> Result<(), ExtractionError> {
+        return Ok(());
+    }
+
+    fn create_file(&mut self, name: &str) -> Result<SinkFile<'a>, ExtractionError> {
+        validate_relative_path(name)?;
+
+        let size = self.receiver.announced_size(name);
+        return Ok(self.receiver.open_file(name, size)?);
+    }
+
+    fn finish_file(&mut self, file: SinkFile<'a>) -> Result<(), ExtractionError> {
+        self.receiver.finish_file(file)?;
+        return Ok(());
+    }
+
+    fn remove_file(&mut self, name: &str) {
+        self.receiver.discard_file(name);
+    }
+}
+
+/// Extracts the files of an archive into `destination`, while it's received.
+///
+/// `progress` is called with the number of extracted bytes so far. Entries are rejected if they'd
+/// end up outside of `destination`, are symbolic links or exceed the `budget`.
+pub(crate) fn unzip_stream<R, F>(
+    reader: R,
+    destination: &str,
+    budget: &mut ExtractionBudget,
+    progress: F,
+) -> Result<Vec<String>, ExtractionError>
+where
+    R: Read,
+    F: FnMut(u64),
+{
+    let destination = Path::new(destination);
+    fs::create_dir_all(destination)?;
+
+    let mut target = DirectoryTarget {
+        destination,
+        written_files: vec![],
+    };
+
+    extract_archive(reader, &mut target, budget, progress)?;
+
+    return Ok(target.written_files);
+}
+
+/// Hands the files of an archive to a sink, while it's received.
+pub(crate) fn unzip_stream_into_sink<R, F>(
+    reader: R,
+    receiver: &mut SinkReceiver,
+    budget: &mut ExtractionBudget,
+    progress: F,
+) -> Result<(), ExtractionError>
+where
+    R: Read,
+    F: FnMut(u64),
+{
+    let mut target = SinkTarget { receiver };
+    return extract_archive(reader, &mut target, budget, progress);
+}
+
+/// Modification time of an entry, `modified` being in seconds since the unix epoch.
+fn entry_time(modified: u64) -> DateTime {
+    return OffsetDateTime::from_unix_timestamp(modified as i64)
+        .ok()
+        .and_then(|time| DateTime::try_from(time).ok())
+        .unwrap_or_default();
+}
+
+/// Streams the files into a new archive written to `writer`.
+///
+/// `progress` is called with the number of uncompressed bytes read from the files so far.
+pub(crate) fn zip_files<W, F>(
+    files: &[SourceFile],
+    writer: W,
+    policy: CompressionPolicy,
+    mut progress: F,
+) -> io::Result<W>
+where
+    W: Write,
+    F: FnMut(u64),
+{
+    let mut archive = ZipWriter::new_stream(writer);
+    let mut read_bytes: u64 = 0;
+    let mut buffer = vec![0u8; BUFFER_SIZE];
+
+    for file in files {
+        let level = deflate_level(policy, &*file.source);
+        let method = match level {
+            Some(_) => CompressionMethod::Deflated,
+            None => CompressionMethod::Stored,
+        };
+
+        // The sizes of a streamed entry aren't known when its header is written.
+        let options = SimpleFileOptions::default()
+            .compression_method(method)
+            .compression_level(level)
+            .last_modified_time(entry_time(file.modified))
+            .large_file(true);
+
+        archive.start_file(file.relative_path.as_str(), options)?;
+        let mut source = file.source.open(0)?;
+
+        loop {
+            let read_size = source.read(&mut buffer)?;
+
+            if read_size == 0 {
+                break;
             }
 
-            // Write the file content
-            let mut outfile = File::create(&out_path)?;
-            std::io::copy(&mut file, &mut outfile)?;
+            archive.write_all(&buffer[..read_size])?;
+            read_bytes += read_size as u64;
+            progress(read_bytes);
         }
+    }
+
+    return Ok(archive.finish()?.into_inner());
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::sync::Arc;
+
+    use tempfile::tempdir;
+    use zip::ZipArchive;
+
+    use super::*;
+    use crate::extraction::ExtractionLimits;
+    use crate::transfer_source::BytesSource;
+
+    fn payload(length: usize) -> Vec<u8> {
+        return (0..length).map(|index| (index % 251) as u8).collect();
+    }
+
+    fn files() -> Vec<(&'static str, Vec<u8>)> {
+        return vec![
+            ("Photos/beach.jpg", payload(200 * 1024)),
+            ("empty.txt", vec![]),
+            ("notes.txt", b"Hello".to_vec()),
+        ];
+    }
+
+    fn archive_of(entries: &[(&str, &[u8])], policy: CompressionPolicy) -> Vec<u8> {
+        let files: Vec<SourceFile> = entries
+            .iter()
+            .map(|(name, content)| {
+                SourceFile::from_source(Arc::new(BytesSource::new(
+                    name.to_string(),
+                    content.to_vec(),
+                )))
+            })
+            .collect::<std::io::Result<_>>()
+            .expect("Failed to collect files");
 
-        println!("Extracted file to {:?}", out_path);
+        return zip_files(&files, Vec::new(), policy, |_| {}).expect("Failed to write archive");
     }
 
-    Ok(written_files)
+    fn stream_archive() -> Vec<u8> {
+        let files = files();
+        let entries: Vec<(&str, &[u8])> = files
+            .iter()
+            .map(|(name, content)| (*name, content.as_slice()))
+            .collect();
+
+        return archive_of(&entries, CompressionPolicy::default());
+    }
+
+    #[test]
+    fn streamed_archives_are_readable_by_other_tools() {
+        let mut archive =
+            ZipArchive::new(Cursor::new(stream_archive())).expect("Failed to open archive");
+
+        assert_eq!(archive.len(), files().len());
+
+        for (name, content) in files() {
+            let mut file = archive.by_name(name).expect("Missing file");
+            let mut read_content = vec![];
+            file.read_to_end(&mut read_content)
+                .expect("Failed to read file");
+
+            assert_eq!(read_content, content);
+        }
+    }
+
+    #[test]
+    fn archives_of_other_tools_are_extracted() {
+        let destination = tempdir().expect("Failed to create temporary directory");
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+
+        for (index, (name, content)) in files().into_iter().enumerate() {
+            let method = if index % 2 == 0 {
+                CompressionMethod::Deflated
+            } else {
+                CompressionMethod::Stored
+            };
+
+            writer
+                .start_file(
+                    name,
+                    SimpleFileOptions::default().compression_method(method),
+                )
+                .expect("Failed to start file");
+            writer.write_all(&content).expect("Failed to write file");
+        }
+
+        let archive = writer
+            .finish()
+            .expect("Failed to finish archive")
+            .into_inner();
+
+        unzip_stream(
+            archive.as_slice(),
+            &destination.path().to_string_lossy(),
+            &mut ExtractionLimits::default().budget(u64::MAX),
+            |_| {},
+        )
+        .expect("Failed to extract archive");
+
+        for (name, content) in files() {
+            let extracted = fs::read(destination.path().join(name)).expect("Missing file");
+            assert_eq!(extracted, content);
+        }
+    }
+
+    #[test]
+    fn streamed_archives_are_extracted() {
+        let destination = tempdir().expect("Failed to create temporary directory");
+        let mut reported_bytes = 0;
+
+        let written_files = unzip_stream(
+            stream_archive().as_slice(),
+            &destination.path().to_string_lossy(),
+            &mut ExtractionLimits::default().budget(u64::MAX),
+            |bytes| reported_bytes = bytes,
+        )
+        .expect("Failed to extract archive");
+
+        assert_eq!(written_files.len(), files().len());
+
+        let total_size: usize = files().iter().map(|(_, content)| content.len()).sum();
+        assert_eq!(reported_bytes, total_size as u64);
+
+        for (name, content) in files() {
+            let extracted = fs::read(destination.path().join(name)).expect("Missing file");
+            assert_eq!(extracted, content);
+        }
+
+        // Only the extracted files are there, the archive isn't buffered.
+        let entries = fs::read_dir(destination.path())
+            .expect("Failed to read destination")
+            .count();
+        assert_eq!(entries, 3);
+    }
+
+    #[test]
+    fn truncated_archives_are_rejected() {
+        let destination = tempdir().expect("Failed to create temporary directory");
+        let archive = stream_archive();
+
+        let result = unzip_stream(
+            &archive[..archive.len() / 2],
+            &destination.path().to_string_lossy(),
+            &mut ExtractionLimits::default().budget(u64::MAX),
+            |_| {},
+        );
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn corrupted_files_are_rejected() {
+        let destination = tempdir().expect("Failed to create temporary directory");
+        let mut archive = archive_of(&[("notes.txt", b"Hello")], CompressionPolicy::Off);
+
+        // Stored entries keep their content as is, change it behind the checksum's back.
+        let position = archive
+            .windows(5)
+            .position(|window| window == b"Hello")
+            .expect("Missing file content");
+        archive[position] = b'J';
+
+        let result = unzip_stream(
+            archive.as_slice(),
+            &destination.path().to_string_lossy(),
+            &mut ExtractionLimits::default().budget(u64::MAX),
+            |_| {},
+        );
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn entries_escaping_the_destination_are_rejected() {
+        let parent = tempdir().expect("Failed to create temporary directory");
+        let destination = parent.path().join("Downloads");
+        fs::create_dir(&destination).expect("Failed to create directory");
+
+        for name in ["../.bashrc", "Photos/../../.bashrc", "/tmp/.bashrc"] {
+            let result = unzip_stream(
+                archive_of(&[(name, b"echo pwned")], CompressionPolicy::Max).as_slice(),
+                &destination.to_string_lossy(),
+                &mut ExtractionLimits::default().budget(u64::MAX),
+                |_| {},
+            );
+
+            assert!(matches!(
+                result,
+                Err(ExtractionError::PathOutsideDestination { .. })
+            ));
+        }
+
+        assert!(!parent.path().join(".bashrc").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn entries_are_not_written_through_symlinks() {
+        let outside = tempdir().expect("Failed to create temporary directory");
+        let destination = tempdir().expect("Failed to create temporary directory");
+
+        std::os::unix::fs::symlink(outside.path(), destination.path().join("Photos"))
+            .expect("Failed to create symlink");
+
+        let result = unzip_stream(
+            archive_of(&[("Photos/beach.jpg", b"photo")], CompressionPolicy::Max).as_slice(),
+            &destination.path().to_string_lossy(),
+            &mut ExtractionLimits::default().budget(u64::MAX),
+            |_| {},
+        );
+
+        assert!(matches!(result, Err(ExtractionError::SymlinkInPath { .. })));
+        assert!(!outside.path().join("beach.jpg").exists());
+    }
+
+    #[test]
+    fn symlink_entries_are_rejected() {
+        let destination = tempdir().expect("Failed to create temporary directory");
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+
+        writer
+            .add_symlink("Photos", "/etc", SimpleFileOptions::default())
+            .expect("Failed to add symlink");
+        writer
+            .start_file("notes.txt", SimpleFileOptions::default())
+            .expect("Failed to start file");
+        writer.write_all(b"Hello").expect("Failed to write file");
+
+        let archive = writer
+            .finish()
+            .expect("Failed to finish archive")
+            .into_inner();
+
+        let result = unzip_stream(
+            archive.as_slice(),
+            &destination.path().to_string_lossy(),
+            &mut ExtractionLimits::default().budget(u64::MAX),
+            |_| {},
+        );
+
+        assert!(matches!(
+            result,
+            Err(ExtractionError::SymlinkEntry { path }) if path == "Photos"
+        ));
+        assert!(fs::symlink_metadata(destination.path().join("Photos")).is_err());
+    }
+
+    #[test]
+    fn entries_are_extracted_as_they_arrive() {
+        let destination = tempdir().expect("Failed to create temporary directory");
+        let archive = stream_archive();
+
+        // The connection drops before the central directory arrives.
+        let central_directory = archive
+            .windows(4)
+            .position(|window| window == CENTRAL_HEADER_SIGNATURE.to_le_bytes())
+            .expect("Missing central directory");
+
+        let result = unzip_stream(
+            &archive[..central_directory],
+            &destination.path().to_string_lossy(),
+            &mut ExtractionLimits::default().budget(u64::MAX),
+            |_| {},
+        );
+
+        assert!(result.is_err());
+
+        for (name, content) in files() {
+            let extracted = fs::read(destination.path().join(name)).expect("Missing file");
+            assert_eq!(extracted, content);
+        }
+    }
+
+    #[test]
+    fn stored_entries_looking_like_data_descriptors_are_extracted() {
+        let destination = tempdir().expect("Failed to create temporary directory");
+        let mut content = DATA_DESCRIPTOR_SIGNATURE.to_le_bytes().to_vec();
+        content.extend_from_slice(&[0u8; 20]);
+        content.extend(DATA_DESCRIPTOR_SIGNATURE.to_le_bytes());
+
+        unzip_stream(
+            archive_of(&[("descriptor.bin", &content)], CompressionPolicy::Off).as_slice(),
+            &destination.path().to_string_lossy(),
+            &mut ExtractionLimits::default().budget(u64::MAX),
+            |_| {},
+        )
+        .expect("Failed to extract archive");
+
+        let extracted = fs::read(destination.path().join("descriptor.bin")).expect("Missing file");
+        assert_eq!(extracted, content);
+    }
+
+    #[test]
+    fn archives_expanding_beyond_the_limits_are_rejected() {
+        let destination = tempdir().expect("Failed to create temporary directory");
+        let zeros = vec![0u8; 10 * 1024 * 1024];
+        let bomb = archive_of(&[("zeros.bin", &zeros)], CompressionPolicy::Max);
+
+        assert!(bomb.len() < 100 * 1024);
+
+        let limits = ExtractionLimits {
+            max_size_ratio: 2.0,
+            max_entries: 2,
+        };
+
+        let result = unzip_stream(
+            bomb.as_slice(),
+            &destination.path().to_string_lossy(),
+            &mut limits.budget(1024 * 1024),
+            |_| {},
+        );
+
+        assert!(matches!(
+            result,
+            Err(ExtractionError::SizeLimitExceeded { limit }) if limit == 2 * 1024 * 1024
+        ));
+
+        let result = unzip_stream(
+            archive_of(
+                &[("a.txt", b"a"), ("b.txt", b"b"), ("c.txt", b"c")],
+                CompressionPolicy::Max,
+            )
+            .as_slice(),
+            &destination.path().to_string_lossy(),
+            &mut limits.budget(1024),
+            |_| {},
+        );
+
+        assert!(matches!(
+            result,
+            Err(ExtractionError::TooManyEntries { limit: 2 })
+        ));
+    }
 }
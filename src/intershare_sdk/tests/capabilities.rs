@@ -0,0 +1,107 @@
+use intershare_sdk::capabilities::{
+    local_capabilities, negotiate_capabilities, negotiate_version, ArchiveFormat, Compression,
+    Intent, PROTOCOL_VERSION,
+};
+use intershare_sdk::communication::initiate_sender_communication;
+use intershare_sdk::errors::ConnectErrors;
+use intershare_sdk::identity::DeviceIdentity;
+use intershare_sdk::protocol::communication::{
+    Capabilities, EncryptionRequest, EncryptionResponse, MessageHeader,
+};
+use prost_stream::Stream;
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+
+fn header(protocol_version: i32, minimum_protocol_version: i32) -> MessageHeader {
+    return MessageHeader {
+        protocol_version,
+        r#type: 0,
+        minimum_protocol_version,
+    };
+}
+
+#[test]
+pub fn highest_common_version_is_picked() {
+    assert_eq!(negotiate_version(&header(3, 2), &header(2, 1)), Some(2));
+    assert_eq!(negotiate_version(&header(2, 1), &header(3, 2)), Some(2));
+    assert_eq!(negotiate_version(&header(3, 3), &header(2, 1)), None);
+    assert_eq!(negotiate_version(&header(2, 1), &header(3, 3)), None);
+}
+
+#[test]
+pub fn highest_common_capabilities_are_picked() {
+    let local = local_capabilities();
+    let remote = Capabilities {
+        intents: vec![Intent::FileTransfer as i32],
+        compression_methods: vec![Compression::None as i32, Compression::Deflate as i32],
+        archive_formats: vec![ArchiveFormat::Zip as i32],
+        max_chunk_size: 1024,
+        max_parallel_connections: 2,
+    };
+
+    let negotiated = negotiate_capabilities(PROTOCOL_VERSION, &local, &remote);
+
+    assert!(negotiated.supports_intent(Intent::FileTransfer));
+    assert!(!negotiated.supports_intent(Intent::Clipboard));
+    assert_eq!(negotiated.compression, Compression::Deflate);
+    assert_eq!(negotiated.archive_format, ArchiveFormat::Zip);
+    assert_eq!(negotiated.max_chunk_size, 1024);
+    assert_eq!(negotiated.max_parallel_connections, 2);
+
+    let negotiated = negotiate_capabilities(PROTOCOL_VERSION, &local, &Capabilities::default());
+
+    assert!(negotiated.intents.is_empty());
+    assert_eq!(negotiated.compression, Compression::None);
+    assert_eq!(negotiated.max_chunk_size, local.max_chunk_size);
+    assert_eq!(negotiated.max_parallel_connections, 1);
+
+    let negotiated = negotiate_capabilities(PROTOCOL_VERSION, &local, &local);
+
+    assert_eq!(negotiated.archive_format, ArchiveFormat::Framed);
+}
+
+#[test]
+pub fn incompatible_receiver_is_reported() {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind listener");
+    let address = listener.local_addr().expect("Failed to get local address");
+
+    let receiver = thread::spawn(move || {
+        let (mut tcp_stream, _) = listener.accept().expect("Failed to accept connection");
+        let mut prost_stream = Stream::new(&mut tcp_stream);
+
+        let request = prost_stream
+            .recv::<EncryptionRequest>()
+            .expect("Failed to read encryption request");
+
+        prost_stream
+            .send(&EncryptionResponse {
+                header: Some(header(1, 1)),
+                ..Default::default()
+            })
+            .expect("Failed to send encryption response");
+
+        request.header.expect("Missing header").protocol_version
+    });
+
+    let tcp_stream = TcpStream::connect(address).expect("Failed to connect");
+    let result = futures::executor::block_on(initiate_sender_communication(
+        tcp_stream,
+        &DeviceIdentity::generate(),
+    ));
+
+    assert_eq!(
+        receiver.join().expect("Receiver thread panicked"),
+        PROTOCOL_VERSION
+    );
+
+    match result {
+        Err(ConnectErrors::IncompatibleProtocolVersion {
+            local_version,
+            remote_version,
+        }) => {
+            assert_eq!(local_version, PROTOCOL_VERSION);
+            assert_eq!(remote_version, 1);
+        }
+        _ => panic!("Expected an incompatible protocol version"),
+    }
+}
@@ -0,0 +1,163 @@
+use intershare_sdk::channel::{
+    is_cancellation, Channel, DataReader, DataWriter, TransferControl, FLOW_CONTROL_WINDOW,
+    TRANSFER_STREAM_ID,
+};
+use intershare_sdk::protocol::communication::message_header::MessageTypes;
+use intershare_sdk::protocol::communication::ProgressAck;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+
+fn connected_pair() -> (TcpStream, TcpStream) {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind listener");
+    let address = listener.local_addr().expect("Failed to get local address");
+
+    let client = TcpStream::connect(address).expect("Failed to connect");
+    let (server, _) = listener.accept().expect("Failed to accept connection");
+
+    return (client, server);
+}
+
+fn payload(length: usize) -> Vec<u8> {
+    return (0..length).map(|index| (index % 251) as u8).collect();
+}
+
+#[test]
+pub fn data_larger_than_the_window_is_transferred() {
+    let (sender_stream, receiver_stream) = connected_pair();
+    let data = payload(3 * FLOW_CONTROL_WINDOW as usize + 17);
+    let expected = data.clone();
+
+    let receiver = thread::spawn(move || {
+        let mut channel = Channel::new(receiver_stream);
+        let control = TransferControl::new();
+        let mut reader = DataReader::new(&mut channel, TRANSFER_STREAM_ID, &control);
+
+        let mut received = Vec::new();
+        reader
+            .read_to_end(&mut received)
+            .expect("Failed to receive data");
+        reader.confirm().expect("Failed to confirm data");
+
+        received
+    });
+
+    let mut channel = Channel::new(sender_stream);
+    let control = TransferControl::new();
+    let mut writer = DataWriter::new(&mut channel, TRANSFER_STREAM_ID, &control);
+
+    writer.write_all(&data).expect("Failed to send data");
+    writer.finish().expect("Receiver did not confirm the data");
+
+    assert_eq!(receiver.join().expect("Receiver thread panicked"), expected);
+}
+
+#[test]
+pub fn trailer_and_result_are_exchanged_at_the_end() {
+    let (sender_stream, receiver_stream) = connected_pair();
+
+    let receiver = thread::spawn(move || {
+        let mut channel = Channel::new(receiver_stream);
+        let control = TransferControl::new();
+        let mut reader = DataReader::new(&mut channel, TRANSFER_STREAM_ID, &control);
+
+        let mut received = Vec::new();
+        reader
+            .read_to_end(&mut received)
+            .expect("Failed to receive data");
+        let trailer = reader.trailer().to_vec();
+        reader
+            .confirm_with(b"result".to_vec())
+            .expect("Failed to confirm data");
+
+        (received, trailer)
+    });
+
+    let mut channel = Channel::new(sender_stream);
+    let control = TransferControl::new();
+    let mut writer = DataWriter::new(&mut channel, TRANSFER_STREAM_ID, &control);
+
+    writer.write_all(b"data").expect("Failed to send data");
+    let result = writer
+        .finish_with(b"trailer".to_vec())
+        .expect("Receiver did not confirm the data");
+
+    assert_eq!(result, b"result");
+    assert_eq!(
+        receiver.join().expect("Receiver thread panicked"),
+        (b"data".to_vec(), b"trailer".to_vec())
+    );
+}
+
+#[test]
+pub fn receiver_cancellation_reaches_the_sender() {
+    let (sender_stream, receiver_stream) = connected_pair();
+    let data = payload(4 * FLOW_CONTROL_WINDOW as usize);
+
+    let receiver = thread::spawn(move || {
+        let mut channel = Channel::new(receiver_stream);
+        let control = TransferControl::new();
+        let mut reader = DataReader::new(&mut channel, TRANSFER_STREAM_ID, &control);
+
+        let mut buffer = [0u8; 1024];
+        reader
+            .read_exact(&mut buffer)
+            .expect("Failed to receive data");
+
+        control.cancel();
+        let error = reader
+            .read(&mut buffer)
+            .expect_err("Read succeeded after cancelling");
+        assert!(is_cancellation(&error));
+
+        // Keep the connection open until the sender is done.
+        let _ = channel.get_mut().read_to_end(&mut Vec::new());
+    });
+
+    let mut channel = Channel::new(sender_stream);
+    let control = TransferControl::new();
+    let mut writer = DataWriter::new(&mut channel, TRANSFER_STREAM_ID, &control);
+
+    let error = writer
+        .write_all(&data)
+        .and_then(|_| writer.finish())
+        .expect_err("Transfer succeeded although the receiver cancelled");
+    assert!(is_cancellation(&error));
+
+    drop(channel);
+    receiver.join().expect("Receiver thread panicked");
+}
+
+#[test]
+pub fn acknowledgements_beyond_the_sent_data_are_rejected() {
+    let (sender_stream, receiver_stream) = connected_pair();
+
+    let receiver = thread::spawn(move || {
+        let mut channel = Channel::new(receiver_stream);
+        channel
+            .send_message(
+                MessageTypes::ProgressAck,
+                TRANSFER_STREAM_ID,
+                &ProgressAck {
+                    received_bytes: u64::MAX,
+                },
+            )
+            .expect("Failed to send acknowledgement");
+
+        // Keep the connection open until the sender is done.
+        let _ = channel.get_mut().read_to_end(&mut Vec::new());
+    });
+
+    let mut channel = Channel::new(sender_stream);
+    let control = TransferControl::new();
+    let mut writer = DataWriter::new(&mut channel, TRANSFER_STREAM_ID, &control);
+
+    writer.write_all(b"data").expect("Failed to send data");
+    let error = writer
+        .finish()
+        .expect_err("Sender accepted an acknowledgement for unsent data");
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+
+    drop(channel);
+    receiver.join().expect("Receiver thread panicked");
+}
@@ -0,0 +1,56 @@
+use intershare_sdk::clipboard::{
+    create_clipboard_intent, is_valid_clipboard_intent, ClipboardRepresentation,
+    MAX_INLINE_CLIPBOARD_SIZE,
+};
+use intershare_sdk::connection_request::DeclineReason;
+use intershare_sdk::protocol::communication::{transfer_request_response, ClipboardEntry};
+
+#[test]
+pub fn large_representations_are_streamed() {
+    let large = vec![0u8; MAX_INLINE_CLIPBOARD_SIZE as usize + 1];
+    let (intent, streamed_data) = create_clipboard_intent(vec![
+        ClipboardRepresentation::plain_text("Text".to_string()),
+        ClipboardRepresentation {
+            mime_type: "image/png".to_string(),
+            data: large.clone(),
+        },
+    ])
+    .expect("Failed to create clipboard intent");
+
+    assert_eq!(intent.clipboard_content, "Text");
+    assert!(!intent.representations[0].streamed);
+    assert!(intent.representations[1].streamed);
+    assert!(intent.representations[1].inline_content.is_empty());
+    assert_eq!(streamed_data, vec![large]);
+    assert!(is_valid_clipboard_intent(&intent));
+}
+
+#[test]
+pub fn oversized_clipboards_are_rejected() {
+    let (mut intent, _) =
+        create_clipboard_intent(vec![]).expect("Failed to create clipboard intent");
+
+    intent.representations.push(ClipboardEntry {
+        mime_type: "image/png".to_string(),
+        size: u64::MAX,
+        inline_content: vec![],
+        streamed: true,
+    });
+    assert!(!is_valid_clipboard_intent(&intent));
+
+    intent.representations[0] = ClipboardEntry {
+        mime_type: "text/plain".to_string(),
+        size: 1,
+        inline_content: b"Too long".to_vec(),
+        streamed: false,
+    };
+    assert!(!is_valid_clipboard_intent(&intent));
+}
+
+#[test]
+pub fn oversized_clipboards_have_their_own_decline_reason() {
+    let reason = transfer_request_response::DeclineReason::from(DeclineReason::TooLarge);
+
+    assert_eq!(reason, transfer_request_response::DeclineReason::TooLarge);
+    assert_eq!(DeclineReason::from(reason), DeclineReason::TooLarge);
+}
@@ -0,0 +1,124 @@
+use intershare_sdk::capabilities::Compression;
+use intershare_sdk::compression::{is_compressed_file, zstd_level, CompressionPolicy};
+use intershare_sdk::extraction::ExtractionLimits;
+use intershare_sdk::framed::{receive_framed_files, send_framed_files};
+use intershare_sdk::manifest::{collect_files, create_manifest};
+use intershare_sdk::transfer_source::{BytesSource, FileSource};
+use std::fs;
+use tempfile::tempdir;
+
+#[test]
+pub fn compressed_files_are_detected() {
+    let directory = tempdir().expect("Failed to create temporary directory");
+
+    let photo = directory.path().join("IMG_0001.JPG");
+    let renamed_png = directory.path().join("image.bin");
+    let renamed_video = directory.path().join("video");
+    let text = directory.path().join("notes.txt");
+
+    fs::write(&photo, b"not really a photo").expect("Failed to write file");
+    fs::write(&renamed_png, [0x89, b'P', b'N', b'G', 0x0D, 0x0A]).expect("Failed to write file");
+    fs::write(&renamed_video, b"\0\0\0\x18ftypmp42").expect("Failed to write file");
+    fs::write(&text, "Hello ".repeat(100)).expect("Failed to write file");
+
+    assert!(is_compressed_file(&photo));
+    assert!(is_compressed_file(&renamed_png));
+    assert!(is_compressed_file(&renamed_video));
+    assert!(!is_compressed_file(&text));
+
+    assert_eq!(
+        zstd_level(
+            CompressionPolicy::Fast,
+            Compression::Zstd,
+            &FileSource::new(photo.clone())
+        ),
+        None
+    );
+    assert_eq!(
+        zstd_level(
+            CompressionPolicy::Max,
+            Compression::Zstd,
+            &FileSource::new(text.clone())
+        ),
+        Some(19)
+    );
+    assert_eq!(
+        zstd_level(
+            CompressionPolicy::Fast,
+            Compression::Deflate,
+            &FileSource::new(text.clone())
+        ),
+        None
+    );
+    assert_eq!(
+        zstd_level(
+            CompressionPolicy::Fast,
+            Compression::Zstd,
+            &BytesSource::new("archive".to_string(), b"PK\x03\x04".to_vec())
+        ),
+        None
+    );
+    assert_eq!(
+        zstd_level(
+            CompressionPolicy::Off,
+            Compression::Zstd,
+            &FileSource::new(text.clone())
+        ),
+        None
+    );
+}
+
+#[test]
+pub fn compressible_files_are_sent_with_zstd() {
+    let source = tempdir().expect("Failed to create temporary directory");
+    let destination = tempdir().expect("Failed to create temporary directory");
+    let content = "The quick brown fox jumps over the lazy dog. ".repeat(10_000);
+
+    fs::write(source.path().join("log.txt"), &content).expect("Failed to write file");
+    fs::write(source.path().join("readme.md"), &content).expect("Failed to write file");
+
+    let files = collect_files(&[source.path().to_string_lossy().to_string()])
+        .expect("Failed to collect files");
+
+    let (uncompressed, _) = send_framed_files(
+        &files,
+        Vec::new(),
+        &[],
+        CompressionPolicy::Off,
+        Compression::Zstd,
+        |_| {},
+    )
+    .expect("Failed to send files");
+
+    let (compressed, _) = send_framed_files(
+        &files,
+        Vec::new(),
+        &[],
+        CompressionPolicy::Fast,
+        Compression::Zstd,
+        |_| {},
+    )
+    .expect("Failed to send files");
+
+    assert!(uncompressed.len() > 2 * content.len());
+    assert!(compressed.len() < content.len() / 10);
+
+    let received_files = receive_framed_files(
+        compressed.as_slice(),
+        &destination.path().to_string_lossy(),
+        &create_manifest(&files),
+        &[],
+        &mut ExtractionLimits::default().budget(u64::MAX),
+        |_| {},
+    )
+    .expect("Failed to receive files");
+
+    assert_eq!(received_files.paths.len(), 2);
+
+    for file in &files {
+        assert_eq!(
+            fs::read_to_string(destination.path().join(&file.relative_path)).expect("Missing file"),
+            content
+        );
+    }
+}
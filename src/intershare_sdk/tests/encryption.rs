@@ -1,12 +1,70 @@
+#![allow(clippy::unused_io_amount)]
+
 use crate::helper::MemoryStream;
 use chacha20::cipher::StreamCipherSeek;
-use intershare_sdk::encryption::{generate_iv, generate_key, EncryptedStream};
+use intershare_sdk::communication::{
+    initiate_receiver_communication, initiate_sender_communication, transcript_hash,
+};
+use intershare_sdk::encryption::{
+    generate_iv, generate_key, AuthenticatedStream, EncryptedStream, HandshakeRole, SessionKeys,
+    AEAD_CHUNK_SIZE, AEAD_STREAM_NONCE_SIZE,
+};
+use intershare_sdk::identity::DeviceIdentity;
 use rand_core::{OsRng, RngCore};
-use std::io::{Read, Write};
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
 use x25519_dalek::{EphemeralSecret, PublicKey};
 
 mod helper;
 
+/// Keys for a stream that reads back what it wrote itself.
+fn loopback_session_keys() -> SessionKeys {
+    let key = generate_key();
+    let mut nonce = [0u8; AEAD_STREAM_NONCE_SIZE];
+    OsRng.fill_bytes(&mut nonce);
+
+    return SessionKeys {
+        send_key: key,
+        send_nonce: nonce,
+        receive_key: key,
+        receive_nonce: nonce,
+    };
+}
+
+fn derive_both_sides() -> (SessionKeys, SessionKeys) {
+    let sender_secret = EphemeralSecret::random_from_rng(OsRng);
+    let sender_public_key = PublicKey::from(&sender_secret);
+
+    let receiver_secret = EphemeralSecret::random_from_rng(OsRng);
+    let receiver_public_key = PublicKey::from(&receiver_secret);
+
+    let salt = generate_iv();
+    let transcript = transcript_hash(
+        sender_public_key.as_bytes(),
+        receiver_public_key.as_bytes(),
+        &salt,
+        &[],
+        &[],
+    );
+
+    let sender_shared_secret = sender_secret.diffie_hellman(&receiver_public_key);
+    let receiver_shared_secret = receiver_secret.diffie_hellman(&sender_public_key);
+
+    return (
+        SessionKeys::derive(
+            sender_shared_secret.as_bytes(),
+            &transcript,
+            HandshakeRole::Sender,
+        ),
+        SessionKeys::derive(
+            receiver_shared_secret.as_bytes(),
+            &transcript,
+            HandshakeRole::Receiver,
+        ),
+    );
+}
+
 #[test]
 pub fn diffie_hellman() {
     let alice_secret = EphemeralSecret::random_from_rng(OsRng);
@@ -106,3 +164,297 @@ pub fn large_stream_encryption() {
 
     assert_eq!(write_data, &decrypted_buffer[..read_bytes]);
 }
+
+#[test]
+pub fn authenticated_stream_encryption() {
+    let memory_stream = MemoryStream::new();
+    let mut encrypted_stream = AuthenticatedStream::new(loopback_session_keys(), memory_stream);
+
+    let mut write_data = vec![0u8; AEAD_CHUNK_SIZE * 2 + 100];
+    OsRng.fill_bytes(&mut write_data);
+
+    encrypted_stream
+        .write_all(&write_data)
+        .expect("Something went wrong, while trying to write to AuthenticatedStream");
+    encrypted_stream
+        .finish()
+        .expect("Failed to finish AuthenticatedStream");
+
+    encrypted_stream.raw_stream.set_position(0);
+
+    let mut encrypted_gibberish = Vec::new();
+    encrypted_stream
+        .raw_stream
+        .read_to_end(&mut encrypted_gibberish)
+        .expect("Error reading memory_stream");
+
+    assert!(encrypted_gibberish.len() > write_data.len());
+
+    encrypted_stream.raw_stream.set_position(0);
+
+    let mut decrypted = Vec::new();
+    encrypted_stream
+        .read_to_end(&mut decrypted)
+        .expect("Something went wrong, while trying to decrypt the stream");
+
+    assert_eq!(write_data, decrypted);
+}
+
+#[test]
+pub fn authenticated_stream_detects_tampering() {
+    let session_keys = loopback_session_keys();
+
+    let mut sending_stream = AuthenticatedStream::new(session_keys.clone(), MemoryStream::new());
+    sending_stream
+        .write_all(&[1, 2, 3, 4, 5, 6])
+        .expect("Something went wrong, while trying to write to AuthenticatedStream");
+
+    sending_stream.raw_stream.set_position(0);
+
+    let mut sealed = Vec::new();
+    sending_stream
+        .raw_stream
+        .read_to_end(&mut sealed)
+        .expect("Error reading memory_stream");
+
+    let last_byte = sealed.len() - 1;
+    sealed[last_byte] ^= 0x01;
+
+    let mut tampered_stream = MemoryStream::new();
+    tampered_stream
+        .write_all(&sealed)
+        .expect("Failed to write memory_stream");
+    tampered_stream.set_position(0);
+
+    let mut receiving_stream = AuthenticatedStream::new(session_keys, tampered_stream);
+    let mut decrypted = [0u8; 6];
+    let error = receiving_stream
+        .read(&mut decrypted)
+        .expect_err("Tampered frame must not be accepted");
+
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+}
+
+/// Seals `data` into frames, finishing the stream if `finish` is set, and returns the raw bytes.
+fn sealed_frames(session_keys: &SessionKeys, data: &[u8], finish: bool) -> Vec<u8> {
+    let mut sending_stream = AuthenticatedStream::new(session_keys.clone(), MemoryStream::new());
+    sending_stream.set_chunk_size(4);
+    sending_stream
+        .write_all(data)
+        .expect("Something went wrong, while trying to write to AuthenticatedStream");
+
+    if finish {
+        sending_stream
+            .finish()
+            .expect("Failed to finish AuthenticatedStream");
+    }
+
+    sending_stream.raw_stream.set_position(0);
+
+    let mut sealed = Vec::new();
+    sending_stream
+        .raw_stream
+        .read_to_end(&mut sealed)
+        .expect("Error reading memory_stream");
+
+    return sealed;
+}
+
+fn open_frames(session_keys: &SessionKeys, sealed: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut memory_stream = MemoryStream::new();
+    memory_stream
+        .write_all(sealed)
+        .expect("Failed to write memory_stream");
+    memory_stream.set_position(0);
+
+    let mut receiving_stream = AuthenticatedStream::new(session_keys.clone(), memory_stream);
+    let mut opened = Vec::new();
+    receiving_stream.read_to_end(&mut opened)?;
+
+    return Ok(opened);
+}
+
+#[test]
+pub fn authenticated_stream_ends_after_the_last_frame() {
+    let session_keys = loopback_session_keys();
+    let mut sealed = sealed_frames(&session_keys, &[1, 2, 3, 4, 5, 6], true);
+
+    // Anything after the last frame is never read.
+    sealed.extend_from_slice(&[0, 0, 0, 0]);
+
+    let opened = open_frames(&session_keys, &sealed).expect("Failed to open frames");
+    assert_eq!(opened, vec![1, 2, 3, 4, 5, 6]);
+}
+
+#[test]
+pub fn authenticated_stream_detects_truncation() {
+    let session_keys = loopback_session_keys();
+
+    // Without the last frame, the stream ends on a frame boundary.
+    let unfinished = sealed_frames(&session_keys, &[1, 2, 3, 4, 5, 6], false);
+    let error = open_frames(&session_keys, &unfinished)
+        .expect_err("Stream without its last frame must not be accepted");
+    assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+
+    // Cutting off the last frame of a finished stream is no different.
+    let finished = sealed_frames(&session_keys, &[1, 2, 3, 4, 5, 6], true);
+    let error = open_frames(&session_keys, &finished[..unfinished.len()])
+        .expect_err("Stream without its last frame must not be accepted");
+    assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+}
+
+#[test]
+pub fn authenticated_stream_detects_forged_last_frame() {
+    let session_keys = loopback_session_keys();
+    let mut sealed = sealed_frames(&session_keys, &[1, 2, 3, 4, 5, 6], false);
+
+    // Flag the first frame as the last one, dropping everything after it.
+    sealed[0] |= 0x80;
+
+    let error =
+        open_frames(&session_keys, &sealed).expect_err("Forged last frame must not be accepted");
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+}
+
+#[test]
+pub fn authenticated_stream_rejects_writes_after_finishing() {
+    let mut encrypted_stream =
+        AuthenticatedStream::new(loopback_session_keys(), MemoryStream::new());
+    encrypted_stream
+        .finish()
+        .expect("Failed to finish AuthenticatedStream");
+
+    let error = encrypted_stream
+        .write_all(&[1, 2, 3])
+        .expect_err("Writing after the last frame must fail");
+    assert_eq!(error.kind(), ErrorKind::BrokenPipe);
+}
+
+#[test]
+pub fn session_keys_are_direction_specific() {
+    let (sender_keys, receiver_keys) = derive_both_sides();
+
+    assert_eq!(sender_keys.send_key, receiver_keys.receive_key);
+    assert_eq!(sender_keys.send_nonce, receiver_keys.receive_nonce);
+    assert_eq!(sender_keys.receive_key, receiver_keys.send_key);
+    assert_eq!(sender_keys.receive_nonce, receiver_keys.send_nonce);
+
+    assert_ne!(sender_keys.send_key, sender_keys.receive_key);
+    assert_ne!(sender_keys.send_nonce, sender_keys.receive_nonce);
+}
+
+#[test]
+pub fn session_directions_never_share_keystream() {
+    let (sender_keys, receiver_keys) = derive_both_sides();
+    let plaintext = vec![7u8; 512];
+
+    let mut sender_stream = AuthenticatedStream::new(sender_keys, MemoryStream::new());
+    let mut receiver_stream = AuthenticatedStream::new(receiver_keys, MemoryStream::new());
+
+    sender_stream
+        .write_all(&plaintext)
+        .expect("Failed to write sender stream");
+    receiver_stream
+        .write_all(&plaintext)
+        .expect("Failed to write receiver stream");
+
+    sender_stream.raw_stream.set_position(0);
+    receiver_stream.raw_stream.set_position(0);
+
+    let mut sender_ciphertext = Vec::new();
+    sender_stream
+        .raw_stream
+        .read_to_end(&mut sender_ciphertext)
+        .expect("Error reading memory_stream");
+
+    let mut receiver_ciphertext = Vec::new();
+    receiver_stream
+        .raw_stream
+        .read_to_end(&mut receiver_ciphertext)
+        .expect("Error reading memory_stream");
+
+    // Same plaintext at the same position must not produce the same ciphertext.
+    assert_eq!(sender_ciphertext.len(), receiver_ciphertext.len());
+    assert_ne!(sender_ciphertext, receiver_ciphertext);
+
+    // A peer can't open its own frames, only those written by the other side.
+    sender_stream.raw_stream.set_position(0);
+    let mut buffer = [0u8; 512];
+    let error = sender_stream
+        .read(&mut buffer)
+        .expect_err("Sender must not be able to decrypt its own direction");
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+
+    let mut forwarded = MemoryStream::new();
+    forwarded
+        .write_all(&sender_ciphertext)
+        .expect("Failed to write memory_stream");
+    forwarded.set_position(0);
+    receiver_stream.raw_stream = forwarded;
+
+    receiver_stream
+        .read_exact(&mut buffer)
+        .expect("Receiver failed to decrypt the sender direction");
+    assert_eq!(buffer.as_slice(), plaintext.as_slice());
+}
+
+#[test]
+pub fn handshake_over_tcp() {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind listener");
+    let address = listener.local_addr().expect("Failed to get local address");
+
+    let sender_identity = DeviceIdentity::generate();
+    let receiver_identity = DeviceIdentity::generate();
+    let sender_public_key = sender_identity.public_key();
+    let receiver_public_key = receiver_identity.public_key();
+
+    let receiver = thread::spawn(move || {
+        let (tcp_stream, _) = listener.accept().expect("Failed to accept connection");
+        let mut session = initiate_receiver_communication(tcp_stream, &receiver_identity)
+            .expect("Receiver handshake failed");
+
+        assert_eq!(session.peer_identity_key, sender_public_key);
+
+        let mut request = [0u8; 4];
+        session
+            .stream
+            .read_exact(&mut request)
+            .expect("Failed to read request");
+        assert_eq!(&request, b"ping");
+
+        session
+            .stream
+            .write_all(b"pong")
+            .expect("Failed to write response");
+
+        session.verification_code()
+    });
+
+    let tcp_stream = TcpStream::connect(address).expect("Failed to connect");
+    let mut session =
+        futures::executor::block_on(initiate_sender_communication(tcp_stream, &sender_identity))
+            .expect("Sender handshake failed");
+
+    assert_eq!(session.peer_identity_key, receiver_public_key);
+
+    session
+        .stream
+        .write_all(b"ping")
+        .expect("Failed to write request");
+
+    let mut response = [0u8; 4];
+    session
+        .stream
+        .read_exact(&mut response)
+        .expect("Failed to read response");
+    assert_eq!(&response, b"pong");
+
+    let receiver_code = receiver.join().expect("Receiver thread panicked");
+    let sender_code = session.verification_code();
+
+    assert_eq!(sender_code, receiver_code);
+    assert_eq!(sender_code.len(), 6);
+    assert!(sender_code
+        .chars()
+        .all(|character| character.is_ascii_digit()));
+}
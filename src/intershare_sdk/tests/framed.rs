@@ -0,0 +1,233 @@
+use intershare_sdk::capabilities::Compression;
+use intershare_sdk::checkpoint::Checkpoint;
+use intershare_sdk::compression::CompressionPolicy;
+use intershare_sdk::errors::ExtractionError;
+use intershare_sdk::extraction::ExtractionLimits;
+use intershare_sdk::framed::{receive_framed_files, send_framed_files};
+use intershare_sdk::manifest::{collect_files, create_manifest, hash_files};
+use intershare_sdk::protocol::communication::FileManifestEntry;
+use std::fs;
+use std::io::ErrorKind;
+use tempfile::tempdir;
+use uuid::Uuid;
+
+fn send_directory() -> (tempfile::TempDir, Vec<FileManifestEntry>, Vec<u8>) {
+    let source = tempdir().expect("Failed to create temporary directory");
+    let album = source.path().join("Album");
+
+    fs::create_dir_all(&album).expect("Failed to create directory");
+    fs::write(album.join("cover.jpg"), vec![7u8; 100 * 1024]).expect("Failed to write file");
+    fs::write(album.join("empty.txt"), b"").expect("Failed to write file");
+
+    let files =
+        collect_files(&[album.to_string_lossy().to_string()]).expect("Failed to collect files");
+    let (stream, _) = send_framed_files(
+        &files,
+        Vec::new(),
+        &[],
+        CompressionPolicy::Fast,
+        Compression::Zstd,
+        |_| {},
+    )
+    .expect("Failed to send files");
+
+    return (source, create_manifest(&files), stream);
+}
+
+#[test]
+pub fn files_are_received_one_by_one() {
+    let (_source, manifest, stream) = send_directory();
+    let destination = tempdir().expect("Failed to create temporary directory");
+    let mut received_bytes = 0;
+
+    let received_files = receive_framed_files(
+        stream.as_slice(),
+        &destination.path().to_string_lossy(),
+        &manifest,
+        &[],
+        &mut ExtractionLimits::default().budget(u64::MAX),
+        |progress| received_bytes = progress.total_bytes,
+    )
+    .expect("Failed to receive files");
+
+    assert_eq!(received_files.paths.len(), 2);
+    assert_eq!(received_bytes, 100 * 1024);
+    assert_eq!(
+        fs::read(destination.path().join("Album").join("cover.jpg")).expect("Missing file"),
+        vec![7u8; 100 * 1024]
+    );
+    assert!(destination.path().join("Album").join("empty.txt").exists());
+}
+
+#[test]
+pub fn truncated_streams_are_rejected() {
+    let (_source, manifest, stream) = send_directory();
+    let destination = tempdir().expect("Failed to create temporary directory");
+
+    let result = receive_framed_files(
+        &stream[..stream.len() - 1],
+        &destination.path().to_string_lossy(),
+        &manifest,
+        &[],
+        &mut ExtractionLimits::default().budget(u64::MAX),
+        |_| {},
+    );
+
+    assert!(result.is_err());
+}
+
+#[test]
+pub fn files_missing_from_the_manifest_are_rejected() {
+    let (_source, manifest, stream) = send_directory();
+
+    let mut renamed = manifest.clone();
+    renamed[0].path = "Album/other.jpg".to_string();
+
+    let mut resized = manifest.clone();
+    resized[0].size += 1;
+
+    for manifest in [renamed, resized, manifest[..1].to_vec()] {
+        let destination = tempdir().expect("Failed to create temporary directory");
+
+        let result = receive_framed_files(
+            stream.as_slice(),
+            &destination.path().to_string_lossy(),
+            &manifest,
+            &[],
+            &mut ExtractionLimits::default().budget(u64::MAX),
+            |_| {},
+        );
+
+        assert!(result.is_err());
+        assert!(!destination.path().join("Album").join("empty.txt").exists());
+    }
+}
+
+#[test]
+pub fn streams_ending_before_every_file_arrived_are_rejected() {
+    let (_source, mut manifest, stream) = send_directory();
+    let destination = tempdir().expect("Failed to create temporary directory");
+
+    manifest.push(FileManifestEntry {
+        path: "Album/missing.txt".to_string(),
+        size: 5,
+        ..Default::default()
+    });
+
+    let result = receive_framed_files(
+        stream.as_slice(),
+        &destination.path().to_string_lossy(),
+        &manifest,
+        &[],
+        &mut ExtractionLimits::default().budget(u64::MAX),
+        |_| {},
+    );
+
+    assert!(matches!(
+        result,
+        Err(ExtractionError::Io(error)) if error.kind() == ErrorKind::UnexpectedEof
+    ));
+}
+
+#[test]
+pub fn interrupted_files_are_resumed() {
+    let source = tempdir().expect("Failed to create temporary directory");
+    let destination = tempdir().expect("Failed to create temporary directory");
+    let content: Vec<u8> = (0..300 * 1024).map(|index| (index % 251) as u8).collect();
+
+    fs::write(source.path().join("video.mp4"), &content).expect("Failed to write file");
+    fs::write(destination.path().join("video.mp4"), &content[..100 * 1024])
+        .expect("Failed to write file");
+
+    let files = collect_files(&[source
+        .path()
+        .join("video.mp4")
+        .to_string_lossy()
+        .to_string()])
+    .expect("Failed to collect files");
+
+    let resume_offsets = [100 * 1024];
+    let (stream, sent_hashes) = send_framed_files(
+        &files,
+        Vec::new(),
+        &resume_offsets,
+        CompressionPolicy::Fast,
+        Compression::Zstd,
+        |_| {},
+    )
+    .expect("Failed to send files");
+
+    assert!(stream.len() < content.len());
+
+    let manifest = create_manifest(&files);
+
+    let received_files = receive_framed_files(
+        stream.as_slice(),
+        &destination.path().to_string_lossy(),
+        &manifest,
+        &resume_offsets,
+        &mut ExtractionLimits::default().budget(u64::MAX),
+        |_| {},
+    )
+    .expect("Failed to receive files");
+
+    assert_eq!(
+        fs::read(destination.path().join("video.mp4")).expect("Missing file"),
+        content
+    );
+
+    // Both sides include the part sent before the interruption in the hash.
+    let expected_hashes = hash_files(&files).expect("Failed to hash files");
+    assert_eq!(sent_hashes, expected_hashes);
+    assert_eq!(received_files.hashes, expected_hashes);
+
+    // The receiver only continues at the offset it reported.
+    let result = receive_framed_files(
+        stream.as_slice(),
+        &destination.path().to_string_lossy(),
+        &manifest,
+        &[],
+        &mut ExtractionLimits::default().budget(u64::MAX),
+        |_| {},
+    );
+    assert!(result.is_err());
+}
+
+#[test]
+pub fn checkpoints_only_resume_the_same_transfer() {
+    let (source, _, _) = send_directory();
+    let destination = tempdir().expect("Failed to create temporary directory");
+    let file_storage = destination.path().to_string_lossy().to_string();
+
+    let files = collect_files(&[source.path().join("Album").to_string_lossy().to_string()])
+        .expect("Failed to collect files");
+    let manifest = create_manifest(&files);
+    let transfer_id = Uuid::new_v4().to_string();
+    let sender_key = [1u8; 32];
+
+    assert!(Checkpoint::new(&file_storage, "../../escape", &sender_key, &manifest).is_none());
+    assert!(Checkpoint::resume(&file_storage, &transfer_id, &sender_key, &manifest).is_none());
+
+    // Only 50 KiB made it to disk, although the checkpoint recorded more.
+    fs::create_dir_all(destination.path().join("Album")).expect("Failed to create directory");
+    fs::write(
+        destination.path().join("Album").join("cover.jpg"),
+        vec![7u8; 50 * 1024],
+    )
+    .expect("Failed to write file");
+
+    let mut checkpoint = Checkpoint::new(&file_storage, &transfer_id, &sender_key, &manifest)
+        .expect("Failed to create checkpoint");
+    checkpoint.update(0, 60 * 1024);
+    checkpoint.save().expect("Failed to save checkpoint");
+
+    let resumed = Checkpoint::resume(&file_storage, &transfer_id, &sender_key, &manifest)
+        .expect("Checkpoint was not resumed");
+    assert_eq!(resumed.resume_offsets(), vec![50 * 1024, 0]);
+
+    assert!(Checkpoint::resume(&file_storage, &transfer_id, &[2u8; 32], &manifest).is_none());
+    assert!(Checkpoint::resume(&file_storage, &transfer_id, &sender_key, &manifest[..1]).is_none());
+
+    resumed.remove();
+    assert!(Checkpoint::resume(&file_storage, &transfer_id, &sender_key, &manifest).is_none());
+}
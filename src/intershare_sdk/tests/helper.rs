@@ -22,6 +22,12 @@ impl MemoryStream {
     }
 }
 
+impl Default for MemoryStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Write for MemoryStream {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
         let written_bytes = self.cursor.write(buf);
@@ -50,7 +56,7 @@ pub fn memory_stream() {
     let mut memory_stream = MemoryStream::new();
 
     memory_stream
-        .write(&[4u8, 5u8, 6u8])
+        .write_all(&[4u8, 5u8, 6u8])
         .expect("Failed to write memory_stream");
 
     memory_stream.set_position(0);
@@ -65,7 +71,7 @@ pub fn memory_stream() {
     // ====
 
     memory_stream
-        .write(&[2u8, 7u8, 9u8])
+        .write_all(&[2u8, 7u8, 9u8])
         .expect("Failed to write memory_stream");
     memory_stream
         .set_position(memory_stream.position() - memory_stream.last_written_byte_length as u64);
@@ -80,11 +86,11 @@ pub fn memory_stream() {
     // ====
 
     memory_stream
-        .write(&[2u8, 7u8, 9u8])
+        .write_all(&[2u8, 7u8, 9u8])
         .expect("Failed to write memory_stream");
 
     memory_stream
-        .write(&[1u8, 2u8, 0u8])
+        .write_all(&[1u8, 2u8, 0u8])
         .expect("Failed to write memory_stream");
 
     memory_stream.set_position(memory_stream.position() - 6);
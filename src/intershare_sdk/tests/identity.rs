@@ -0,0 +1,95 @@
+use intershare_sdk::errors::IdentityError;
+use intershare_sdk::identity::{derive_device_id, verify_signature, DeviceIdentity};
+use intershare_sdk::nearby::NearbyServer;
+use intershare_sdk::Device;
+use std::fs;
+use tempfile::tempdir;
+
+#[test]
+pub fn identity_is_persisted() {
+    let directory = tempdir().expect("Failed to create temporary directory");
+    let key_path = directory.path().join("keys").join("identity.key");
+
+    let created = DeviceIdentity::load_or_create(&key_path).expect("Failed to create identity");
+    let loaded = DeviceIdentity::load_or_create(&key_path).expect("Failed to load identity");
+
+    assert_eq!(created.public_key(), loaded.public_key());
+    assert_eq!(created.device_id(), loaded.device_id());
+}
+
+#[test]
+pub fn device_id_is_bound_to_public_key() {
+    let identity = DeviceIdentity::generate();
+    let other_identity = DeviceIdentity::generate();
+
+    assert_eq!(
+        identity.device_id(),
+        derive_device_id(&identity.public_key())
+    );
+    assert_ne!(identity.device_id(), other_identity.device_id());
+}
+
+#[test]
+pub fn signatures_are_verified() {
+    let identity = DeviceIdentity::generate();
+    let other_identity = DeviceIdentity::generate();
+    let signature = identity.sign(b"transcript");
+
+    assert!(verify_signature(
+        &identity.public_key(),
+        b"transcript",
+        &signature
+    ));
+    assert!(!verify_signature(
+        &identity.public_key(),
+        b"other transcript",
+        &signature
+    ));
+    assert!(!verify_signature(
+        &other_identity.public_key(),
+        b"transcript",
+        &signature
+    ));
+}
+
+fn nearby_server(key_path: &str) -> Result<NearbyServer, IdentityError> {
+    let device = Device {
+        id: "chosen-by-the-app".to_string(),
+        name: "Laptop".to_string(),
+        device_type: 0,
+    };
+
+    return NearbyServer::new(device, String::new(), key_path.to_string(), None);
+}
+
+#[test]
+pub fn nearby_server_keeps_its_device_id_across_restarts() {
+    let directory = tempdir().expect("Failed to create temporary directory");
+    let key_path = directory.path().join("identity.key");
+    let key_path = key_path.to_string_lossy();
+
+    let first_id = nearby_server(&key_path)
+        .expect("Failed to create nearby server")
+        .get_device_id();
+    let second_id = nearby_server(&key_path)
+        .expect("Failed to create nearby server")
+        .get_device_id();
+
+    let identity = DeviceIdentity::load_or_create(directory.path().join("identity.key").as_path())
+        .expect("Failed to load identity");
+
+    assert_eq!(first_id, second_id);
+    assert_eq!(first_id, identity.device_id());
+}
+
+#[test]
+pub fn nearby_server_rejects_invalid_identity_keys() {
+    let directory = tempdir().expect("Failed to create temporary directory");
+    let key_path = directory.path().join("identity.key");
+    fs::write(&key_path, b"not a key").expect("Failed to write key file");
+
+    assert!(matches!(
+        nearby_server(&key_path.to_string_lossy()),
+        Err(IdentityError::InvalidKey)
+    ));
+}
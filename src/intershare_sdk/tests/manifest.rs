@@ -0,0 +1,177 @@
+use intershare_sdk::errors::ExtractionError;
+use intershare_sdk::manifest::{
+    collect_files, create_manifest, hash_files, verify_files, ReceivedFiles,
+};
+use intershare_sdk::protocol::communication::FileManifestEntry;
+use std::fs;
+use std::path::Path;
+use tempfile::tempdir;
+
+/// The files of `manifest`, as if the transfer wrote them to `destination`.
+fn written(
+    destination: &Path,
+    manifest: &[FileManifestEntry],
+    hashes: &[Vec<u8>],
+) -> ReceivedFiles {
+    return ReceivedFiles {
+        paths: manifest
+            .iter()
+            .map(|entry| destination.join(&entry.path).to_string_lossy().to_string())
+            .collect(),
+        hashes: hashes.to_vec(),
+    };
+}
+
+#[test]
+pub fn received_files_are_verified() {
+    let source = tempdir().expect("Failed to create temporary directory");
+    let documents = source.path().join("Documents");
+    fs::create_dir_all(&documents).expect("Failed to create directory");
+    fs::write(documents.join("a.txt"), b"first").expect("Failed to write file");
+    fs::write(documents.join("b.txt"), b"second").expect("Failed to write file");
+
+    let files =
+        collect_files(&[documents.to_string_lossy().to_string()]).expect("Failed to collect files");
+    let hashes = hash_files(&files).expect("Failed to hash files");
+    let manifest = create_manifest(&files);
+
+    assert_eq!(manifest[0].path, "Documents/a.txt");
+    assert_eq!(manifest[0].mime_type, "text/plain");
+
+    let result = verify_files(
+        &source.path().to_string_lossy(),
+        &manifest,
+        &hashes,
+        &written(source.path(), &manifest, &[]),
+    )
+    .expect("Failed to verify files");
+    assert!(result.verified);
+    assert!(result.corrupt_files.is_empty());
+
+    fs::write(documents.join("b.txt"), b"Second").expect("Failed to write file");
+
+    let result = verify_files(
+        &source.path().to_string_lossy(),
+        &manifest,
+        &hashes,
+        &written(source.path(), &manifest, &[]),
+    )
+    .expect("Failed to verify files");
+    assert!(!result.verified);
+    assert_eq!(result.corrupt_files, vec!["Documents/b.txt".to_string()]);
+    assert!(!documents.join("b.txt").exists());
+    assert!(documents.join("a.txt").exists());
+}
+
+#[test]
+pub fn hashes_computed_while_receiving_are_not_recomputed() {
+    let source = tempdir().expect("Failed to create temporary directory");
+    fs::write(source.path().join("a.txt"), b"first").expect("Failed to write file");
+
+    let files = collect_files(&[source.path().join("a.txt").to_string_lossy().to_string()])
+        .expect("Failed to collect files");
+    let hashes = hash_files(&files).expect("Failed to hash files");
+
+    // The file changed after it was written, but the hash of what was received still matches.
+    fs::write(source.path().join("a.txt"), b"First").expect("Failed to write file");
+
+    let manifest = create_manifest(&files);
+    let result = verify_files(
+        &source.path().to_string_lossy(),
+        &manifest,
+        &hashes,
+        &written(source.path(), &manifest, &hashes),
+    )
+    .expect("Failed to verify files");
+
+    assert!(result.verified);
+    assert!(result.corrupt_files.is_empty());
+}
+
+#[test]
+pub fn files_without_hashes_are_not_verified() {
+    let source = tempdir().expect("Failed to create temporary directory");
+    fs::write(source.path().join("a.txt"), b"first").expect("Failed to write file");
+
+    let files = collect_files(&[source.path().join("a.txt").to_string_lossy().to_string()])
+        .expect("Failed to collect files");
+    let manifest = create_manifest(&files);
+    let result = verify_files(
+        &source.path().to_string_lossy(),
+        &manifest,
+        &[],
+        &written(source.path(), &manifest, &[]),
+    )
+    .expect("Failed to verify files");
+
+    assert!(!result.verified);
+    assert!(result.corrupt_files.is_empty());
+}
+
+#[test]
+pub fn manifest_entries_outside_the_destination_are_rejected() {
+    let parent = tempdir().expect("Failed to create temporary directory");
+    let destination = parent.path().join("Downloads");
+    fs::create_dir_all(destination.join("Documents")).expect("Failed to create directory");
+    fs::write(destination.join("Documents").join("a.txt"), b"first").expect("Failed to write file");
+    fs::write(parent.path().join(".bashrc"), b"keep me").expect("Failed to write file");
+
+    let manifest = vec![
+        FileManifestEntry {
+            path: "Documents/a.txt".to_string(),
+            ..Default::default()
+        },
+        FileManifestEntry {
+            path: "../.bashrc".to_string(),
+            ..Default::default()
+        },
+    ];
+
+    let result = verify_files(
+        &destination.to_string_lossy(),
+        &manifest,
+        &[vec![0u8; 32], vec![0u8; 32]],
+        &written(&destination, &manifest[..1], &[]),
+    );
+
+    assert!(matches!(
+        result,
+        Err(ExtractionError::PathOutsideDestination { .. })
+    ));
+    assert!(parent.path().join(".bashrc").exists());
+    assert!(destination.join("Documents").join("a.txt").exists());
+}
+
+#[test]
+pub fn files_not_written_by_the_transfer_are_left_alone() {
+    let destination = tempdir().expect("Failed to create temporary directory");
+    fs::write(destination.path().join("a.txt"), b"received").expect("Failed to write file");
+    fs::write(destination.path().join("b.txt"), b"keep me").expect("Failed to write file");
+
+    let manifest = vec![
+        FileManifestEntry {
+            path: "a.txt".to_string(),
+            ..Default::default()
+        },
+        FileManifestEntry {
+            path: "b.txt".to_string(),
+            ..Default::default()
+        },
+    ];
+
+    let result = verify_files(
+        &destination.path().to_string_lossy(),
+        &manifest,
+        &[vec![0u8; 32], vec![0u8; 32]],
+        &written(destination.path(), &manifest[..1], &[]),
+    )
+    .expect("Failed to verify files");
+
+    assert!(!result.verified);
+    assert_eq!(
+        result.corrupt_files,
+        vec!["a.txt".to_string(), "b.txt".to_string()]
+    );
+    assert!(!destination.path().join("a.txt").exists());
+    assert!(destination.path().join("b.txt").exists());
+}
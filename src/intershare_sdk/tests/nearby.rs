@@ -0,0 +1,956 @@
+use intershare_sdk::clipboard::ClipboardRepresentation;
+use intershare_sdk::compression::CompressionPolicy;
+use intershare_sdk::connection_request::{ConnectionRequest, DeclineReason};
+use intershare_sdk::discovery::Discovery;
+use intershare_sdk::errors::{ConnectErrors, TransferSourceError};
+use intershare_sdk::nearby::{
+    ConnectionIntentType, ConnectionMedium, NearbyConnectionDelegate, NearbyServer,
+    RecipientOutcome, RecipientProgressDelegate, SendProgressDelegate, SendProgressState,
+};
+use intershare_sdk::protocol::communication::FileManifestEntry;
+use intershare_sdk::protocol::discovery::device_discovery_message::Content;
+use intershare_sdk::protocol::discovery::DeviceDiscoveryMessage;
+use intershare_sdk::protocol::prost::Message;
+use intershare_sdk::transfer_history::{TransferDirection, TransferHistoryQuery, TransferOutcome};
+use intershare_sdk::transfer_queue::{
+    QueuedTransfer, QueuedTransferState, TransferPriority, TransferQueueDelegate,
+};
+use intershare_sdk::transfer_sink::TransferSink;
+use intershare_sdk::transfer_source::{BytesSource, TransferSource};
+use intershare_sdk::Device;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tempfile::tempdir;
+
+struct ReceivedTransfer {
+    intent_type: ConnectionIntentType,
+    result: Option<Vec<String>>,
+    clipboard: Option<Vec<ClipboardRepresentation>>,
+    manifest: Vec<FileManifestEntry>,
+}
+
+#[derive(Debug)]
+struct AcceptingDelegate {
+    results: Mutex<Sender<ReceivedTransfer>>,
+}
+
+impl NearbyConnectionDelegate for AcceptingDelegate {
+    fn received_connection_request(&self, request: Arc<ConnectionRequest>) {
+        let result = ReceivedTransfer {
+            intent_type: request.get_intent_type(),
+            manifest: request.get_file_manifest(),
+            result: request.accept(),
+            clipboard: request.get_clipboard_representations(),
+        };
+
+        let _ = self
+            .results
+            .lock()
+            .expect("Failed to lock results")
+            .send(result);
+    }
+}
+
+#[derive(Debug)]
+struct DecliningDelegate;
+
+impl NearbyConnectionDelegate for DecliningDelegate {
+    fn received_connection_request(&self, request: Arc<ConnectionRequest>) {
+        request.decline();
+    }
+}
+
+#[derive(Debug, Default)]
+struct RecipientRecorder {
+    finished: Arc<Mutex<Vec<String>>>,
+}
+
+impl RecipientProgressDelegate for RecipientRecorder {
+    fn progress_changed(&self, device_id: String, progress: SendProgressState) {
+        if matches!(progress, SendProgressState::Finished) {
+            self.finished
+                .lock()
+                .expect("Failed to lock finished recipients")
+                .push(device_id);
+        }
+    }
+}
+
+/// Source that can only be read in pieces, like one implemented by an app.
+#[derive(Debug)]
+struct PatternSource {
+    size: u64,
+}
+
+impl TransferSource for PatternSource {
+    fn get_name(&self) -> String {
+        return "pattern.bin".to_string();
+    }
+
+    fn get_size(&self) -> Result<u64, TransferSourceError> {
+        return Ok(self.size);
+    }
+
+    fn read_at(&self, offset: u64, length: u64) -> Result<Vec<u8>, TransferSourceError> {
+        let end = std::cmp::min(offset + std::cmp::min(length, 1000), self.size);
+
+        return Ok((offset..end).map(|index| (index % 251) as u8).collect());
+    }
+}
+
+/// Keeps the finished files in memory.
+#[derive(Debug, Default)]
+struct MemorySink {
+    open_files: Mutex<HashMap<String, Vec<u8>>>,
+    files: Arc<Mutex<HashMap<String, Vec<u8>>>>,
+}
+
+impl TransferSink for MemorySink {
+    fn open_file(&self, path: String, size: u64) -> bool {
+        self.open_files
+            .lock()
+            .expect("Failed to lock open files")
+            .insert(path, Vec::with_capacity(size as usize));
+        return true;
+    }
+
+    fn write(&self, path: String, data: Vec<u8>) -> bool {
+        let mut open_files = self.open_files.lock().expect("Failed to lock open files");
+
+        let Some(file) = open_files.get_mut(&path) else {
+            return false;
+        };
+
+        file.extend_from_slice(&data);
+        return true;
+    }
+
+    fn finish_file(&self, path: String) -> bool {
+        let Some(data) = self
+            .open_files
+            .lock()
+            .expect("Failed to lock open files")
+            .remove(&path)
+        else {
+            return false;
+        };
+
+        self.files
+            .lock()
+            .expect("Failed to lock files")
+            .insert(path, data);
+        return true;
+    }
+
+    fn discard_file(&self, path: String) {
+        self.open_files
+            .lock()
+            .expect("Failed to lock open files")
+            .remove(&path);
+    }
+}
+
+#[derive(Debug, Default)]
+struct VerificationRecorder {
+    verified: Arc<AtomicBool>,
+}
+
+impl SendProgressDelegate for VerificationRecorder {
+    fn progress_changed(&self, progress: SendProgressState) {
+        if matches!(progress, SendProgressState::Verified) {
+            self.verified.store(true, Ordering::SeqCst);
+        }
+    }
+}
+
+#[derive(Debug)]
+struct QueueRecorder {
+    updates: Mutex<Sender<Vec<QueuedTransfer>>>,
+}
+
+impl TransferQueueDelegate for QueueRecorder {
+    fn queue_changed(&self, transfers: Vec<QueuedTransfer>) {
+        let _ = self
+            .updates
+            .lock()
+            .expect("Failed to lock updates")
+            .send(transfers);
+    }
+}
+
+fn device(name: &str) -> Device {
+    return Device {
+        id: String::new(),
+        name: name.to_string(),
+        device_type: 0,
+    };
+}
+
+fn nearby_server(
+    name: &str,
+    file_storage: &str,
+    delegate: Option<Box<dyn NearbyConnectionDelegate>>,
+) -> NearbyServer {
+    let key_directory = tempdir().expect("Failed to create temporary directory");
+    let key_path = key_directory.path().join("identity.key");
+
+    return NearbyServer::new(
+        device(name),
+        file_storage.to_string(),
+        key_path.to_string_lossy().to_string(),
+        delegate,
+    )
+    .expect("Failed to create nearby server");
+}
+
+/// Makes the receiver known to discovery, reachable over loopback.
+fn discover(receiver: &NearbyServer) -> Device {
+    let mut connection_info = receiver
+        .variables
+        .blocking_read()
+        .device_connection_info
+        .clone();
+
+    let mut tcp = connection_info
+        .tcp
+        .expect("Receiver did not start a TCP server");
+    tcp.hostname = "127.0.0.1".to_string();
+    connection_info.tcp = Some(tcp);
+
+    let message = DeviceDiscoveryMessage {
+        content: Some(Content::DeviceConnectionInfo(connection_info.clone())),
+    };
+
+    Discovery::new(None)
+        .expect("Failed to create discovery")
+        .parse_discovery_message(message.encode_length_delimited_to_vec(), None);
+
+    return connection_info.device.expect("Missing device");
+}
+
+/// Like [`discover`], but the receiver is reached through a proxy that cuts the first connection
+/// after `cut_after` bytes were sent to the receiver.
+fn discover_through_interrupting_proxy(receiver: &NearbyServer, cut_after: u64) -> Device {
+    let receiver_device = discover(receiver);
+    let mut connection_info = receiver
+        .variables
+        .blocking_read()
+        .device_connection_info
+        .clone();
+
+    let mut tcp = connection_info
+        .tcp
+        .expect("Receiver did not start a TCP server");
+    let target = SocketAddr::from(([127, 0, 0, 1], tcp.port as u16));
+
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind proxy");
+    tcp.hostname = "127.0.0.1".to_string();
+    tcp.port = listener
+        .local_addr()
+        .expect("Failed to get proxy address")
+        .port() as u32;
+    connection_info.tcp = Some(tcp);
+
+    thread::spawn(move || {
+        for (index, client) in listener.incoming().enumerate() {
+            let (Ok(client), Ok(server)) = (client, TcpStream::connect(target)) else {
+                break;
+            };
+
+            let limit = if index == 0 { cut_after } else { u64::MAX };
+            let (mut client_reader, mut server_writer) = (
+                client.try_clone().expect("Failed to clone stream"),
+                server.try_clone().expect("Failed to clone stream"),
+            );
+            let (mut server_reader, mut client_writer) = (server, client);
+
+            thread::spawn(move || {
+                let _ = io::copy(&mut server_reader, &mut client_writer);
+            });
+
+            thread::spawn(move || {
+                let _ = io::copy(&mut (&mut client_reader).take(limit), &mut server_writer);
+                let _ = server_writer.shutdown(Shutdown::Both);
+                let _ = client_reader.shutdown(Shutdown::Both);
+            });
+        }
+    });
+
+    let message = DeviceDiscoveryMessage {
+        content: Some(Content::DeviceConnectionInfo(connection_info)),
+    };
+
+    Discovery::new(None)
+        .expect("Failed to create discovery")
+        .parse_discovery_message(message.encode_length_delimited_to_vec(), None);
+
+    return receiver_device;
+}
+
+fn start_receiver(file_storage: &Path) -> (NearbyServer, Receiver<ReceivedTransfer>) {
+    let (results, received) = channel();
+    let receiver = nearby_server(
+        "Receiver",
+        &file_storage.to_string_lossy(),
+        Some(Box::new(AcceptingDelegate {
+            results: Mutex::new(results),
+        })),
+    );
+    futures::executor::block_on(receiver.start());
+
+    return (receiver, received);
+}
+
+#[test]
+pub fn clipboard_is_transferred_over_loopback() {
+    let (receiver, received) = start_receiver(&std::env::temp_dir());
+    let receiver_device = discover(&receiver);
+
+    let sender = nearby_server("Sender", "", None);
+    futures::executor::block_on(sender.send_clipboard(
+        receiver_device,
+        "Hello from the other side".to_string(),
+        None,
+    ))
+    .expect("Failed to send clipboard");
+
+    let transfer = received
+        .recv_timeout(Duration::from_secs(10))
+        .expect("Receiver did not get a request");
+
+    assert!(matches!(
+        transfer.intent_type,
+        ConnectionIntentType::Clipboard
+    ));
+    assert_eq!(
+        transfer.result,
+        Some(vec!["Hello from the other side".to_string()])
+    );
+
+    receiver.stop();
+}
+
+#[test]
+pub fn clipboard_representations_are_transferred_over_loopback() {
+    let (receiver, received) = start_receiver(&std::env::temp_dir());
+    let receiver_device = discover(&receiver);
+
+    let image: Vec<u8> = (0..300 * 1024).map(|index| (index % 256) as u8).collect();
+    let representations = vec![
+        ClipboardRepresentation::plain_text("Picture".to_string()),
+        ClipboardRepresentation {
+            mime_type: "text/html".to_string(),
+            data: b"<img alt=\"Picture\">".to_vec(),
+        },
+        ClipboardRepresentation {
+            mime_type: "image/png".to_string(),
+            data: image,
+        },
+    ];
+
+    let sender = nearby_server("Sender", "", None);
+    futures::executor::block_on(sender.send_clipboard_representations(
+        receiver_device,
+        representations.clone(),
+        None,
+    ))
+    .expect("Failed to send clipboard");
+
+    let transfer = received
+        .recv_timeout(Duration::from_secs(10))
+        .expect("Receiver did not get a request");
+
+    assert_eq!(transfer.result, Some(vec!["Picture".to_string()]));
+    assert_eq!(transfer.clipboard, Some(representations));
+
+    receiver.stop();
+}
+
+#[test]
+pub fn files_are_transferred_over_loopback() {
+    let source = tempdir().expect("Failed to create temporary directory");
+    let destination = tempdir().expect("Failed to create temporary directory");
+
+    let photos = source.path().join("Photos");
+    fs::create_dir_all(photos.join("2024")).expect("Failed to create directories");
+    fs::create_dir_all(photos.join("2025")).expect("Failed to create directories");
+    fs::write(photos.join("2024").join("beach.jpg"), b"beach").expect("Failed to write file");
+    fs::write(photos.join("2025").join("snow.png"), b"snow").expect("Failed to write file");
+    fs::write(source.path().join("notes.txt"), b"notes").expect("Failed to write file");
+
+    let (receiver, received) = start_receiver(destination.path());
+    let receiver_device = discover(&receiver);
+
+    let recorder = VerificationRecorder::default();
+    let verified = recorder.verified.clone();
+
+    let sender = nearby_server("Sender", "", None);
+    futures::executor::block_on(sender.send_files(
+        receiver_device,
+        vec![
+            photos.to_string_lossy().to_string(),
+            source.path().join("notes.txt").to_string_lossy().to_string(),
+        ],
+        Some(Box::new(recorder)),
+    ))
+    .expect("Failed to send files");
+
+    let transfer = received
+        .recv_timeout(Duration::from_secs(10))
+        .expect("Receiver did not get a request");
+
+    let manifest: Vec<(String, u64, String)> = transfer
+        .manifest
+        .into_iter()
+        .map(|entry| (entry.path, entry.size, entry.mime_type))
+        .collect();
+
+    assert_eq!(
+        manifest,
+        vec![
+            (
+                "Photos/2024/beach.jpg".to_string(),
+                5,
+                "image/jpeg".to_string()
+            ),
+            (
+                "Photos/2025/snow.png".to_string(),
+                4,
+                "image/png".to_string()
+            ),
+            ("notes.txt".to_string(), 5, "text/plain".to_string()),
+        ]
+    );
+    assert!(transfer.result.is_some());
+    assert!(verified.load(Ordering::SeqCst));
+
+    let received_file = destination
+        .path()
+        .join("Photos")
+        .join("2025")
+        .join("snow.png");
+    assert_eq!(
+        fs::read(received_file).expect("Missing received file"),
+        b"snow"
+    );
+
+    receiver.stop();
+}
+
+#[test]
+pub fn large_files_are_transferred_over_parallel_connections() {
+    let source = tempdir().expect("Failed to create temporary directory");
+    let destination = tempdir().expect("Failed to create temporary directory");
+    let content: Vec<u8> = (0..10 * 1024 * 1024 + 123)
+        .map(|index| (index % 251) as u8)
+        .collect();
+
+    fs::write(source.path().join("video.raw"), &content).expect("Failed to write file");
+    fs::write(source.path().join("empty.txt"), b"").expect("Failed to write file");
+
+    let (receiver, received) = start_receiver(destination.path());
+    let receiver_device = discover(&receiver);
+
+    let recorder = VerificationRecorder::default();
+    let verified = recorder.verified.clone();
+
+    let sender = nearby_server("Sender", "", None);
+    sender.set_max_parallel_connections(3);
+
+    futures::executor::block_on(sender.send_files(
+        receiver_device,
+        vec![source.path().to_string_lossy().to_string()],
+        Some(Box::new(recorder)),
+    ))
+    .expect("Failed to send files");
+
+    let transfer = received
+        .recv_timeout(Duration::from_secs(10))
+        .expect("Receiver did not get a request");
+
+    assert_eq!(transfer.result.map(|files| files.len()), Some(2));
+    assert!(verified.load(Ordering::SeqCst));
+
+    let received_directory = destination
+        .path()
+        .join(source.path().file_name().expect("Missing directory name"));
+    assert_eq!(
+        fs::read(received_directory.join("video.raw")).expect("Missing received file"),
+        content
+    );
+    assert!(received_directory.join("empty.txt").exists());
+
+    receiver.stop();
+}
+
+#[test]
+pub fn queued_transfers_are_sent_one_after_another() {
+    let source = tempdir().expect("Failed to create temporary directory");
+    let destination = tempdir().expect("Failed to create temporary directory");
+
+    fs::write(source.path().join("first.txt"), "First").expect("Failed to write file");
+    fs::write(source.path().join("second.txt"), "Second").expect("Failed to write file");
+
+    let (receiver, received) = start_receiver(destination.path());
+    let receiver_device = discover(&receiver);
+
+    let (updates, queue_updates) = channel();
+    let sender = nearby_server("Sender", "", None);
+    sender.set_transfer_queue_delegate(Some(Box::new(QueueRecorder {
+        updates: Mutex::new(updates),
+    })));
+
+    for name in ["first.txt", "second.txt"] {
+        sender.enqueue_files(
+            receiver_device.clone(),
+            vec![source.path().join(name).to_string_lossy().to_string()],
+            TransferPriority::Normal,
+            None,
+        );
+    }
+
+    loop {
+        let transfers = queue_updates
+            .recv_timeout(Duration::from_secs(10))
+            .expect("Queue did not finish");
+
+        let active = transfers
+            .iter()
+            .filter(|transfer| transfer.state == QueuedTransferState::Active)
+            .count();
+        assert!(active <= 1);
+
+        if transfers
+            .iter()
+            .all(|transfer| transfer.state == QueuedTransferState::Done)
+        {
+            break;
+        }
+    }
+
+    for _ in 0..2 {
+        let transfer = received
+            .recv_timeout(Duration::from_secs(10))
+            .expect("Receiver did not get a request");
+        assert_eq!(transfer.result.map(|files| files.len()), Some(1));
+    }
+
+    assert_eq!(
+        fs::read_to_string(destination.path().join("second.txt")).expect("Missing file"),
+        "Second"
+    );
+
+    sender.clear_finished_transfers();
+    assert!(sender.get_transfer_queue().is_empty());
+
+    receiver.stop();
+}
+
+#[test]
+pub fn queued_transfers_resume_after_a_connection_loss() {
+    let source = tempdir().expect("Failed to create temporary directory");
+    let destination = tempdir().expect("Failed to create temporary directory");
+
+    let mut state: u32 = 1;
+    let content: Vec<u8> = (0..4 * 1024 * 1024)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect();
+    fs::write(source.path().join("video.mp4"), &content).expect("Failed to write file");
+
+    let (receiver, received) = start_receiver(destination.path());
+    let receiver_device = discover_through_interrupting_proxy(&receiver, 1024 * 1024);
+
+    let (updates, queue_updates) = channel();
+    let sender = nearby_server("Sender", "", None);
+    sender.set_max_parallel_connections(1);
+    sender.set_compression_policy(CompressionPolicy::Off);
+    sender.set_transfer_queue_delegate(Some(Box::new(QueueRecorder {
+        updates: Mutex::new(updates),
+    })));
+
+    sender.enqueue_files(
+        receiver_device,
+        vec![source
+            .path()
+            .join("video.mp4")
+            .to_string_lossy()
+            .to_string()],
+        TransferPriority::Normal,
+        None,
+    );
+
+    loop {
+        let transfers = queue_updates
+            .recv_timeout(Duration::from_secs(20))
+            .expect("Queue did not finish");
+
+        for transfer in &transfers {
+            assert_ne!(
+                transfer.state,
+                QueuedTransferState::Failed,
+                "{:?}",
+                transfer.error
+            );
+        }
+
+        if transfers
+            .iter()
+            .all(|transfer| transfer.state == QueuedTransferState::Done)
+        {
+            break;
+        }
+    }
+
+    let interrupted = received
+        .recv_timeout(Duration::from_secs(10))
+        .expect("Receiver did not get a request");
+    assert!(interrupted.result.is_none());
+
+    let resumed = received
+        .recv_timeout(Duration::from_secs(10))
+        .expect("Receiver did not get the resumed request");
+    assert_eq!(resumed.result.map(|files| files.len()), Some(1));
+
+    assert_eq!(
+        fs::read(destination.path().join("video.mp4")).expect("Missing file"),
+        content
+    );
+
+    receiver.stop();
+}
+
+#[test]
+pub fn transfers_exceeding_the_sender_quota_are_declined() {
+    let source = tempdir().expect("Failed to create temporary directory");
+    let destination = tempdir().expect("Failed to create temporary directory");
+
+    fs::write(source.path().join("first.txt"), "0123456789").expect("Failed to write file");
+    fs::write(source.path().join("second.txt"), "0123456789").expect("Failed to write file");
+
+    let (receiver, received) = start_receiver(destination.path());
+    receiver.set_sender_quota(15);
+    let receiver_device = discover(&receiver);
+
+    let sender = nearby_server("Sender", "", None);
+    let send = |file_name: &str| {
+        return futures::executor::block_on(sender.send_files(
+            receiver_device.clone(),
+            vec![source.path().join(file_name).to_string_lossy().to_string()],
+            None,
+        ));
+    };
+
+    send("first.txt").expect("Failed to send files");
+
+    let result = send("second.txt");
+    assert!(matches!(
+        result,
+        Err(ConnectErrors::DeclinedAutomatically {
+            reason: DeclineReason::SenderQuotaExceeded
+        })
+    ));
+
+    for _ in 0..2 {
+        received
+            .recv_timeout(Duration::from_secs(10))
+            .expect("Receiver did not get a request");
+    }
+
+    assert_eq!(receiver.get_sender_usage(sender.get_device_id()), 10);
+    assert!(!destination.path().join("second.txt").exists());
+
+    receiver.reset_sender_usage(sender.get_device_id());
+    send("second.txt").expect("Failed to send files");
+
+    receiver.stop();
+}
+
+#[test]
+pub fn transfers_are_recorded_in_the_history() {
+    let source = tempdir().expect("Failed to create temporary directory");
+    let destination = tempdir().expect("Failed to create temporary directory");
+    let declining_destination = tempdir().expect("Failed to create temporary directory");
+    let history = tempdir().expect("Failed to create temporary directory");
+
+    fs::write(source.path().join("notes.txt"), "notes").expect("Failed to write file");
+    let file_paths = vec![source
+        .path()
+        .join("notes.txt")
+        .to_string_lossy()
+        .to_string()];
+
+    let (receiver, received) = start_receiver(destination.path());
+    receiver
+        .load_transfer_history(
+            history
+                .path()
+                .join("receiver")
+                .to_string_lossy()
+                .to_string(),
+        )
+        .expect("Failed to load history");
+    let receiver_device = discover(&receiver);
+
+    let declining_receiver = nearby_server(
+        "Declining",
+        &declining_destination.path().to_string_lossy(),
+        Some(Box::new(DecliningDelegate)),
+    );
+    futures::executor::block_on(declining_receiver.start());
+    let declining_device = discover(&declining_receiver);
+
+    let sender = nearby_server("Sender", "", None);
+    sender
+        .load_transfer_history(history.path().join("sender").to_string_lossy().to_string())
+        .expect("Failed to load history");
+
+    futures::executor::block_on(sender.send_files(
+        receiver_device.clone(),
+        file_paths.clone(),
+        None,
+    ))
+    .expect("Failed to send files");
+    let _ =
+        futures::executor::block_on(sender.send_files(declining_device.clone(), file_paths, None));
+
+    received
+        .recv_timeout(Duration::from_secs(10))
+        .expect("Receiver did not get a request");
+
+    let sent = sender.get_transfer_history(TransferHistoryQuery::default());
+    let sent: Vec<(String, TransferDirection, TransferOutcome)> = sent
+        .into_iter()
+        .map(|record| (record.peer.id, record.direction, record.outcome))
+        .collect();
+
+    assert_eq!(
+        sent,
+        vec![
+            (
+                declining_device.id.clone(),
+                TransferDirection::Sent,
+                TransferOutcome::Declined
+            ),
+            (
+                receiver_device.id.clone(),
+                TransferDirection::Sent,
+                TransferOutcome::Completed
+            ),
+        ]
+    );
+
+    let received_records = receiver.get_transfer_history(TransferHistoryQuery {
+        device_id: Some(sender.get_device_id()),
+        ..Default::default()
+    });
+
+    assert_eq!(received_records.len(), 1);
+    assert_eq!(received_records[0].direction, TransferDirection::Received);
+    assert_eq!(received_records[0].outcome, TransferOutcome::Completed);
+    assert_eq!(received_records[0].medium, Some(ConnectionMedium::WiFi));
+    assert_eq!(received_records[0].total_size, 5);
+    assert_eq!(received_records[0].files[0].path, "notes.txt");
+
+    sender
+        .clear_transfer_history()
+        .expect("Failed to clear history");
+    assert!(sender
+        .get_transfer_history(TransferHistoryQuery::default())
+        .is_empty());
+
+    receiver.stop();
+    declining_receiver.stop();
+}
+
+#[test]
+pub fn files_are_sent_to_many_receivers() {
+    let source = tempdir().expect("Failed to create temporary directory");
+    let first_destination = tempdir().expect("Failed to create temporary directory");
+    let second_destination = tempdir().expect("Failed to create temporary directory");
+    let declining_destination = tempdir().expect("Failed to create temporary directory");
+
+    fs::write(source.path().join("agenda.txt"), "Agenda").expect("Failed to write file");
+
+    let (first_receiver, first_received) = start_receiver(first_destination.path());
+    let (second_receiver, second_received) = start_receiver(second_destination.path());
+
+    let declining_receiver = nearby_server(
+        "Declining",
+        &declining_destination.path().to_string_lossy(),
+        Some(Box::new(DecliningDelegate)),
+    );
+    futures::executor::block_on(declining_receiver.start());
+
+    let receivers = vec![
+        discover(&first_receiver),
+        discover(&declining_receiver),
+        discover(&second_receiver),
+    ];
+
+    let recorder = RecipientRecorder::default();
+    let finished = recorder.finished.clone();
+    let sender = nearby_server("Sender", "", None);
+
+    let results = futures::executor::block_on(sender.send_files_to_many(
+        receivers.clone(),
+        vec![source.path().join("agenda.txt").to_string_lossy().to_string()],
+        Some(Box::new(recorder)),
+    ))
+    .expect("Failed to prepare files");
+
+    let outcomes: Vec<(String, RecipientOutcome)> = results
+        .iter()
+        .map(|result| (result.device_id.clone(), result.outcome))
+        .collect();
+
+    assert_eq!(
+        outcomes,
+        vec![
+            (receivers[0].id.clone(), RecipientOutcome::Finished),
+            (receivers[1].id.clone(), RecipientOutcome::Declined),
+            (receivers[2].id.clone(), RecipientOutcome::Finished),
+        ]
+    );
+
+    let mut finished = finished
+        .lock()
+        .expect("Failed to lock finished recipients")
+        .clone();
+    finished.sort();
+
+    let mut expected = vec![receivers[0].id.clone(), receivers[2].id.clone()];
+    expected.sort();
+    assert_eq!(finished, expected);
+
+    for received in [first_received, second_received] {
+        let transfer = received
+            .recv_timeout(Duration::from_secs(10))
+            .expect("Receiver did not get a request");
+        assert_eq!(transfer.result.map(|files| files.len()), Some(1));
+    }
+
+    for destination in [&first_destination, &second_destination] {
+        assert_eq!(
+            fs::read_to_string(destination.path().join("agenda.txt")).expect("Missing file"),
+            "Agenda"
+        );
+    }
+
+    first_receiver.stop();
+    second_receiver.stop();
+    declining_receiver.stop();
+}
+
+#[test]
+pub fn sources_are_transferred_over_loopback() {
+    let destination = tempdir().expect("Failed to create temporary directory");
+
+    let (receiver, received) = start_receiver(destination.path());
+    let receiver_device = discover(&receiver);
+
+    let recorder = VerificationRecorder::default();
+    let verified = recorder.verified.clone();
+
+    let sender = nearby_server("Sender", "", None);
+
+    futures::executor::block_on(sender.send_sources(
+        receiver_device,
+        vec![
+            Box::new(BytesSource::new(
+                "Notes/hello.txt".to_string(),
+                b"Hello".to_vec(),
+            )),
+            Box::new(PatternSource { size: 300_000 }),
+        ],
+        Some(Box::new(recorder)),
+    ))
+    .expect("Failed to send sources");
+
+    let transfer = received
+        .recv_timeout(Duration::from_secs(10))
+        .expect("Receiver did not get a request");
+
+    assert_eq!(transfer.result.map(|files| files.len()), Some(2));
+    assert!(verified.load(Ordering::SeqCst));
+
+    assert_eq!(
+        fs::read_to_string(destination.path().join("Notes").join("hello.txt"))
+            .expect("Missing file"),
+        "Hello"
+    );
+
+    let pattern: Vec<u8> = (0..300_000u64).map(|index| (index % 251) as u8).collect();
+    assert_eq!(
+        fs::read(destination.path().join("pattern.bin")).expect("Missing file"),
+        pattern
+    );
+
+    receiver.stop();
+}
+
+#[test]
+pub fn files_are_received_into_a_sink() {
+    let source = tempdir().expect("Failed to create temporary directory");
+    let destination = tempdir().expect("Failed to create temporary directory");
+    let photos = source.path().join("Photos");
+
+    fs::create_dir(&photos).expect("Failed to create directory");
+    fs::write(photos.join("a.jpg"), b"first photo").expect("Failed to write file");
+    fs::write(photos.join("b.jpg"), vec![7u8; 5 * 1024 * 1024]).expect("Failed to write file");
+
+    let (receiver, received) = start_receiver(destination.path());
+    let sink = MemorySink::default();
+    let files = sink.files.clone();
+    receiver.set_transfer_sink(Some(Box::new(sink)));
+
+    let receiver_device = discover(&receiver);
+
+    let recorder = VerificationRecorder::default();
+    let verified = recorder.verified.clone();
+
+    let sender = nearby_server("Sender", "", None);
+
+    futures::executor::block_on(sender.send_files(
+        receiver_device,
+        vec![photos.to_string_lossy().to_string()],
+        Some(Box::new(recorder)),
+    ))
+    .expect("Failed to send files");
+
+    let transfer = received
+        .recv_timeout(Duration::from_secs(10))
+        .expect("Receiver did not get a request");
+
+    assert_eq!(
+        transfer.result,
+        Some(vec!["Photos/a.jpg".to_string(), "Photos/b.jpg".to_string()])
+    );
+    assert!(verified.load(Ordering::SeqCst));
+
+    let files = files.lock().expect("Failed to lock files");
+    assert_eq!(files["Photos/a.jpg"], b"first photo");
+    assert_eq!(files["Photos/b.jpg"], vec![7u8; 5 * 1024 * 1024]);
+
+    // Nothing ends up in the file storage.
+    assert_eq!(
+        fs::read_dir(destination.path())
+            .expect("Failed to read destination")
+            .count(),
+        0
+    );
+
+    receiver.stop();
+}
@@ -0,0 +1,156 @@
+use intershare_sdk::capabilities::Compression;
+use intershare_sdk::channel::TransferControl;
+use intershare_sdk::compression::CompressionPolicy;
+use intershare_sdk::manifest::{collect_files, create_manifest};
+use intershare_sdk::parallel::{split_chunks, Chunk, ParallelReceiver, ParallelSender, CHUNK_SIZE};
+use intershare_sdk::rate_limit::RateLimits;
+use std::fs;
+use std::io::{self, Write};
+use std::sync::Arc;
+use tempfile::tempdir;
+
+/// Connection that drops after a number of bytes.
+struct DroppingWriter {
+    written: Vec<u8>,
+    limit: usize,
+}
+
+impl Write for DroppingWriter {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        if self.written.len() + buffer.len() > self.limit {
+            return Err(io::ErrorKind::ConnectionReset.into());
+        }
+
+        self.written.extend_from_slice(buffer);
+        return Ok(buffer.len());
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        return Ok(());
+    }
+}
+
+#[test]
+pub fn files_are_split_into_chunks() {
+    let chunks = split_chunks(&[2 * CHUNK_SIZE + 1, 0, 10], &[CHUNK_SIZE]);
+
+    assert_eq!(
+        chunks,
+        vec![
+            Chunk {
+                file_index: 0,
+                offset: CHUNK_SIZE,
+                size: CHUNK_SIZE,
+            },
+            Chunk {
+                file_index: 0,
+                offset: 2 * CHUNK_SIZE,
+                size: 1,
+            },
+            Chunk {
+                file_index: 2,
+                offset: 0,
+                size: 10,
+            },
+        ]
+    );
+}
+
+#[test]
+pub fn chunks_of_failed_connections_are_sent_again() {
+    let source = tempdir().expect("Failed to create temporary directory");
+    let destination = tempdir().expect("Failed to create temporary directory");
+    let content: Vec<u8> = (0..3 * CHUNK_SIZE as usize - 7)
+        .map(|index| (index % 251) as u8)
+        .collect();
+
+    fs::write(source.path().join("video.raw"), &content).expect("Failed to write file");
+    fs::write(source.path().join("empty.txt"), b"").expect("Failed to write file");
+
+    let files = collect_files(&[source.path().to_string_lossy().to_string()])
+        .expect("Failed to collect files");
+    let sender = ParallelSender::new(&files, &[], CompressionPolicy::Off, Compression::Zstd);
+
+    // The first connection drops in the middle of its second chunk.
+    let mut dropped_connection = DroppingWriter {
+        written: vec![],
+        limit: CHUNK_SIZE as usize + 1024,
+    };
+    let mut sent_chunks = vec![];
+    let result = sender.send_chunks(&mut dropped_connection, &mut sent_chunks, |_| {});
+
+    assert!(result.is_err());
+    assert_eq!(sent_chunks.len(), 2);
+    sender.requeue(sent_chunks);
+
+    let mut other_connection = vec![];
+    sender
+        .send_chunks(&mut other_connection, &mut vec![], |_| {})
+        .expect("Failed to send chunks");
+    assert_eq!(sender.sent_bytes(), content.len() as u64);
+
+    let receiver = ParallelReceiver::new(
+        destination.path().to_string_lossy().to_string(),
+        [1u8; 32],
+        create_manifest(&files),
+        None,
+        Arc::new(TransferControl::new()),
+        Arc::new(RateLimits::default().for_transfer()),
+        2,
+    );
+
+    assert!(receiver.add_connection(&[1u8; 32]));
+    assert!(!receiver.add_connection(&[1u8; 32]));
+
+    // Whatever made it through the dropped connection is kept, but counted only once.
+    let mut received_bytes = 0;
+    let result = receiver.receive_chunks(dropped_connection.written.as_slice(), |bytes| {
+        received_bytes = bytes
+    });
+    assert!(result.is_err());
+    assert_eq!(received_bytes, CHUNK_SIZE);
+
+    receiver
+        .receive_chunks(other_connection.as_slice(), |bytes| received_bytes = bytes)
+        .expect("Failed to receive chunks");
+    assert_eq!(received_bytes, content.len() as u64);
+
+    let written_files = receiver.finish().expect("Transfer is incomplete");
+    assert_eq!(written_files.len(), 2);
+
+    let received_directory = destination
+        .path()
+        .join(source.path().file_name().expect("Missing directory name"));
+    assert_eq!(
+        fs::read(received_directory.join("video.raw")).expect("Missing file"),
+        content
+    );
+    assert!(received_directory.join("empty.txt").exists());
+}
+
+#[test]
+pub fn incomplete_transfers_are_rejected() {
+    let destination = tempdir().expect("Failed to create temporary directory");
+    let source = tempdir().expect("Failed to create temporary directory");
+    fs::write(source.path().join("notes.txt"), b"Hello").expect("Failed to write file");
+
+    let files = collect_files(&[source
+        .path()
+        .join("notes.txt")
+        .to_string_lossy()
+        .to_string()])
+    .expect("Failed to collect files");
+
+    let receiver = ParallelReceiver::new(
+        destination.path().to_string_lossy().to_string(),
+        [1u8; 32],
+        create_manifest(&files),
+        None,
+        Arc::new(TransferControl::new()),
+        Arc::new(RateLimits::default().for_transfer()),
+        2,
+    );
+
+    assert!(!receiver.add_connection(&[2u8; 32]));
+    assert!(receiver.finish().is_err());
+}
@@ -0,0 +1,82 @@
+use intershare_sdk::progress::ProgressTracker;
+use std::thread;
+use std::time::Duration;
+
+fn files() -> Vec<(String, u64)> {
+    return vec![
+        ("Photos/beach.jpg".to_string(), 600),
+        ("notes.txt".to_string(), 0),
+        ("video.mp4".to_string(), 400),
+    ];
+}
+
+#[test]
+pub fn progress_is_coalesced_to_the_interval() {
+    let tracker = ProgressTracker::new(files(), 1000, Duration::from_secs(60));
+
+    let reported: Vec<u64> = (0..=100)
+        .map(|step| step * 10)
+        .filter_map(|transferred_bytes| tracker.update(transferred_bytes))
+        .map(|progress| progress.transferred_bytes)
+        .collect();
+
+    // The first and the completing update always get through.
+    assert_eq!(reported, vec![0, 1000]);
+    assert!(tracker.update(1000).is_none());
+
+    let tracker = ProgressTracker::new(files(), 1000, Duration::ZERO);
+    let reported = (1..=10)
+        .filter_map(|step| tracker.update(step * 100))
+        .count();
+
+    assert_eq!(reported, 10);
+}
+
+#[test]
+pub fn progress_names_the_current_file() {
+    let tracker = ProgressTracker::new(files(), 1000, Duration::ZERO);
+
+    let current_files: Vec<(u64, Option<String>)> = [0, 599, 600, 999, 1000]
+        .into_iter()
+        .filter_map(|transferred_bytes| tracker.update(transferred_bytes))
+        .map(|progress| (progress.file_index, progress.file_name))
+        .collect();
+
+    assert_eq!(
+        current_files,
+        vec![
+            (0, Some("Photos/beach.jpg".to_string())),
+            (0, Some("Photos/beach.jpg".to_string())),
+            (2, Some("video.mp4".to_string())),
+            (2, Some("video.mp4".to_string())),
+            (2, Some("video.mp4".to_string())),
+        ]
+    );
+
+    let clipboard = ProgressTracker::new(vec![], 10, Duration::ZERO);
+    let progress = clipboard.update(5).expect("Missing progress");
+
+    assert_eq!(progress.file_name, None);
+    assert_eq!(progress.fraction, 0.5);
+}
+
+#[test]
+pub fn progress_estimates_throughput_and_remaining_time() {
+    let tracker = ProgressTracker::new(files(), 1000, Duration::ZERO);
+
+    let first = tracker.update(0).expect("Missing progress");
+    assert_eq!(first.bytes_per_second, 0.0);
+    assert_eq!(first.remaining_seconds, None);
+
+    thread::sleep(Duration::from_millis(100));
+
+    let second = tracker.update(100).expect("Missing progress");
+    assert!(second.bytes_per_second > 0.0 && second.bytes_per_second <= 1000.0);
+
+    let remaining_seconds = second.remaining_seconds.expect("Missing remaining time");
+    assert!((remaining_seconds - 900.0 / second.bytes_per_second).abs() < 0.001);
+
+    let last = tracker.update(1000).expect("Missing progress");
+    assert_eq!(last.fraction, 1.0);
+    assert_eq!(last.remaining_seconds, Some(0.0));
+}
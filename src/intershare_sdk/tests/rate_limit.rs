@@ -0,0 +1,46 @@
+use intershare_sdk::rate_limit::{RateLimited, RateLimits};
+use std::io::{self, Read};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[test]
+pub fn transfers_are_slowed_down_to_the_limit() {
+    let rate_limits = RateLimits::default();
+    rate_limits.set_transfer_limit(1024 * 1024);
+
+    let limit = rate_limits.for_transfer();
+    let data = vec![0u8; 768 * 1024];
+    let start = Instant::now();
+
+    // The first 256 KiB pass right away, the rest takes half a second.
+    let mut reader = RateLimited::new(data.as_slice(), &limit);
+    let mut buffer = vec![0u8; 256 * 1024];
+    while reader.read(&mut buffer).expect("Failed to read") > 0 {}
+
+    assert!(start.elapsed() >= Duration::from_millis(450));
+}
+
+#[test]
+pub fn limits_apply_to_running_transfers() {
+    let rate_limits = RateLimits::default();
+    rate_limits.set_global_limit(64 * 1024);
+
+    let limit = rate_limits.for_transfer();
+    let start = Instant::now();
+
+    let remover = {
+        let rate_limits = rate_limits.clone();
+
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(200));
+            rate_limits.set_global_limit(0);
+        })
+    };
+
+    // Would take 16 seconds at the initial limit.
+    let mut reader = RateLimited::new(io::repeat(0).take(1024 * 1024), &limit);
+    io::copy(&mut reader, &mut io::sink()).expect("Failed to copy");
+
+    assert!(start.elapsed() < Duration::from_secs(5));
+    remover.join().expect("Failed to remove limit");
+}
@@ -0,0 +1,97 @@
+use intershare_sdk::connection_request::DeclineReason;
+use intershare_sdk::storage_quota::{available_space, reserve_storage, StorageQuotas};
+use std::fs;
+use std::sync::{Arc, Mutex};
+use tempfile::tempdir;
+
+#[test]
+pub fn reservations_count_against_the_storage_quota() {
+    let file_storage = tempdir().expect("Failed to create temporary directory");
+    fs::create_dir(file_storage.path().join("Photos")).expect("Failed to create directory");
+    fs::write(
+        file_storage.path().join("Photos").join("beach.jpg"),
+        [0u8; 400],
+    )
+    .expect("Failed to write file");
+
+    let quotas = Arc::new(Mutex::new(StorageQuotas::default()));
+    quotas
+        .lock()
+        .expect("Failed to lock storage quotas")
+        .set_storage_quota(1000);
+
+    let first = reserve_storage(&quotas, Some(file_storage.path()), "phone", 500)
+        .expect("Transfer should fit");
+
+    let second = reserve_storage(&quotas, Some(file_storage.path()), "tablet", 200);
+    assert!(matches!(second, Err(DeclineReason::StorageQuotaExceeded)));
+
+    drop(first);
+
+    reserve_storage(&quotas, Some(file_storage.path()), "tablet", 200)
+        .expect("Transfer should fit once the first one was given up");
+
+    // Sinks don't write into the file storage.
+    reserve_storage(&quotas, None, "tablet", 5000).expect("Quota doesn't apply to sinks");
+}
+
+#[test]
+pub fn finished_transfers_count_towards_the_sender_quota() {
+    let quotas = Arc::new(Mutex::new(StorageQuotas::default()));
+    quotas
+        .lock()
+        .expect("Failed to lock storage quotas")
+        .set_sender_quota(100);
+
+    reserve_storage(&quotas, None, "phone", 60)
+        .expect("Transfer should fit")
+        .finish();
+
+    // Given up, so it doesn't count.
+    drop(reserve_storage(&quotas, None, "phone", 30).expect("Transfer should fit"));
+
+    let result = reserve_storage(&quotas, None, "phone", 60);
+    assert!(matches!(result, Err(DeclineReason::SenderQuotaExceeded)));
+
+    reserve_storage(&quotas, None, "tablet", 60).expect("Quota applies to every sender");
+
+    let quotas_guard = quotas.lock().expect("Failed to lock storage quotas");
+    assert_eq!(quotas_guard.sender_usage("phone"), 60);
+    assert_eq!(quotas_guard.sender_usage("tablet"), 0);
+}
+
+#[test]
+pub fn transfers_larger_than_the_free_space_are_declined() {
+    let file_storage = tempdir().expect("Failed to create temporary directory");
+    let not_yet_created = file_storage.path().join("Downloads").join("InterShare");
+
+    let Some(available) = available_space(&not_yet_created) else {
+        return;
+    };
+
+    let quotas = Arc::new(Mutex::new(StorageQuotas::default()));
+
+    let result = reserve_storage(&quotas, Some(&not_yet_created), "phone", available + 1);
+    assert!(matches!(result, Err(DeclineReason::InsufficientStorage)));
+
+    reserve_storage(&quotas, Some(&not_yet_created), "phone", 1).expect("Transfer should fit");
+}
+
+#[test]
+pub fn sizes_wrapping_past_the_limits_are_declined() {
+    let file_storage = tempdir().expect("Failed to create temporary directory");
+    let quotas = Arc::new(Mutex::new(StorageQuotas::default()));
+    quotas
+        .lock()
+        .expect("Failed to lock storage quotas")
+        .set_sender_quota(1000);
+
+    let _first = reserve_storage(&quotas, Some(file_storage.path()), "phone", 10)
+        .expect("Transfer should fit");
+
+    let result = reserve_storage(&quotas, Some(file_storage.path()), "phone", u64::MAX);
+    assert!(matches!(result, Err(DeclineReason::TooLarge)));
+
+    let result = reserve_storage(&quotas, None, "tablet", u64::MAX - 5);
+    assert!(matches!(result, Err(DeclineReason::TooLarge)));
+}
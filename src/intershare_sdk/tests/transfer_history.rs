@@ -0,0 +1,108 @@
+use intershare_sdk::nearby::ConnectionMedium;
+use intershare_sdk::protocol::communication::FileManifestEntry;
+use intershare_sdk::transfer_history::{
+    TransferDirection, TransferHistory, TransferHistoryQuery, TransferOutcome, TransferRecord,
+};
+use intershare_sdk::Device;
+use tempfile::tempdir;
+
+fn record(id: &str, peer: &str, direction: TransferDirection, started_at: u64) -> TransferRecord {
+    return TransferRecord {
+        id: id.to_string(),
+        direction,
+        peer: Device {
+            id: peer.to_string(),
+            name: peer.to_string(),
+            device_type: 0,
+        },
+        files: vec![FileManifestEntry {
+            path: "notes.txt".to_string(),
+            size: 5,
+            mime_type: "text/plain".to_string(),
+            modified: 0,
+        }],
+        total_size: 5,
+        medium: Some(ConnectionMedium::WiFi),
+        started_at,
+        duration_millis: 20,
+        outcome: TransferOutcome::Completed,
+        error: None,
+    };
+}
+
+fn ids(records: Vec<TransferRecord>) -> Vec<String> {
+    return records.into_iter().map(|record| record.id).collect();
+}
+
+#[test]
+pub fn history_is_persisted_and_queried_newest_first() {
+    let directory = tempdir().expect("Failed to create temporary directory");
+    let path = directory.path().join("history");
+
+    let mut history = TransferHistory::load(&path).expect("Failed to load history");
+    history
+        .record(record("1", "phone", TransferDirection::Received, 100))
+        .expect("Failed to record transfer");
+    history
+        .record(record("2", "laptop", TransferDirection::Sent, 200))
+        .expect("Failed to record transfer");
+    history
+        .record(record("3", "phone", TransferDirection::Sent, 300))
+        .expect("Failed to record transfer");
+
+    let history = TransferHistory::load(&path).expect("Failed to load history");
+
+    let all = history.query(&TransferHistoryQuery::default());
+    assert_eq!(ids(all.clone()), vec!["3", "2", "1"]);
+    assert_eq!(all[2].direction, TransferDirection::Received);
+    assert_eq!(all[2].medium, Some(ConnectionMedium::WiFi));
+    assert_eq!(all[2].files[0].path, "notes.txt");
+
+    let from_phone = history.query(&TransferHistoryQuery {
+        device_id: Some("phone".to_string()),
+        ..Default::default()
+    });
+    assert_eq!(ids(from_phone), vec!["3", "1"]);
+
+    let sent = history.query(&TransferHistoryQuery {
+        direction: Some(TransferDirection::Sent),
+        limit: Some(1),
+        ..Default::default()
+    });
+    assert_eq!(ids(sent), vec!["3"]);
+
+    let earlier = history.query(&TransferHistoryQuery {
+        since: Some(100),
+        until: Some(300),
+        ..Default::default()
+    });
+    assert_eq!(ids(earlier), vec!["2", "1"]);
+}
+
+#[test]
+pub fn records_are_deleted_and_cleared() {
+    let directory = tempdir().expect("Failed to create temporary directory");
+    let path = directory.path().join("history");
+
+    let mut history = TransferHistory::load(&path).expect("Failed to load history");
+    history
+        .record(record("1", "phone", TransferDirection::Received, 100))
+        .expect("Failed to record transfer");
+    history
+        .record(record("2", "phone", TransferDirection::Received, 200))
+        .expect("Failed to record transfer");
+
+    assert!(history.delete("1").expect("Failed to delete record"));
+    assert!(!history.delete("1").expect("Failed to delete record"));
+
+    let reloaded = TransferHistory::load(&path).expect("Failed to load history");
+    assert_eq!(
+        ids(reloaded.query(&TransferHistoryQuery::default())),
+        vec!["2"]
+    );
+
+    history.clear().expect("Failed to clear history");
+
+    let reloaded = TransferHistory::load(&path).expect("Failed to load history");
+    assert!(reloaded.query(&TransferHistoryQuery::default()).is_empty());
+}
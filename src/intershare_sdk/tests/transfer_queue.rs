@@ -0,0 +1,76 @@
+use intershare_sdk::errors::ConnectErrors;
+use intershare_sdk::transfer_queue::{QueuedTransferState, TransferPriority, TransferQueue};
+use intershare_sdk::Device;
+
+fn device(id: &str) -> Device {
+    return Device {
+        id: id.to_string(),
+        name: id.to_string(),
+        device_type: 0,
+    };
+}
+
+fn enqueue(queue: &mut TransferQueue, device_id: &str, priority: TransferPriority) -> String {
+    return queue.enqueue(
+        device(device_id),
+        vec![device_id.to_string()],
+        priority,
+        None,
+    );
+}
+
+fn started(queue: &mut TransferQueue) -> Vec<String> {
+    return queue
+        .start_next()
+        .into_iter()
+        .map(|transfer| transfer.id)
+        .collect();
+}
+
+#[test]
+pub fn transfers_start_by_priority_within_limits() {
+    let mut queue = TransferQueue::default();
+    queue.set_max_active(2);
+
+    let first = enqueue(&mut queue, "phone", TransferPriority::Normal);
+    let second = enqueue(&mut queue, "phone", TransferPriority::Normal);
+    let urgent = enqueue(&mut queue, "phone", TransferPriority::High);
+    let laptop = enqueue(&mut queue, "laptop", TransferPriority::Low);
+    let tablet = enqueue(&mut queue, "tablet", TransferPriority::Normal);
+
+    // One transfer per device, and two overall.
+    assert_eq!(started(&mut queue), vec![urgent.clone(), tablet.clone()]);
+    assert!(started(&mut queue).is_empty());
+
+    queue.finish(&urgent, &Ok(()));
+    assert_eq!(started(&mut queue), vec![first.clone()]);
+
+    queue.finish(&tablet, &Err(ConnectErrors::Declined));
+    assert_eq!(started(&mut queue), vec![laptop.clone()]);
+
+    let states: Vec<QueuedTransferState> = queue
+        .transfers()
+        .iter()
+        .map(|transfer| transfer.state)
+        .collect();
+
+    assert_eq!(
+        states,
+        vec![
+            QueuedTransferState::Active,
+            QueuedTransferState::Queued,
+            QueuedTransferState::Done,
+            QueuedTransferState::Active,
+            QueuedTransferState::Failed,
+        ]
+    );
+
+    assert!(queue.transfers()[4].error.is_some());
+
+    // Active transfers stay in the queue.
+    assert!(!queue.remove(&first));
+    assert!(queue.remove(&second));
+
+    queue.clear_finished();
+    assert_eq!(queue.transfers().len(), 2);
+}
@@ -0,0 +1,59 @@
+use intershare_sdk::capabilities::Compression;
+use intershare_sdk::compression::CompressionPolicy;
+use intershare_sdk::errors::TransferSourceError;
+use intershare_sdk::framed::send_framed_files;
+use intershare_sdk::manifest::SourceFile;
+use intershare_sdk::transfer_source::{FileSource, TransferSource};
+use std::sync::Arc;
+use tempfile::tempdir;
+
+/// Source whose data becomes unavailable after the first bytes, like a revoked content URI.
+#[derive(Debug)]
+struct RevokedSource;
+
+impl TransferSource for RevokedSource {
+    fn get_name(&self) -> String {
+        return "revoked.bin".to_string();
+    }
+
+    fn get_size(&self) -> Result<u64, TransferSourceError> {
+        return Ok(1024);
+    }
+
+    fn read_at(&self, offset: u64, length: u64) -> Result<Vec<u8>, TransferSourceError> {
+        if offset >= 100 {
+            return Err(TransferSourceError::FailedToRead {
+                error: "Permission revoked".to_string(),
+            });
+        }
+
+        return Ok(vec![1u8; std::cmp::min(length, 100 - offset) as usize]);
+    }
+}
+
+#[test]
+pub fn read_errors_fail_the_transfer() {
+    let files = vec![SourceFile::from_source(Arc::new(RevokedSource)).expect("Failed to get size")];
+
+    let result = send_framed_files(
+        &files,
+        Vec::new(),
+        &[],
+        CompressionPolicy::Off,
+        Compression::None,
+        |_| {},
+    );
+
+    let error = result.expect_err("Transfer succeeded without data");
+    assert!(error.to_string().contains("Permission revoked"));
+}
+
+#[test]
+pub fn missing_files_have_no_size() {
+    let directory = tempdir().expect("Failed to create temporary directory");
+    let source = FileSource::new(directory.path().join("missing.txt"));
+
+    assert!(source.get_size().is_err());
+    assert!(source.read_at(0, 10).is_err());
+    assert!(SourceFile::from_source(Arc::new(source)).is_err());
+}
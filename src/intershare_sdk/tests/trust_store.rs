@@ -0,0 +1,74 @@
+use intershare_sdk::identity::DeviceIdentity;
+use intershare_sdk::trust_store::{TrustStatus, TrustStore};
+use intershare_sdk::Device;
+use tempfile::tempdir;
+
+fn device_for(identity: &DeviceIdentity, name: &str) -> Device {
+    return Device {
+        id: identity.device_id(),
+        name: name.to_string(),
+        device_type: 0,
+    };
+}
+
+#[test]
+pub fn pinned_devices_are_persisted() {
+    let directory = tempdir().expect("Failed to create temporary directory");
+    let path = directory.path().join("trusted_devices.bin");
+
+    let identity = DeviceIdentity::generate();
+    let device = device_for(&identity, "Laptop");
+
+    let mut trust_store = TrustStore::load(&path).expect("Failed to load trust store");
+    assert_eq!(
+        trust_store.status(&device, &identity.public_key()),
+        TrustStatus::Unknown
+    );
+
+    trust_store
+        .pin(&device, &identity.public_key())
+        .expect("Failed to pin device");
+
+    let trust_store = TrustStore::load(&path).expect("Failed to load trust store");
+    assert_eq!(
+        trust_store.status(&device, &identity.public_key()),
+        TrustStatus::Trusted
+    );
+}
+
+#[test]
+pub fn changed_keys_and_revocations_are_detected() {
+    let identity = DeviceIdentity::generate();
+    let impostor = DeviceIdentity::generate();
+    let device = device_for(&identity, "Phone");
+
+    let mut trust_store = TrustStore::in_memory();
+    trust_store
+        .pin(&device, &identity.public_key())
+        .expect("Failed to pin device");
+
+    assert_eq!(
+        trust_store.status(&device, &impostor.public_key()),
+        TrustStatus::KeyChanged
+    );
+    assert_eq!(
+        trust_store.status(&device_for(&impostor, "Phone"), &impostor.public_key()),
+        TrustStatus::KeyChanged
+    );
+
+    assert!(trust_store
+        .revoke(&device.id)
+        .expect("Failed to revoke device"));
+    assert_eq!(
+        trust_store.status(&device, &identity.public_key()),
+        TrustStatus::Revoked
+    );
+
+    trust_store
+        .forget(&device.id)
+        .expect("Failed to forget device");
+    assert_eq!(
+        trust_store.status(&device, &identity.public_key()),
+        TrustStatus::Unknown
+    );
+}
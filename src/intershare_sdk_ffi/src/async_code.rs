@@ -6,10 +6,17 @@ pub use intershare_sdk::protocol::discovery::{
 use intershare_sdk::protocol::prost::Message;
 pub use intershare_sdk::stream::NativeStreamDelegate;
 pub use intershare_sdk::{
+    clipboard::ClipboardRepresentation,
+    compression::CompressionPolicy,
     nearby::{
         BleServerImplementationDelegate, L2CapDelegate, NearbyConnectionDelegate, NearbyServer,
-        SendProgressDelegate,
+        RecipientProgressDelegate, RecipientResult, SendProgressDelegate,
     },
+    transfer_history::{TransferHistoryQuery, TransferRecord},
+    transfer_queue::{QueuedTransfer, TransferPriority, TransferQueueDelegate},
+    transfer_sink::TransferSink,
+    transfer_source::TransferSource,
+    trust_store::TrustedDevice,
     Device,
 };
 
@@ -24,17 +31,127 @@ impl InternalNearbyServer {
     pub fn new(
         my_device: Device,
         file_storage: String,
+        identity_key_path: String,
         delegate: Option<Box<dyn NearbyConnectionDelegate>>,
-    ) -> Self {
-        let server = NearbyServer::new(my_device, file_storage, delegate);
+    ) -> Result<Self, IdentityError> {
+        let server = NearbyServer::new(my_device, file_storage, identity_key_path, delegate)?;
 
-        Self { handler: server }
+        Ok(Self { handler: server })
     }
 
     pub fn get_current_ip(&self) -> Option<String> {
         return self.handler.get_current_ip();
     }
 
+    pub fn get_device_id(&self) -> String {
+        return self.handler.get_device_id();
+    }
+
+    pub fn load_trust_store(&self, path: String) -> Result<(), StorageError> {
+        return self.handler.load_trust_store(path);
+    }
+
+    pub fn get_trusted_devices(&self) -> Vec<TrustedDevice> {
+        return self.handler.get_trusted_devices();
+    }
+
+    pub fn revoke_device(&self, device_id: String) -> Result<bool, StorageError> {
+        return self.handler.revoke_device(device_id);
+    }
+
+    pub fn forget_device(&self, device_id: String) -> Result<(), StorageError> {
+        return self.handler.forget_device(device_id);
+    }
+
+    pub fn load_transfer_history(&self, path: String) -> Result<(), StorageError> {
+        return self.handler.load_transfer_history(path);
+    }
+
+    pub fn get_transfer_history(&self, query: TransferHistoryQuery) -> Vec<TransferRecord> {
+        return self.handler.get_transfer_history(query);
+    }
+
+    pub fn delete_transfer_record(&self, id: String) -> Result<bool, StorageError> {
+        return self.handler.delete_transfer_record(id);
+    }
+
+    pub fn clear_transfer_history(&self) -> Result<(), StorageError> {
+        return self.handler.clear_transfer_history();
+    }
+
+    pub fn set_auto_accept_trusted(&self, enabled: bool) {
+        self.handler.set_auto_accept_trusted(enabled);
+    }
+
+    pub fn set_compression_policy(&self, policy: CompressionPolicy) {
+        self.handler.set_compression_policy(policy);
+    }
+
+    pub fn set_max_parallel_connections(&self, connections: u32) {
+        self.handler.set_max_parallel_connections(connections);
+    }
+
+    pub fn set_rate_limit(&self, bytes_per_second: u64) {
+        self.handler.set_rate_limit(bytes_per_second);
+    }
+
+    pub fn set_transfer_rate_limit(&self, bytes_per_second: u64) {
+        self.handler.set_transfer_rate_limit(bytes_per_second);
+    }
+
+    pub fn set_transfer_sink(&self, sink: Option<Box<dyn TransferSink>>) {
+        self.handler.set_transfer_sink(sink);
+    }
+
+    pub fn set_extraction_limits(&self, max_size_ratio: f64, max_entries: u64) {
+        self.handler
+            .set_extraction_limits(max_size_ratio, max_entries);
+    }
+
+    pub fn set_progress_interval(&self, milliseconds: u64) {
+        self.handler.set_progress_interval(milliseconds);
+    }
+
+    pub fn set_storage_quota(&self, bytes: u64) {
+        self.handler.set_storage_quota(bytes);
+    }
+
+    pub fn set_sender_quota(&self, bytes: u64) {
+        self.handler.set_sender_quota(bytes);
+    }
+
+    pub fn get_sender_usage(&self, device_id: String) -> u64 {
+        return self.handler.get_sender_usage(device_id);
+    }
+
+    pub fn reset_sender_usage(&self, device_id: String) {
+        self.handler.reset_sender_usage(device_id);
+    }
+
+    pub fn set_max_active_transfers(&self, transfers: u32) {
+        self.handler.set_max_active_transfers(transfers);
+    }
+
+    pub fn set_max_active_transfers_per_device(&self, transfers: u32) {
+        self.handler.set_max_active_transfers_per_device(transfers);
+    }
+
+    pub fn set_transfer_queue_delegate(&self, delegate: Option<Box<dyn TransferQueueDelegate>>) {
+        self.handler.set_transfer_queue_delegate(delegate);
+    }
+
+    pub fn get_transfer_queue(&self) -> Vec<QueuedTransfer> {
+        return self.handler.get_transfer_queue();
+    }
+
+    pub fn remove_queued_transfer(&self, id: String) -> bool {
+        return self.handler.remove_queued_transfer(id);
+    }
+
+    pub fn clear_finished_transfers(&self) {
+        self.handler.clear_finished_transfers();
+    }
+
     pub fn add_l2_cap_client(&self, delegate: Box<dyn L2CapDelegate>) {
         self.handler.add_l2_cap_client(delegate);
     }
@@ -120,6 +237,66 @@ impl InternalNearbyServer {
             .await;
     }
 
+    pub async fn send_sources(
+        &self,
+        receiver: Device,
+        sources: Vec<Box<dyn TransferSource>>,
+        progress_delegate: Option<Box<dyn SendProgressDelegate>>,
+    ) -> Result<(), ConnectErrors> {
+        return self
+            .handler
+            .send_sources(receiver, sources, progress_delegate)
+            .await;
+    }
+
+    pub async fn send_files_to_many(
+        &self,
+        receivers: Vec<Device>,
+        file_paths: Vec<String>,
+        progress_delegate: Option<Box<dyn RecipientProgressDelegate>>,
+    ) -> Result<Vec<RecipientResult>, ConnectErrors> {
+        return self
+            .handler
+            .send_files_to_many(receivers, file_paths, progress_delegate)
+            .await;
+    }
+
+    pub fn enqueue_files(
+        &self,
+        receiver: Device,
+        file_paths: Vec<String>,
+        priority: TransferPriority,
+        progress_delegate: Option<Box<dyn SendProgressDelegate>>,
+    ) -> String {
+        return self
+            .handler
+            .enqueue_files(receiver, file_paths, priority, progress_delegate);
+    }
+
+    pub async fn send_clipboard(
+        &self,
+        receiver: Device,
+        clipboard_content: String,
+        progress_delegate: Option<Box<dyn SendProgressDelegate>>,
+    ) -> Result<(), ConnectErrors> {
+        return self
+            .handler
+            .send_clipboard(receiver, clipboard_content, progress_delegate)
+            .await;
+    }
+
+    pub async fn send_clipboard_representations(
+        &self,
+        receiver: Device,
+        representations: Vec<ClipboardRepresentation>,
+        progress_delegate: Option<Box<dyn SendProgressDelegate>>,
+    ) -> Result<(), ConnectErrors> {
+        return self
+            .handler
+            .send_clipboard_representations(receiver, representations, progress_delegate)
+            .await;
+    }
+
     pub fn stop(&self) {
         self.handler.stop();
     }
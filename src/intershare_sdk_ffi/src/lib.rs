@@ -1,8 +1,10 @@
 use std::io;
 use std::sync::Arc;
 
+pub use intershare_sdk::clipboard::ClipboardRepresentation;
+pub use intershare_sdk::compression::CompressionPolicy;
 pub use intershare_sdk::connection_request::{
-    ConnectionRequest, ReceiveProgressDelegate, ReceiveProgressState,
+    ConnectionRequest, DeclineReason, ReceiveProgressDelegate, ReceiveProgressState,
 };
 pub use intershare_sdk::discovery::{BleDiscoveryImplementationDelegate, Discovery};
 pub use intershare_sdk::encryption::EncryptedStream;
@@ -10,12 +12,24 @@ pub use intershare_sdk::errors::*;
 pub use intershare_sdk::nearby::ConnectionIntentType;
 pub use intershare_sdk::nearby::{
     BleServerImplementationDelegate, ConnectionMedium, L2CapDelegate, NearbyConnectionDelegate,
-    NearbyServer, SendProgressDelegate, SendProgressState,
+    NearbyServer, RecipientOutcome, RecipientProgressDelegate, RecipientResult,
+    SendProgressDelegate, SendProgressState,
 };
-pub use intershare_sdk::protocol::communication::FileTransferIntent;
+pub use intershare_sdk::progress::TransferProgress;
+pub use intershare_sdk::protocol::communication::ClipboardEntry;
+pub use intershare_sdk::protocol::communication::{FileManifestEntry, FileTransferIntent};
 use intershare_sdk::protocol::discovery::{BluetoothLeConnectionInfo, TcpConnectionInfo};
 pub use intershare_sdk::stream::NativeStreamDelegate;
+pub use intershare_sdk::transfer_history::{
+    TransferDirection, TransferHistoryQuery, TransferOutcome, TransferRecord,
+};
+pub use intershare_sdk::transfer_queue::{
+    QueuedTransfer, QueuedTransferState, TransferPriority, TransferQueueDelegate,
+};
+pub use intershare_sdk::transfer_sink::TransferSink;
+pub use intershare_sdk::transfer_source::TransferSource;
 pub use intershare_sdk::transmission::TransmissionSetupError;
+pub use intershare_sdk::trust_store::{TrustStatus, TrustedDevice};
 pub use intershare_sdk::Device;
 pub use intershare_sdk::DiscoveryDelegate as DeviceListUpdateDelegate;
 pub use intershare_sdk::*;
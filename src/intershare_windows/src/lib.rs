@@ -3,15 +3,25 @@ pub mod nearby_server;
 pub mod discovery;
 
 pub use intershare_sdk::{ClipboardTransferIntent};
-pub use intershare_sdk::connection_request::{ConnectionRequest, ReceiveProgressState, ReceiveProgressDelegate};
+pub use intershare_sdk::connection_request::{ConnectionRequest, DeclineReason, ReceiveProgressState, ReceiveProgressDelegate};
 pub use intershare_sdk::Device;
 pub use intershare_sdk::DiscoveryDelegate;
 pub use intershare_sdk::encryption::EncryptedStream;
 pub use intershare_sdk::nearby::{ConnectionMedium, SendProgressState, SendProgressDelegate, BleServerImplementationDelegate, L2CapDelegate, NearbyConnectionDelegate};
 pub use intershare_sdk::nearby::ConnectionIntentType;
-pub use intershare_sdk::protocol::communication::FileTransferIntent;
+pub use intershare_sdk::nearby::{RecipientOutcome, RecipientProgressDelegate, RecipientResult};
+pub use intershare_sdk::protocol::communication::{FileManifestEntry, FileTransferIntent};
+pub use intershare_sdk::progress::TransferProgress;
 pub use intershare_sdk::stream::NativeStreamDelegate;
+pub use intershare_sdk::clipboard::ClipboardRepresentation;
+pub use intershare_sdk::protocol::communication::ClipboardEntry;
+pub use intershare_sdk::transfer_history::{TransferDirection, TransferHistoryQuery, TransferOutcome, TransferRecord};
+pub use intershare_sdk::transfer_sink::TransferSink;
+pub use intershare_sdk::transfer_source::TransferSource;
 pub use intershare_sdk::transmission::TransmissionSetupError;
+pub use intershare_sdk::transfer_queue::{QueuedTransfer, QueuedTransferState, TransferPriority, TransferQueueDelegate};
+pub use intershare_sdk::compression::CompressionPolicy;
+pub use intershare_sdk::trust_store::{TrustStatus, TrustedDevice};
 pub use intershare_sdk::errors::*;
 pub use intershare_sdk::*;
 pub use crate::discovery::{Discovery};
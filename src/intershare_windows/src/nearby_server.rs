@@ -1,11 +1,18 @@
 use crate::ble::ble_server::BleServer;
-use intershare_sdk::nearby::{NearbyConnectionDelegate, SendProgressDelegate};
+use intershare_sdk::nearby::{NearbyConnectionDelegate, RecipientProgressDelegate, RecipientResult, SendProgressDelegate};
 use intershare_sdk::nearby::NearbyServer as InternalNearbyServer;
+use intershare_sdk::clipboard::ClipboardRepresentation;
+use intershare_sdk::compression::CompressionPolicy;
 use intershare_sdk::Device;
 use std::sync::Arc;
-use dirs::download_dir;
+use dirs::{data_local_dir, download_dir};
 use tokio::runtime::Runtime;
-use intershare_sdk::errors::ConnectErrors;
+use intershare_sdk::errors::{ConnectErrors, StorageError};
+use intershare_sdk::trust_store::TrustedDevice;
+use intershare_sdk::transfer_history::{TransferHistoryQuery, TransferRecord};
+use intershare_sdk::transfer_queue::{QueuedTransfer, TransferPriority, TransferQueueDelegate};
+use intershare_sdk::transfer_sink::TransferSink;
+use intershare_sdk::transfer_source::TransferSource;
 
 pub struct NearbyServer {
     runtime: Runtime,
@@ -17,13 +24,27 @@ impl NearbyServer {
         let downloads_dir = download_dir().expect("Failed to get downloads directory").to_string_lossy().to_string();
         println!("Downloads directory: {}", downloads_dir);
 
-        let nearby = Arc::new(InternalNearbyServer::new(my_device, downloads_dir, delegate));
+        let data_dir = data_local_dir().expect("Failed to get local data directory").join("InterShare");
+        let key_path = data_dir.join("identity.key").to_string_lossy().to_string();
+
+        let nearby = Arc::new(
+            InternalNearbyServer::new(my_device, downloads_dir, key_path, delegate)
+                .expect("Failed to load identity")
+        );
+
+        let trust_store_path = data_dir.join("trusted_devices.bin");
+
+        if let Err(error) = nearby.load_trust_store(trust_store_path.to_string_lossy().to_string()) {
+            println!("Failed to load trust store: {:?}", error);
+        }
         let ble_server = BleServer::new(Arc::clone(&nearby))
             .expect("Failed to initialize BLE Server");
 
         nearby.add_bluetooth_implementation(Box::new(ble_server));
 
+        // Resumed transfers wait on a tokio timer before reconnecting.
         let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
             .build()
             .unwrap();
 
@@ -33,6 +54,106 @@ impl NearbyServer {
         }
     }
 
+    pub fn get_device_id(&self) -> String {
+        self.internal_nearby_server.get_device_id()
+    }
+
+    pub fn get_trusted_devices(&self) -> Vec<TrustedDevice> {
+        self.internal_nearby_server.get_trusted_devices()
+    }
+
+    pub fn revoke_device(&self, device_id: String) -> Result<bool, StorageError> {
+        self.internal_nearby_server.revoke_device(device_id)
+    }
+
+    pub fn set_auto_accept_trusted(&self, enabled: bool) {
+        self.internal_nearby_server.set_auto_accept_trusted(enabled)
+    }
+
+    pub fn set_compression_policy(&self, policy: CompressionPolicy) {
+        self.internal_nearby_server.set_compression_policy(policy)
+    }
+
+    pub fn set_max_parallel_connections(&self, connections: u32) {
+        self.internal_nearby_server.set_max_parallel_connections(connections)
+    }
+
+    pub fn set_rate_limit(&self, bytes_per_second: u64) {
+        self.internal_nearby_server.set_rate_limit(bytes_per_second)
+    }
+
+    pub fn set_transfer_rate_limit(&self, bytes_per_second: u64) {
+        self.internal_nearby_server.set_transfer_rate_limit(bytes_per_second)
+    }
+
+    pub fn set_transfer_sink(&self, sink: Option<Box<dyn TransferSink>>) {
+        self.internal_nearby_server.set_transfer_sink(sink)
+    }
+
+    pub fn set_extraction_limits(&self, max_size_ratio: f64, max_entries: u64) {
+        self.internal_nearby_server.set_extraction_limits(max_size_ratio, max_entries)
+    }
+
+    pub fn set_progress_interval(&self, milliseconds: u64) {
+        self.internal_nearby_server.set_progress_interval(milliseconds)
+    }
+
+    pub fn load_transfer_history(&self, path: String) -> Result<(), StorageError> {
+        self.internal_nearby_server.load_transfer_history(path)
+    }
+
+    pub fn get_transfer_history(&self, query: TransferHistoryQuery) -> Vec<TransferRecord> {
+        self.internal_nearby_server.get_transfer_history(query)
+    }
+
+    pub fn delete_transfer_record(&self, id: String) -> Result<bool, StorageError> {
+        self.internal_nearby_server.delete_transfer_record(id)
+    }
+
+    pub fn clear_transfer_history(&self) -> Result<(), StorageError> {
+        self.internal_nearby_server.clear_transfer_history()
+    }
+
+    pub fn set_storage_quota(&self, bytes: u64) {
+        self.internal_nearby_server.set_storage_quota(bytes)
+    }
+
+    pub fn set_sender_quota(&self, bytes: u64) {
+        self.internal_nearby_server.set_sender_quota(bytes)
+    }
+
+    pub fn get_sender_usage(&self, device_id: String) -> u64 {
+        self.internal_nearby_server.get_sender_usage(device_id)
+    }
+
+    pub fn reset_sender_usage(&self, device_id: String) {
+        self.internal_nearby_server.reset_sender_usage(device_id)
+    }
+
+    pub fn set_max_active_transfers(&self, transfers: u32) {
+        self.internal_nearby_server.set_max_active_transfers(transfers)
+    }
+
+    pub fn set_max_active_transfers_per_device(&self, transfers: u32) {
+        self.internal_nearby_server.set_max_active_transfers_per_device(transfers)
+    }
+
+    pub fn set_transfer_queue_delegate(&self, delegate: Option<Box<dyn TransferQueueDelegate>>) {
+        self.internal_nearby_server.set_transfer_queue_delegate(delegate)
+    }
+
+    pub fn get_transfer_queue(&self) -> Vec<QueuedTransfer> {
+        self.internal_nearby_server.get_transfer_queue()
+    }
+
+    pub fn remove_queued_transfer(&self, id: String) -> bool {
+        self.internal_nearby_server.remove_queued_transfer(id)
+    }
+
+    pub fn clear_finished_transfers(&self) {
+        self.internal_nearby_server.clear_finished_transfers()
+    }
+
     pub fn start(&self) {
         self.runtime.block_on(self.internal_nearby_server.start());
     }
@@ -48,4 +169,24 @@ impl NearbyServer {
     pub fn send_files(&self, receiver: Device, file_paths: Vec<String>, progress_delegate: Option<Box<dyn SendProgressDelegate>>) -> Result<(), ConnectErrors> {
         return self.runtime.block_on(self.internal_nearby_server.send_files(receiver, file_paths, progress_delegate))
     }
+
+    pub fn send_sources(&self, receiver: Device, sources: Vec<Box<dyn TransferSource>>, progress_delegate: Option<Box<dyn SendProgressDelegate>>) -> Result<(), ConnectErrors> {
+        return self.runtime.block_on(self.internal_nearby_server.send_sources(receiver, sources, progress_delegate))
+    }
+
+    pub fn send_files_to_many(&self, receivers: Vec<Device>, file_paths: Vec<String>, progress_delegate: Option<Box<dyn RecipientProgressDelegate>>) -> Result<Vec<RecipientResult>, ConnectErrors> {
+        return self.runtime.block_on(self.internal_nearby_server.send_files_to_many(receivers, file_paths, progress_delegate))
+    }
+
+    pub fn enqueue_files(&self, receiver: Device, file_paths: Vec<String>, priority: TransferPriority, progress_delegate: Option<Box<dyn SendProgressDelegate>>) -> String {
+        self.internal_nearby_server.enqueue_files(receiver, file_paths, priority, progress_delegate)
+    }
+
+    pub fn send_clipboard(&self, receiver: Device, clipboard_content: String, progress_delegate: Option<Box<dyn SendProgressDelegate>>) -> Result<(), ConnectErrors> {
+        return self.runtime.block_on(self.internal_nearby_server.send_clipboard(receiver, clipboard_content, progress_delegate))
+    }
+
+    pub fn send_clipboard_representations(&self, receiver: Device, representations: Vec<ClipboardRepresentation>, progress_delegate: Option<Box<dyn SendProgressDelegate>>) -> Result<(), ConnectErrors> {
+        return self.runtime.block_on(self.internal_nearby_server.send_clipboard_representations(receiver, representations, progress_delegate))
+    }
 }
\ No newline at end of file
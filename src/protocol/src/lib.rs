@@ -12,6 +12,10 @@ pub mod communication {
     ));
 }
 
+pub mod storage {
+    include!(concat!(env!("OUT_DIR"), "/inter_share_sdk.storage.rs"));
+}
+
 pub trait DiscoveryDelegate: Send + Sync + Debug {
     fn device_added(&self, value: discovery::Device);
     fn device_removed(&self, device_id: String);